@@ -4,12 +4,20 @@ pragma solidity ^0.8.26;
 import {ISignatureUtils} from "eigenlayer-contracts/src/contracts/interfaces/ISignatureUtils.sol";
 import {IAVSDirectory} from "eigenlayer-contracts/src/contracts/interfaces/IAVSDirectory.sol";
 import {ECDSA} from "solady/utils/ECDSA.sol";
+import {BN254} from "eigenlayer-middleware/src/libraries/BN254.sol";
 
 /// @title ServiceManager
 /// @notice Manages code execution tasks and operator responses for EigenLayer AVS
 /// @dev Handles operator registration, task creation, and response collection
 contract ServiceManager {
     using ECDSA for bytes32;
+    using BN254 for BN254.G1Point;
+
+    /// @notice Gas limit for the pairing precompile call, as in BLSSignatureChecker
+    uint256 internal constant PAIRING_EQUALITY_CHECK_GAS = 120000;
+
+    /// @notice Maximum number of BLS operators, one per signer bitmap bit
+    uint256 public constant MAX_BLS_OPERATORS = 256;
 
     // --- State Variables ---
     /// @notice Address of the EigenLayer AVS Directory contract
@@ -32,6 +40,18 @@ contract ServiceManager {
     /// @dev taskIndex => submissionCount
     mapping(uint256 => uint256) public taskSubmissionCount;
 
+    /// @notice Operators with a registered BLS key, in signer bitmap order
+    address[] public blsOperators;
+
+    /// @notice Position of each operator in `blsOperators`, plus one (0 = not registered)
+    mapping(address => uint256) public blsOperatorIndex;
+
+    /// @notice BLS public keys in G1, summed on-chain into the aggregate key
+    mapping(address => BN254.G1Point) public blsPubkeyG1;
+
+    /// @notice BLS public keys in G2
+    mapping(address => BN254.G2Point) internal _blsPubkeyG2;
+
     // --- Events ---
     /// @notice Emitted when a new compute task is created
     event ComputeRequestCreated(
@@ -50,6 +70,22 @@ contract ServiceManager {
         address operator
     );
 
+    /// @notice Emitted when an operator registers its BLS public key
+    event BlsPubkeyRegistered(
+        address indexed operator,
+        uint256 index,
+        BN254.G1Point pubkeyG1,
+        BN254.G2Point pubkeyG2
+    );
+
+    /// @notice Emitted when an aggregated BLS response is accepted for a task
+    event TaskRespondedWithBLS(
+        uint256 indexed taskIndex,
+        Task task,
+        string responseString,
+        uint256 signerBitmap
+    );
+
     // --- Structs ---
     /// @notice Represents a code execution task
     /// @param codeArweaveUri URI of the code snippet on Arweave
@@ -152,9 +188,140 @@ contract ServiceManager {
         );
         
         allTaskResponses[msg.sender][referenceTaskIndex] = signature;
+        taskSubmissionCount[referenceTaskIndex]++;
         emit TaskResponded(referenceTaskIndex, task, responseString, msg.sender);
     }
 
+    /// @notice Registers the caller's BLS public key for aggregated responses
+    /// @dev Both keys must share the same secret, checked with e(pkG1, -g2) * e(g1, pkG2) == 1.
+    /// The proof of possession shows the caller knows that secret, so a key made up
+    /// from other operators' keys can't be registered to forge aggregate signatures.
+    /// @param pubkeyG1 Public key in G1
+    /// @param pubkeyG2 Public key in G2
+    /// @param signature The key's signature over blsRegistrationMessageHash(msg.sender, pubkeyG1)
+    function registerBlsPublicKey(
+        BN254.G1Point memory pubkeyG1,
+        BN254.G2Point memory pubkeyG2,
+        BN254.G1Point memory signature
+    ) external onlyOperator {
+        require(blsOperatorIndex[msg.sender] == 0, "BLS key already registered");
+        require(blsOperators.length < MAX_BLS_OPERATORS, "Too many BLS operators");
+        require(
+            BN254.pairing(pubkeyG1, BN254.negGeneratorG2(), BN254.generatorG1(), pubkeyG2),
+            "BLS public keys do not match"
+        );
+        require(
+            BN254.pairing(
+                signature,
+                BN254.negGeneratorG2(),
+                BN254.hashToG1(blsRegistrationMessageHash(msg.sender, pubkeyG1)),
+                pubkeyG2
+            ),
+            "Invalid BLS proof of possession"
+        );
+
+        blsOperators.push(msg.sender);
+        blsOperatorIndex[msg.sender] = blsOperators.length;
+        blsPubkeyG1[msg.sender] = pubkeyG1;
+        _blsPubkeyG2[msg.sender] = pubkeyG2;
+        emit BlsPubkeyRegistered(msg.sender, blsOperators.length - 1, pubkeyG1, pubkeyG2);
+    }
+
+    /// @notice Message an operator signs with its BLS key to register it
+    /// @param operator Address of the operator registering the key
+    /// @param pubkeyG1 Public key in G1
+    /// @return keccak256(abi.encodePacked(operator, pubkeyG1.X, pubkeyG1.Y))
+    function blsRegistrationMessageHash(
+        address operator,
+        BN254.G1Point memory pubkeyG1
+    ) public pure returns (bytes32) {
+        return keccak256(abi.encodePacked(operator, pubkeyG1.X, pubkeyG1.Y));
+    }
+
+    /// @notice Gets an operator's BLS public key in G2
+    /// @param operator Address of the operator
+    /// @return The G2 public key, zero if none is registered
+    function getBlsPubkeyG2(address operator) external view returns (BN254.G2Point memory) {
+        return _blsPubkeyG2[operator];
+    }
+
+    /// @notice Accepts one aggregated BLS signature in place of per-operator responses
+    /// @dev Verifies sigma against the G1 keys of the signers, as BLSSignatureChecker does
+    /// @param task Task being responded to
+    /// @param referenceTaskIndex Index of the task
+    /// @param responseString Response data all signers agreed on
+    /// @param signerBitmap Bit i set when blsOperators[i] signed
+    /// @param apkG2 Sum of the signers' G2 public keys
+    /// @param sigma Sum of the signers' signatures
+    function respondToTaskWithBLS(
+        Task calldata task,
+        uint256 referenceTaskIndex,
+        string memory responseString,
+        uint256 signerBitmap,
+        BN254.G2Point memory apkG2,
+        BN254.G1Point memory sigma
+    ) external {
+        require(
+            keccak256(abi.encode(task)) == allTaskHashes[referenceTaskIndex],
+            "Task hash mismatch"
+        );
+
+        BN254.G1Point memory apk;
+        uint256 signerCount;
+        for (uint256 i = 0; i < blsOperators.length; i++) {
+            if ((signerBitmap >> i) & 1 == 0) {
+                continue;
+            }
+            address operator = blsOperators[i];
+            require(operatorRegistered[operator], "Signer is not a registered operator");
+            require(
+                allTaskResponses[operator][referenceTaskIndex].length == 0,
+                "Operator has already responded"
+            );
+            apk = apk.plus(blsPubkeyG1[operator]);
+            signerCount++;
+        }
+        require(signerCount > 0 && signerBitmap >> blsOperators.length == 0, "Invalid signer bitmap");
+        require(signerCount >= task.responseCount, "Not enough signers");
+
+        bytes32 messageHash = keccak256(
+            abi.encodePacked(responseString, referenceTaskIndex)
+        );
+        uint256 gamma = uint256(
+            keccak256(
+                abi.encodePacked(
+                    messageHash,
+                    apk.X,
+                    apk.Y,
+                    apkG2.X[0],
+                    apkG2.X[1],
+                    apkG2.Y[0],
+                    apkG2.Y[1],
+                    sigma.X,
+                    sigma.Y
+                )
+            )
+        ) % BN254.FR_MODULUS;
+        (bool pairingSuccessful, bool signatureIsValid) = BN254.safePairing(
+            sigma.plus(apk.scalar_mul(gamma)),
+            BN254.negGeneratorG2(),
+            BN254.hashToG1(messageHash).plus(BN254.generatorG1().scalar_mul(gamma)),
+            apkG2,
+            PAIRING_EQUALITY_CHECK_GAS
+        );
+        require(pairingSuccessful, "Pairing precompile call failed");
+        require(signatureIsValid, "Invalid aggregated signature");
+
+        bytes memory encodedSigma = abi.encode(sigma);
+        for (uint256 i = 0; i < blsOperators.length; i++) {
+            if ((signerBitmap >> i) & 1 == 1) {
+                allTaskResponses[blsOperators[i]][referenceTaskIndex] = encodedSigma;
+            }
+        }
+        taskSubmissionCount[referenceTaskIndex] += signerCount;
+        emit TaskRespondedWithBLS(referenceTaskIndex, task, responseString, signerBitmap);
+    }
+
     /// @notice Gets the total number of submissions for a task
     /// @param taskIndex Index of the task
     /// @return Number of submissions
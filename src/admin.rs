@@ -0,0 +1,327 @@
+//! Admin HTTP API for the operator.
+//!
+//! `/health` and `/ready` are open so they can back liveness and readiness
+//! probes. The task and pause endpoints require `Authorization: Bearer <token>`.
+
+use crate::config::Config;
+use crate::executor::ExecutionLogs;
+use crate::health::HealthReport;
+use crate::listener::ListenerStatus;
+use crate::retry::unix_now;
+use crate::task_store::{StateTransition, StoredReceipt, TaskRecord, TaskState, TaskStore};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use colored::*;
+use ethers::prelude::*;
+use eyre::Result;
+use tracing::info;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+pub struct AdminApi {
+    provider: Provider<Http>,
+    status: Arc<ListenerStatus>,
+    store: Option<Arc<TaskStore>>,
+    token: String,
+    max_lag_blocks: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthResponse {
+    pub paused: bool,
+    pub last_processed_block: u64,
+    pub lag_blocks: u64,
+    /// The latest periodic health check
+    pub report: Option<HealthReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadyResponse {
+    pub ready: bool,
+    /// Why the operator is not ready, empty when it is
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskDetail {
+    pub task: TaskRecord,
+    pub history: Vec<StateTransition>,
+    /// Console output of the latest run
+    pub logs: Option<ExecutionLogs>,
+    /// Execution receipt of the latest successful run
+    pub receipt: Option<StoredReceipt>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TaskQuery {
+    pub state: Option<String>,
+    pub limit: Option<usize>,
+}
+
+type ApiError = (StatusCode, String);
+
+fn internal_error(e: impl std::fmt::Display) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
+
+impl AdminApi {
+    /// Serves the status and task history of a listener, see `EventListener::status`
+    pub fn new(config: &Config, status: Arc<ListenerStatus>, store: Option<Arc<TaskStore>>) -> Result<Self> {
+        Ok(Self {
+            provider: Provider::<Http>::try_from(config.rpc_url.as_str())?,
+            status,
+            store,
+            token: config.admin.token.expose_secret().to_string(),
+            max_lag_blocks: config.admin.max_lag_blocks,
+        })
+    }
+
+    pub async fn serve(self: Arc<Self>, listen_addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(listen_addr).await?;
+        info!("Serving the admin API on {}", listener.local_addr()?.to_string().cyan());
+        axum::serve(listener, self.router()).await?;
+        Ok(())
+    }
+
+    fn router(self: Arc<Self>) -> Router {
+        let protected = Router::new()
+            .route("/tasks", get(list_tasks))
+            .route("/tasks/{task_index}", get(show_task))
+            .route("/tasks/{task_index}/retry", post(retry_task))
+            .route("/pause", post(pause))
+            .route("/resume", post(resume))
+            .route_layer(middleware::from_fn_with_state(self.clone(), require_token));
+        Router::new()
+            .route("/health", get(health))
+            .route("/ready", get(ready))
+            .merge(protected)
+            .with_state(self)
+    }
+
+    fn store(&self) -> Result<&TaskStore, ApiError> {
+        self.store
+            .as_deref()
+            .ok_or((StatusCode::SERVICE_UNAVAILABLE, "no task store".to_string()))
+    }
+
+    async fn readiness(&self) -> ReadyResponse {
+        let mut reasons = Vec::new();
+        if let Err(e) = self.provider.get_block_number().await {
+            reasons.push(format!("rpc: {}", e));
+        }
+        match self.status.last_report() {
+            Some(report) => reasons.extend(report.problems()),
+            None => reasons.push("health: not checked yet".to_string()),
+        }
+        if self.status.is_paused() {
+            reasons.push("listener: paused".to_string());
+        }
+        let lag = self.status.lag_blocks();
+        if lag > self.max_lag_blocks {
+            reasons.push(format!("listener: {} blocks behind (max {})", lag, self.max_lag_blocks));
+        }
+        ReadyResponse { ready: reasons.is_empty(), reasons }
+    }
+}
+
+/// Compares in constant time so the token can't be guessed byte by byte
+fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn require_token(State(api): State<Arc<AdminApi>>, request: Request, next: Next) -> Result<Response, ApiError> {
+    let given = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        // An empty token never matches, even if the configured one is empty
+        Some(token) if !token.is_empty() && token_matches(&api.token, token) => Ok(next.run(request).await),
+        _ => Err((StatusCode::UNAUTHORIZED, "missing or invalid bearer token".to_string())),
+    }
+}
+
+async fn health(State(api): State<Arc<AdminApi>>) -> Json<HealthResponse> {
+    Json(HealthResponse {
+        paused: api.status.is_paused(),
+        last_processed_block: api.status.last_processed_block(),
+        lag_blocks: api.status.lag_blocks(),
+        report: api.status.last_report(),
+    })
+}
+
+async fn ready(State(api): State<Arc<AdminApi>>) -> (StatusCode, Json<ReadyResponse>) {
+    let readiness = api.readiness().await;
+    let status = if readiness.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(readiness))
+}
+
+async fn list_tasks(
+    State(api): State<Arc<AdminApi>>,
+    Query(query): Query<TaskQuery>,
+) -> Result<Json<Vec<TaskRecord>>, ApiError> {
+    let state = query
+        .state
+        .map(|state| state.parse::<TaskState>())
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let records = api.store()?.list(state, query.limit.unwrap_or(50)).map_err(internal_error)?;
+    Ok(Json(records))
+}
+
+async fn show_task(
+    State(api): State<Arc<AdminApi>>,
+    Path(task_index): Path<u32>,
+) -> Result<Json<TaskDetail>, ApiError> {
+    let store = api.store()?;
+    let task = store
+        .get(task_index)
+        .map_err(internal_error)?
+        .ok_or((StatusCode::NOT_FOUND, format!("task {} is not in the task store", task_index)))?;
+    let history = store.history(task_index).map_err(internal_error)?;
+    let logs = store.logs(task_index).map_err(internal_error)?;
+    let receipt = store.receipt(task_index).map_err(internal_error)?;
+    Ok(Json(TaskDetail { task, history, logs, receipt }))
+}
+
+async fn retry_task(
+    State(api): State<Arc<AdminApi>>,
+    Path(task_index): Path<u32>,
+) -> Result<Json<TaskDetail>, ApiError> {
+    let store = api.store()?;
+    if !store.requeue(task_index, unix_now()).map_err(internal_error)? {
+        return match store.get(task_index).map_err(internal_error)? {
+            Some(task) => Err((
+                StatusCode::CONFLICT,
+                format!("task {} is {}, only failed or dead-lettered tasks can be retried", task_index, task.state),
+            )),
+            None => Err((StatusCode::NOT_FOUND, format!("task {} is not in the task store", task_index))),
+        };
+    }
+    info!("{}", format!("Task {} requeued through the admin API", task_index).yellow());
+    show_task(State(api), Path(task_index)).await
+}
+
+async fn pause(State(api): State<Arc<AdminApi>>) -> Json<HealthResponse> {
+    if !api.status.set_paused(true) {
+        info!("{}", "Listener paused through the admin API".yellow());
+    }
+    health(State(api)).await
+}
+
+async fn resume(State(api): State<Arc<AdminApi>>) -> Json<HealthResponse> {
+    if api.status.set_paused(false) {
+        info!("{}", "Listener resumed through the admin API".green());
+    }
+    health(State(api)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_mock::MockChain;
+    use crate::config::SecretString;
+    use crate::respond::Task;
+
+    const TOKEN: &str = "admin-token";
+
+    /// Serves the admin API on an ephemeral port, with tasks 1 (failed) and 2 (confirmed)
+    async fn serve(chain: &MockChain) -> (String, Arc<ListenerStatus>) {
+        let mut config = Config { rpc_url: chain.url().to_string(), ..Config::default() };
+        config.admin.token = SecretString::new(TOKEN.to_string());
+        let store = TaskStore::open_in_memory().unwrap();
+        for request_id in [1, 2] {
+            store
+                .record_discovered(&Task {
+                    code_arweave_uri: "code".to_string(),
+                    language: "js".to_string(),
+                    response_count: U256::one(),
+                    task_created_block: 1,
+                    request_id,
+                })
+                .unwrap();
+        }
+        store.set_failed(1, "gateway down", "gateway", 1, Some(0)).unwrap();
+        store.set_state(2, TaskState::Confirmed, None).unwrap();
+
+        let status = Arc::new(ListenerStatus::default());
+        let api = Arc::new(AdminApi::new(&config, status.clone(), Some(Arc::new(store))).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, api.router()).await });
+        (url, status)
+    }
+
+    async fn request(method: reqwest::Method, url: String, authorization: Option<&str>) -> (u16, String) {
+        let mut request = reqwest::Client::new().request(method, url);
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION.as_str(), authorization);
+        }
+        let reply = request.send().await.unwrap();
+        (reply.status().as_u16(), reply.text().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn task_routes_require_the_token() {
+        let chain = MockChain::start().await.unwrap();
+        let (url, _) = serve(&chain).await;
+        let bearer = format!("Bearer {}", TOKEN);
+        for authorization in [None, Some("Bearer wrong-token"), Some("Bearer "), Some(TOKEN)] {
+            let (status, _) = request(reqwest::Method::GET, format!("{}/tasks", url), authorization).await;
+            assert_eq!(status, 401, "{:?}", authorization);
+        }
+        let (status, body) = request(reqwest::Method::GET, format!("{}/tasks", url), Some(&bearer)).await;
+        assert_eq!(status, 200);
+        assert_eq!(serde_json::from_str::<Vec<serde_json::Value>>(&body).unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn health_and_ready_are_open() {
+        let chain = MockChain::start().await.unwrap();
+        let (url, status) = serve(&chain).await;
+        status.set_paused(true);
+
+        let (code, body) = request(reqwest::Method::GET, format!("{}/health", url), None).await;
+        assert_eq!(code, 200);
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&body).unwrap()["paused"], true);
+
+        let (code, body) = request(reqwest::Method::GET, format!("{}/ready", url), None).await;
+        assert_eq!(code, 503);
+        let readiness: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(readiness["ready"], false);
+        assert_eq!(readiness["reasons"], serde_json::json!(["health: not checked yet", "listener: paused"]));
+    }
+
+    #[tokio::test]
+    async fn only_failed_tasks_can_be_retried() {
+        let chain = MockChain::start().await.unwrap();
+        let (url, _) = serve(&chain).await;
+        let bearer = format!("Bearer {}", TOKEN);
+        let retry = |task_index: u32| format!("{}/tasks/{}/retry", url, task_index);
+
+        let (status, body) = request(reqwest::Method::POST, retry(2), Some(&bearer)).await;
+        assert_eq!(status, 409);
+        assert!(body.contains("task 2 is confirmed"), "{}", body);
+
+        let (status, _) = request(reqwest::Method::POST, retry(3), Some(&bearer)).await;
+        assert_eq!(status, 404);
+
+        let (status, body) = request(reqwest::Method::POST, retry(1), Some(&bearer)).await;
+        assert_eq!(status, 200);
+        let detail: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(detail["task"]["state"], "discovered");
+        assert_eq!(detail["task"]["attempts"], 0);
+    }
+}
@@ -0,0 +1,359 @@
+use crate::config::Config;
+use crate::listener::{ComputeRequestCreated, TaskResponded};
+use crate::error_response::ErrorResponse;
+use crate::respond::recover_response_signer;
+use colored::*;
+use ethers::abi::{Abi, RawLog};
+use ethers::prelude::*;
+use ethers::types::{Address, Bytes, U256, U64};
+use eyre::Result;
+use tracing::{error, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// The task as announced on-chain
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskInfo {
+    pub code_arweave_uri: String,
+    pub language: String,
+    pub response_count: U256,
+    pub task_created_block: U256,
+}
+
+/// Where the aggregator learned about a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseSource {
+    /// A `TaskResponded` event
+    OnChain,
+    /// Posted directly to the aggregator by the operator
+    Gossip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperatorResponse {
+    pub operator: Address,
+    pub response_string: String,
+    /// Whether the signature recovers to the responding operator
+    pub signature_valid: bool,
+    pub source: ResponseSource,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsensusResult {
+    pub response_string: String,
+    pub agreeing: Vec<Address>,
+    /// Responses that disagree with the result or carry an invalid signature
+    pub dissenters: Vec<OperatorResponse>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TaskTally {
+    pub task: Option<TaskInfo>,
+    pub responses: Vec<OperatorResponse>,
+    pub result: Option<ConsensusResult>,
+}
+
+impl TaskTally {
+    pub fn has_response_from(&self, operator: Address) -> bool {
+        self.responses.iter().any(|response| response.operator == operator)
+    }
+
+    /// Declares a result once `quorum` valid responses agree. Returns true when
+    /// this call reached the result.
+    fn evaluate(&mut self, quorum: usize) -> bool {
+        if let Some(result) = &mut self.result {
+            // Late responses still get checked against the result
+            if let Some(last) = self.responses.last() {
+                if !last.signature_valid || last.response_string != result.response_string {
+                    result.dissenters.push(last.clone());
+                } else {
+                    result.agreeing.push(last.operator);
+                }
+            }
+            return false;
+        }
+
+        let mut counts: BTreeMap<&str, Vec<Address>> = BTreeMap::new();
+        for response in self.responses.iter().filter(|r| r.signature_valid) {
+            counts
+                .entry(response.response_string.as_str())
+                .or_default()
+                .push(response.operator);
+        }
+        let Some((response_string, agreeing)) = counts
+            .into_iter()
+            .find(|(_, operators)| operators.len() >= quorum)
+        else {
+            return false;
+        };
+
+        let response_string = response_string.to_string();
+        let dissenters = self
+            .responses
+            .iter()
+            .filter(|r| !r.signature_valid || r.response_string != response_string)
+            .cloned()
+            .collect();
+        self.result = Some(ConsensusResult {
+            response_string,
+            agreeing,
+            dissenters,
+        });
+        true
+    }
+}
+
+/// The quorum a task asks for with its `responseCount`. Requesters choose the
+/// count, so one too large to reach saturates instead of panicking.
+pub fn response_quorum(response_count: U256) -> usize {
+    if response_count > U256::from(usize::MAX) {
+        usize::MAX
+    } else {
+        response_count.as_usize()
+    }
+}
+
+/// Tallies per task index, shared between the chain watcher and the HTTP receiver
+pub type SharedTallies = Arc<Mutex<BTreeMap<U256, TaskTally>>>;
+
+/// Adds a response to its task's tally and logs when the task reaches quorum.
+/// An operator counts once per task, whether its response arrived on-chain,
+/// off-chain or both.
+pub fn record_response(
+    tallies: &SharedTallies,
+    task_index: U256,
+    task: TaskInfo,
+    response: OperatorResponse,
+    quorum: Option<usize>,
+) {
+    let quorum = quorum.unwrap_or_else(|| response_quorum(task.response_count)).max(1);
+    let mut tallies = tallies.lock().unwrap();
+    let tally = tallies.entry(task_index).or_default();
+    // Tasks created before the start block are only known from their responses
+    tally.task.get_or_insert(task);
+    if tally.has_response_from(response.operator) {
+        info!("Task {}: already counted {:?}", task_index, response.operator);
+        return;
+    }
+    let operator = response.operator;
+    tally.responses.push(response);
+
+    let had_result = tally.result.is_some();
+    if tally.evaluate(quorum) {
+        let result = tally.result.as_ref().expect("evaluate set the result");
+        let failure = match result.response_string.parse::<ErrorResponse>() {
+            Ok(error) => format!(" (the task failed: {})", error.class),
+            Err(_) => String::new(),
+        };
+        info!(
+            "{}",
+            format!(
+                "✓ Task {} reached quorum ({}/{}): {}{}",
+                task_index,
+                result.agreeing.len(),
+                quorum,
+                result.response_string,
+                failure
+            )
+            .green()
+            .bold()
+        );
+        for dissenter in &result.dissenters {
+            warn!("{}", format!("  Dissenter {:?}: {}", dissenter.operator, dissenter.response_string).yellow());
+        }
+    } else if had_result {
+        let result = tally.result.as_ref().expect("result already set");
+        if result.dissenters.last().map(|d| d.operator) == Some(operator) {
+            warn!(
+                "{}",
+                format!("  Late dissenter on task {}: {:?}", task_index, operator).yellow()
+            );
+        }
+    }
+}
+
+/// Watches task creation and operator responses, and declares a result per task
+/// once enough operators agree
+pub struct Aggregator {
+    provider: Provider<Http>,
+    contract: Contract<Provider<Http>>,
+    quorum: Option<usize>,
+    poll_interval: tokio::time::Duration,
+    last_processed_block: Option<U64>,
+    tallies: SharedTallies,
+}
+
+impl Aggregator {
+    pub async fn new(config: &Config) -> Result<Self> {
+        let provider = Provider::<Http>::try_from(config.rpc_url.as_str())?;
+        let address: Address = config.contract_address.parse().map_err(|_| {
+            eyre::eyre!("contract_address ({:?}) is not a valid address", config.contract_address)
+        })?;
+        if config.aggregator.quorum == Some(0) {
+            return Err(eyre::eyre!("aggregator.quorum must be greater than 0"));
+        }
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string(&config.abi_path)?)?;
+        let contract = Contract::new(address, abi, std::sync::Arc::new(provider.clone()));
+
+        Ok(Self {
+            provider,
+            contract,
+            quorum: config.aggregator.quorum,
+            poll_interval: tokio::time::Duration::from_millis(config.limits.poll_interval_ms),
+            last_processed_block: config
+                .aggregator
+                .start_block
+                .map(|block| U64::from(block).saturating_sub(U64::one())),
+            tallies: SharedTallies::default(),
+        })
+    }
+
+    pub fn tallies(&self) -> SharedTallies {
+        self.tallies.clone()
+    }
+
+    pub async fn run(&mut self) -> Result<()> {
+        if self.last_processed_block.is_none() {
+            self.last_processed_block = Some(self.provider.get_block_number().await?);
+        }
+        info!(
+            "Aggregating responses after block: {}",
+            self.last_processed_block.unwrap_or_default().to_string().cyan()
+        );
+
+        loop {
+            let current_block = self.provider.get_block_number().await?;
+            let last_processed_block = self.last_processed_block.unwrap_or_default();
+            if current_block > last_processed_block {
+                self.process_range(last_processed_block + 1, current_block).await?;
+                self.last_processed_block = Some(current_block);
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    async fn process_range(&mut self, from_block: U64, to_block: U64) -> Result<()> {
+        let filter = Filter::new()
+            .address(self.contract.address())
+            .topic0(vec![ComputeRequestCreated::signature(), TaskResponded::signature()])
+            .from_block(from_block)
+            .to_block(to_block);
+
+        for log in self.provider.get_logs(&filter).await? {
+            let raw = RawLog::from(log.clone());
+            if let Ok(event) = <ComputeRequestCreated as EthEvent>::decode_log(&raw) {
+                info!("{}", format!("Task {} created", event.request_id).green());
+                self.tallies.lock().unwrap().entry(event.request_id).or_default().task = Some(TaskInfo {
+                    code_arweave_uri: event.code_json,
+                    language: event.code_lang,
+                    response_count: event.node_count,
+                    task_created_block: event.task_created_block,
+                });
+            } else if let Ok(event) = <TaskResponded as EthEvent>::decode_log(&raw) {
+                self.handle_response(event).await?;
+            } else {
+                error!("{}", "Failed to decode event".red());
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_response(&mut self, event: TaskResponded) -> Result<()> {
+        let signature: Bytes = self
+            .contract
+            .method::<_, Bytes>("allTaskResponses", (event.operator, event.task_index))?
+            .call()
+            .await?;
+        let signature_valid = match recover_response_signer(&signature, &event.response_string, event.task_index) {
+            Ok(signer) => signer == event.operator,
+            Err(_) => false,
+        };
+        if !signature_valid {
+            warn!(
+                "{}",
+                format!(
+                    "Task {}: signature from {:?} does not recover to the operator",
+                    event.task_index, event.operator
+                )
+                .yellow()
+            );
+        }
+        info!(
+            "Task {}: {:?} responded {}",
+            event.task_index,
+            event.operator,
+            event.response_string.cyan()
+        );
+
+        let (code_arweave_uri, response_count, language, task_created_block) = event.task;
+        record_response(
+            &self.tallies,
+            event.task_index,
+            TaskInfo {
+                code_arweave_uri,
+                language,
+                response_count,
+                task_created_block: U256::from(task_created_block),
+            },
+            OperatorResponse {
+                operator: event.operator,
+                response_string: event.response_string,
+                signature_valid,
+                source: ResponseSource::OnChain,
+            },
+            self.quorum,
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(response_count: U256) -> TaskInfo {
+        TaskInfo {
+            code_arweave_uri: "code".to_string(),
+            language: "js".to_string(),
+            response_count,
+            task_created_block: U256::one(),
+        }
+    }
+
+    fn response(operator: u64) -> OperatorResponse {
+        OperatorResponse {
+            operator: Address::from_low_u64_be(operator),
+            response_string: "42".to_string(),
+            signature_valid: true,
+            source: ResponseSource::OnChain,
+        }
+    }
+
+    #[test]
+    fn quorum_saturates() {
+        assert_eq!(response_quorum(U256::from(3)), 3);
+        assert_eq!(response_quorum(U256::MAX), usize::MAX);
+    }
+
+    #[test]
+    fn huge_response_count_does_not_poison_tallies() {
+        let tallies = SharedTallies::default();
+        record_response(&tallies, U256::one(), task(U256::MAX), response(1), None);
+        record_response(&tallies, U256::one(), task(U256::MAX), response(2), None);
+        let tallies = tallies.lock().unwrap();
+        assert_eq!(tallies[&U256::one()].responses.len(), 2);
+        assert!(tallies[&U256::one()].result.is_none());
+    }
+
+    #[test]
+    fn reaches_quorum_of_response_count() {
+        let tallies = SharedTallies::default();
+        record_response(&tallies, U256::one(), task(U256::from(2)), response(1), None);
+        record_response(&tallies, U256::one(), task(U256::from(2)), response(1), None);
+        assert!(tallies.lock().unwrap()[&U256::one()].result.is_none());
+        record_response(&tallies, U256::one(), task(U256::from(2)), response(2), None);
+        assert_eq!(tallies.lock().unwrap()[&U256::one()].result.as_ref().unwrap().agreeing.len(), 2);
+    }
+}
@@ -0,0 +1,501 @@
+//! HTTP endpoint where operators send signed responses off-chain.
+//!
+//! ECDSA responses posted to `/responses` are checked against the registered
+//! operators and counted in the same tallies as on-chain `TaskResponded`
+//! events. BLS responses posted to `/bls/responses` are checked against the
+//! operator's registered key, aggregated per task and submitted with a single
+//! `respondToTaskWithBLS` once enough operators agree.
+
+use crate::aggregator::{
+    record_response, response_quorum, OperatorResponse, ResponseSource, SharedTallies, TaskInfo, TaskTally,
+};
+use crate::bls::{self, BlsSignedResponse, BlsTaskAggregation};
+use crate::config::Config;
+use crate::contract::ContractClient;
+use crate::gossip::SignedResponse;
+use crate::respond::{recover_response_signer, Task};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use colored::*;
+use ethers::abi::Abi;
+use ethers::prelude::*;
+use ethers::types::{Address, H256, U256};
+use eyre::Result;
+use tracing::{error, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+
+#[derive(Default)]
+struct BlsTaskState {
+    aggregation: BlsTaskAggregation,
+    /// Set while or after the aggregated response is submitted
+    submitted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResponseReceipt {
+    /// Operators counted for the task so far
+    pub responses: usize,
+    /// The task's result, once a quorum agreed
+    pub result: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlsReceipt {
+    /// Operators that signed the same response so far
+    pub signers: usize,
+    /// Transaction of the aggregated submission, if this response completed the quorum
+    pub transaction_hash: Option<H256>,
+}
+
+pub struct AggregatorService {
+    contract: Contract<Provider<Http>>,
+    /// Sends aggregated BLS responses, only available with `aggregator.private_key`
+    submitter: Option<ContractClient>,
+    quorum: Option<usize>,
+    tallies: SharedTallies,
+    bls_tasks: Mutex<BTreeMap<u32, BlsTaskState>>,
+}
+
+type ApiError = (StatusCode, String);
+
+fn upstream_error(e: impl std::fmt::Display) -> ApiError {
+    (StatusCode::BAD_GATEWAY, e.to_string())
+}
+
+impl AggregatorService {
+    /// Records gossiped responses into `tallies`, normally `Aggregator::tallies()`
+    pub async fn new(config: &Config, tallies: SharedTallies) -> Result<Self> {
+        let provider = Provider::<Http>::try_from(config.rpc_url.as_str())?;
+        let address: Address = config.contract_address.parse().map_err(|_| {
+            eyre::eyre!("contract_address ({:?}) is not a valid address", config.contract_address)
+        })?;
+        let abi: Abi = serde_json::from_str(&std::fs::read_to_string(&config.abi_path)?)?;
+        let contract = Contract::new(address, abi, Arc::new(provider));
+
+        let submitter = if config.aggregator.private_key.is_empty() {
+            None
+        } else {
+            Some(ContractClient::from_config_with_key(config, config.aggregator.private_key.expose_secret()).await?)
+        };
+        Ok(Self {
+            contract,
+            submitter,
+            quorum: config.aggregator.quorum,
+            tallies,
+            bls_tasks: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    pub async fn serve(self: Arc<Self>, listen_addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(listen_addr).await?;
+        info!("Accepting signed responses on {}", listener.local_addr()?.to_string().cyan());
+        axum::serve(listener, self.router()).await?;
+        Ok(())
+    }
+
+    fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/responses", post(receive_response))
+            .route("/bls/responses", post(receive_bls_response))
+            .route("/tasks/{task_index}/responses", get(task_responses))
+            .with_state(self)
+    }
+
+    async fn check_operator(&self, operator: Address) -> Result<(), ApiError> {
+        let registered: bool = self
+            .contract
+            .method::<_, bool>("operatorRegistered", operator)
+            .map_err(upstream_error)?
+            .call()
+            .await
+            .map_err(upstream_error)?;
+        if !registered {
+            return Err((StatusCode::FORBIDDEN, format!("{:?} is not a registered operator", operator)));
+        }
+        Ok(())
+    }
+
+    /// Only accept responses to tasks the contract knows
+    async fn check_task(&self, task: &Task) -> Result<(), ApiError> {
+        let on_chain: H256 = self
+            .contract
+            .method::<_, H256>("allTaskHashes", U256::from(task.request_id))
+            .map_err(upstream_error)?
+            .call()
+            .await
+            .map_err(upstream_error)?;
+        if on_chain != task.hash() {
+            return Err((StatusCode::BAD_REQUEST, format!("task {} does not match the on-chain task", task.request_id)));
+        }
+        Ok(())
+    }
+
+    async fn handle(&self, response: SignedResponse) -> Result<ResponseReceipt, ApiError> {
+        let task_index = U256::from(response.task.request_id);
+        match recover_response_signer(&response.signature, &response.response_string, task_index) {
+            Ok(signer) if signer == response.operator => {}
+            Ok(signer) => {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!("signature recovers to {:?}, not {:?}", signer, response.operator),
+                ))
+            }
+            Err(e) => return Err((StatusCode::BAD_REQUEST, format!("invalid signature: {}", e))),
+        }
+        self.check_operator(response.operator).await?;
+        self.check_task(&response.task).await?;
+
+        info!(
+            "Task {}: {:?} sent {}",
+            task_index,
+            response.operator,
+            response.response_string.cyan()
+        );
+        let task = response.task;
+        record_response(
+            &self.tallies,
+            task_index,
+            TaskInfo {
+                code_arweave_uri: task.code_arweave_uri,
+                language: task.language,
+                response_count: task.response_count,
+                task_created_block: U256::from(task.task_created_block),
+            },
+            OperatorResponse {
+                operator: response.operator,
+                response_string: response.response_string,
+                signature_valid: true,
+                source: ResponseSource::Gossip,
+            },
+            self.quorum,
+        );
+
+        let tallies = self.tallies.lock().unwrap();
+        let tally = &tallies[&task_index];
+        Ok(ResponseReceipt {
+            responses: tally.responses.len(),
+            result: tally.result.as_ref().map(|result| result.response_string.clone()),
+        })
+    }
+
+    async fn handle_bls(&self, response: BlsSignedResponse) -> Result<BlsReceipt, ApiError> {
+        let client = self.submitter.as_ref().ok_or((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "BLS responses are disabled: aggregator.private_key is not set".to_string(),
+        ))?;
+        self.check_operator(response.operator).await?;
+        let operator_index = client
+            .bls_operator_index(response.operator)
+            .await
+            .map_err(upstream_error)?
+            .ok_or_else(|| (StatusCode::FORBIDDEN, format!("{:?} has no registered BLS key", response.operator)))?;
+        let public_key = client.bls_pubkey_g2(response.operator).await.map_err(upstream_error)?;
+        match bls::verify(response.digest(), response.signature, public_key) {
+            Ok(true) => {}
+            Ok(false) => return Err((StatusCode::BAD_REQUEST, "BLS signature does not verify".to_string())),
+            Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string())),
+        }
+        // The submission would revert on a task hash mismatch
+        self.check_task(&response.task).await?;
+
+        let task_index = response.task.request_id;
+        let quorum = self
+            .quorum
+            .unwrap_or_else(|| response_quorum(response.task.response_count))
+            .max(1);
+
+        let candidates = {
+            let mut tasks = self.bls_tasks.lock().unwrap();
+            let state = tasks.entry(task_index).or_default();
+            let signers = state
+                .aggregation
+                .add(&response, operator_index, public_key)
+                .map_err(|e| (StatusCode::CONFLICT, e.to_string()))?;
+            info!(
+                "Task {}: BLS response from {:?} ({}/{}) {}",
+                task_index,
+                response.operator,
+                signers,
+                quorum,
+                response.response_string.cyan()
+            );
+            if state.submitted || signers < quorum {
+                return Ok(BlsReceipt { signers, transaction_hash: None });
+            }
+            state.submitted = true;
+            state.aggregation.signers(&response.response_string)
+        };
+
+        // respondToTaskWithBLS reverts if any signer already responded on its own
+        let mut responded = Vec::new();
+        for operator in candidates {
+            match client.has_responded(U256::from(task_index), operator).await {
+                Ok(true) => responded.push(operator),
+                Ok(false) => {}
+                Err(e) => {
+                    self.release_bls_task(task_index);
+                    return Err(upstream_error(e));
+                }
+            }
+        }
+        let aggregated = {
+            let mut tasks = self.bls_tasks.lock().unwrap();
+            let state = tasks.get_mut(&task_index).expect("the task was just added");
+            if !responded.is_empty() {
+                info!("Task {}: leaving out {:?}, which already responded", task_index, responded);
+                state.aggregation.remove(&responded);
+            }
+            let signers = state.aggregation.signers(&response.response_string).len();
+            if signers < quorum {
+                state.submitted = false;
+                return Ok(BlsReceipt { signers, transaction_hash: None });
+            }
+            let aggregated = state.aggregation.aggregate(&response.response_string);
+            if aggregated.is_err() {
+                state.submitted = false;
+            }
+            aggregated
+                .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+                .expect("the response has signers")
+        };
+
+        let signers = aggregated.signers.len();
+        match client.respond_with_bls(aggregated).await {
+            Ok(receipt) => {
+                info!(
+                    "{}",
+                    format!(
+                        "✓ Task {} submitted with {} BLS signers in {:?}",
+                        task_index, signers, receipt.transaction_hash
+                    )
+                    .green()
+                    .bold()
+                );
+                Ok(BlsReceipt { signers, transaction_hash: Some(receipt.transaction_hash) })
+            }
+            Err(e) => {
+                error!("{}", format!("Task {}: aggregated submission failed: {:#}", task_index, e).red());
+                // Let the next response retry the submission
+                self.release_bls_task(task_index);
+                Err(upstream_error(e))
+            }
+        }
+    }
+
+    /// Lets the next response to the task try the submission again
+    fn release_bls_task(&self, task_index: u32) {
+        if let Some(state) = self.bls_tasks.lock().unwrap().get_mut(&task_index) {
+            state.submitted = false;
+        }
+    }
+}
+
+fn log_rejection((status, message): ApiError) -> ApiError {
+    warn!("{}", format!("Rejected response: {}", message).yellow());
+    (status, message)
+}
+
+async fn receive_response(
+    State(service): State<Arc<AggregatorService>>,
+    Json(response): Json<SignedResponse>,
+) -> Result<Json<ResponseReceipt>, ApiError> {
+    service.handle(response).await.map(Json).map_err(log_rejection)
+}
+
+async fn receive_bls_response(
+    State(service): State<Arc<AggregatorService>>,
+    Json(response): Json<BlsSignedResponse>,
+) -> Result<Json<BlsReceipt>, ApiError> {
+    service.handle_bls(response).await.map(Json).map_err(log_rejection)
+}
+
+async fn task_responses(
+    State(service): State<Arc<AggregatorService>>,
+    Path(task_index): Path<u64>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let tallies = service.tallies.lock().unwrap();
+    let tally: &TaskTally = tallies
+        .get(&U256::from(task_index))
+        .ok_or((StatusCode::NOT_FOUND, format!("no responses for task {}", task_index)))?;
+    serde_json::to_value(tally)
+        .map(Json)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::bls::BlsKeyPair;
+    use crate::chain_mock::MockChain;
+    use crate::config::SecretString;
+    use crate::respond::create_signature_for_task;
+
+    pub(crate) fn task() -> Task {
+        Task {
+            code_arweave_uri: "ar://code".to_string(),
+            language: "js".to_string(),
+            response_count: U256::from(3),
+            task_created_block: 1,
+            request_id: 0,
+        }
+    }
+
+    /// A chain that knows `task`, with the operators registered and holding a BLS key
+    pub(crate) async fn chain_with(task: &Task, operators: &[(Address, BlsKeyPair)]) -> MockChain {
+        let chain = MockChain::start().await.unwrap();
+        {
+            let mut state = chain.state();
+            state.task_hashes.insert(task.request_id, task.hash());
+            for (operator, key) in operators {
+                state.operators.insert(*operator);
+                state.bls_operators.push((*operator, key.public_g2()));
+            }
+        }
+        chain
+    }
+
+    pub(crate) fn bls_operators(count: u64) -> Vec<(Address, BlsKeyPair)> {
+        (1..=count).map(|i| (Address::from_low_u64_be(i), BlsKeyPair::random())).collect()
+    }
+
+    /// Serves an aggregator backed by `chain` on an ephemeral port
+    pub(crate) async fn serve(chain: &MockChain, quorum: usize) -> String {
+        let mut config = Config {
+            rpc_url: chain.url().to_string(),
+            contract_address: format!("{:?}", Address::from_low_u64_be(0xc0)),
+            ..Config::default()
+        };
+        config.aggregator.quorum = Some(quorum);
+        config.aggregator.private_key =
+            SecretString::new("0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d".to_string());
+        let service = Arc::new(AggregatorService::new(&config, SharedTallies::default()).await.unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, service.router()).await });
+        url
+    }
+
+    async fn post<T: Serialize>(url: &str, path: &str, body: &T) -> (StatusCode, serde_json::Value) {
+        let reply = reqwest::Client::new().post(format!("{}{}", url, path)).json(body).send().await.unwrap();
+        let status = StatusCode::from_u16(reply.status().as_u16()).unwrap();
+        let text = reply.text().await.unwrap();
+        (status, serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text)))
+    }
+
+    async fn signed(wallet: &LocalWallet, task: &Task, response_string: &str) -> SignedResponse {
+        SignedResponse {
+            task: task.clone(),
+            response_string: response_string.to_string(),
+            operator: wallet.address(),
+            signature: create_signature_for_task(wallet, response_string, task, None).await.unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn responses_are_checked_and_counted_once() {
+        let task = task();
+        let chain = chain_with(&task, &[]).await;
+        let operator = LocalWallet::new(&mut rand::thread_rng());
+        let outsider = LocalWallet::new(&mut rand::thread_rng());
+        chain.state().operators.insert(operator.address());
+        let url = serve(&chain, 2).await;
+
+        // Signed by someone other than the operator it claims to come from
+        let mut forged = signed(&outsider, &task, "42").await;
+        forged.operator = operator.address();
+        assert_eq!(post(&url, "/responses", &forged).await.0, StatusCode::BAD_REQUEST);
+
+        let (status, _) = post(&url, "/responses", &signed(&outsider, &task, "42").await).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let mut unknown_task = task.clone();
+        unknown_task.code_arweave_uri = "ar://other".to_string();
+        let (status, _) = post(&url, "/responses", &signed(&operator, &unknown_task, "42").await).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let response = signed(&operator, &task, "42").await;
+        for _ in 0..2 {
+            let (status, receipt) = post(&url, "/responses", &response).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(receipt["responses"], 1);
+            assert!(receipt["result"].is_null());
+        }
+    }
+
+    #[tokio::test]
+    async fn bls_responses_are_checked_and_submitted_once() {
+        let task = task();
+        let operators = bls_operators(3);
+        let chain = chain_with(&task, &operators).await;
+        // Registered, but without a BLS key
+        let keyless = Address::from_low_u64_be(0xee);
+        chain.state().operators.insert(keyless);
+        let url = serve(&chain, 2).await;
+        let response = |i: usize, response_string: &str| {
+            let (operator, key) = &operators[i];
+            BlsSignedResponse::new(key, *operator, task.clone(), response_string.to_string())
+        };
+
+        let mut forged = response(0, "42");
+        forged.signature = operators[1].1.sign(forged.digest());
+        assert_eq!(post(&url, "/bls/responses", &forged).await.0, StatusCode::BAD_REQUEST);
+
+        let unregistered = BlsSignedResponse::new(&BlsKeyPair::random(), Address::from_low_u64_be(0xff), task.clone(), "42".to_string());
+        assert_eq!(post(&url, "/bls/responses", &unregistered).await.0, StatusCode::FORBIDDEN);
+        let no_key = BlsSignedResponse::new(&operators[0].1, keyless, task.clone(), "42".to_string());
+        assert_eq!(post(&url, "/bls/responses", &no_key).await.0, StatusCode::FORBIDDEN);
+
+        // Sending the same response twice still counts one signer
+        for _ in 0..2 {
+            let (status, receipt) = post(&url, "/bls/responses", &response(0, "42")).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(receipt["signers"], 1);
+        }
+        assert_eq!(post(&url, "/bls/responses", &response(0, "43")).await.0, StatusCode::CONFLICT);
+
+        let (_, receipt) = post(&url, "/bls/responses", &response(1, "42")).await;
+        assert!(!receipt["transaction_hash"].is_null());
+        // Once submitted, later signers are counted but nothing is sent again
+        let (status, receipt) = post(&url, "/bls/responses", &response(2, "42")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(receipt["signers"], 3);
+        assert!(receipt["transaction_hash"].is_null());
+        assert_eq!(chain.state().transactions.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn signers_that_already_responded_are_left_out() {
+        let task = task();
+        let operators = bls_operators(3);
+        let chain = chain_with(&task, &operators).await;
+        // The first operator already sent its own respondToTask
+        chain.state().responses.insert((operators[0].0, task.request_id), Bytes::from(vec![1]));
+        let url = serve(&chain, 2).await;
+
+        let mut receipts = Vec::new();
+        for (operator, key) in &operators {
+            let response = BlsSignedResponse::new(key, *operator, task.clone(), "42".to_string());
+            let (status, receipt) = post(&url, "/bls/responses", &response).await;
+            assert_eq!(status, StatusCode::OK);
+            receipts.push(receipt);
+        }
+
+        // The second response reaches the quorum, but only one signer is left after the check
+        assert_eq!(receipts[1]["signers"], 1);
+        assert!(receipts[1]["transaction_hash"].is_null());
+        assert_eq!(receipts[2]["signers"], 2);
+        assert!(!receipts[2]["transaction_hash"].is_null());
+
+        // The mock reverts if any signer in the bitmap had already responded
+        let state = chain.state();
+        assert_eq!(state.transactions.len(), 1);
+        assert!(state.transactions[0].1);
+        assert!(state.responses.contains_key(&(operators[1].0, task.request_id)));
+        assert!(state.responses.contains_key(&(operators[2].0, task.request_id)));
+    }
+}
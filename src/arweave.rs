@@ -1,4 +1,8 @@
+use crate::arweave_tx::{ArweaveWallet, DataItem, Tag, Transaction};
+use crate::executor::CodeModule;
+use crate::metrics::METRICS;
 use reqwest::{self, Response};
+use serde::Deserialize;
 use serde_json::Value;
 use thiserror::Error;
 
@@ -8,32 +12,183 @@ pub enum ArweaveError {
     RequestError(#[from] reqwest::Error),
     #[error("JSON parsing failed: {0}")]
     JsonError(#[from] serde_json::Error),
+    #[error("Invalid encoding: {0}")]
+    EncodingError(String),
+    #[error("Wallet error: {0}")]
+    WalletError(String),
+    #[error("No Arweave wallet configured for uploads")]
+    NoWallet,
+    #[error("Gateway rejected upload ({status}): {body}")]
+    UploadRejected { status: u16, body: String },
+}
+
+/// Metadata published as tags alongside a code module
+#[derive(Debug, Clone)]
+pub struct FunctionMetadata {
+    pub language: String,
+    pub name: Option<String>,
+    pub version: Option<String>,
+}
+
+impl FunctionMetadata {
+    fn tags(&self) -> Vec<Tag> {
+        let mut tags = vec![
+            Tag::new("Content-Type", "application/json"),
+            Tag::new("App-Name", "FunctionLayer"),
+            Tag::new("App-Version", env!("CARGO_PKG_VERSION")),
+            Tag::new("Function-Language", &self.language),
+        ];
+        if let Some(name) = &self.name {
+            tags.push(Tag::new("Function-Name", name));
+        }
+        if let Some(version) = &self.version {
+            tags.push(Tag::new("Function-Version", version));
+        }
+        tags
+    }
+}
+
+#[derive(Deserialize)]
+struct BundlerReceipt {
+    id: String,
 }
 
 pub struct ArweaveClient {
     gateway_url: String,
+    bundler_url: Option<String>,
+    wallet: Option<ArweaveWallet>,
+    http: reqwest::Client,
 }
 
 impl ArweaveClient {
     pub fn new(gateway_url: Option<String>) -> Self {
         Self {
             gateway_url: gateway_url.unwrap_or_else(|| "https://arweave.net".to_string()),
+            bundler_url: None,
+            wallet: None,
+            http: reqwest::Client::new(),
         }
     }
 
+    /// Sets the wallet used to sign uploads
+    pub fn with_wallet(mut self, wallet: ArweaveWallet) -> Self {
+        self.wallet = Some(wallet);
+        self
+    }
+
+    /// Uploads go to this bundler as ANS-104 data items instead of to the gateway
+    pub fn with_bundler(mut self, bundler_url: String) -> Self {
+        self.bundler_url = Some(bundler_url);
+        self
+    }
+
     pub async fn get_transaction_data_json(&self, tx_id: &str) -> Result<Value, ArweaveError> {
+        Ok(serde_json::from_slice(&self.get_transaction_bytes(tx_id).await?)?)
+    }
+
+    /// The data of a transaction exactly as the gateway serves it
+    pub async fn get_transaction_bytes(&self, tx_id: &str) -> Result<Vec<u8>, ArweaveError> {
         let url = format!("{}/{}", self.gateway_url, tx_id);
-        
-        let response = reqwest::get(&url).await?;
-        let json: Value = response.json().await?;
-        
-        Ok(json)
+
+        let timer = METRICS.arweave_fetch_duration.start_timer();
+        let bytes = async { reqwest::get(&url).await?.error_for_status()?.bytes().await }.await;
+        timer.observe_duration();
+        if bytes.is_err() {
+            METRICS.arweave_fetch_errors.inc();
+        }
+
+        Ok(bytes?.to_vec())
     }
 
     pub async fn get_transaction_data(&self, tx_id: &str) -> Result<Response, ArweaveError> {
         let url = format!("{}/{}", self.gateway_url, tx_id);
-        
+
         let response = reqwest::get(&url).await?;
         Ok(response)
     }
-}
\ No newline at end of file
+
+    /// Serializes the code module, tags it with its metadata and uploads it.
+    /// Returns the transaction (or data item) ID to reference in `createNewTask`.
+    pub async fn upload_code_module(
+        &self,
+        code_module: &CodeModule,
+        metadata: &FunctionMetadata,
+    ) -> Result<String, ArweaveError> {
+        let data = serde_json::to_vec(code_module)?;
+        self.upload(&data, &metadata.tags()).await
+    }
+
+    pub async fn upload(&self, data: &[u8], tags: &[Tag]) -> Result<String, ArweaveError> {
+        let wallet = self.wallet.as_ref().ok_or(ArweaveError::NoWallet)?;
+        match &self.bundler_url {
+            Some(bundler_url) => self.upload_to_bundler(bundler_url, wallet, data, tags).await,
+            None => self.upload_to_gateway(wallet, data, tags).await,
+        }
+    }
+
+    async fn upload_to_gateway(
+        &self,
+        wallet: &ArweaveWallet,
+        data: &[u8],
+        tags: &[Tag],
+    ) -> Result<String, ArweaveError> {
+        let last_tx = self.get_text(&format!("{}/tx_anchor", self.gateway_url)).await?;
+        let reward = self.get_text(&format!("{}/price/{}", self.gateway_url, data.len())).await?;
+
+        let tx = Transaction::new_signed(wallet, data, tags, last_tx.trim(), reward.trim())?;
+        let response = self
+            .http
+            .post(format!("{}/tx", self.gateway_url))
+            .json(&tx)
+            .send()
+            .await?;
+
+        // 208 means the gateway already has this transaction
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ArweaveError::UploadRejected {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+        Ok(tx.id)
+    }
+
+    async fn upload_to_bundler(
+        &self,
+        bundler_url: &str,
+        wallet: &ArweaveWallet,
+        data: &[u8],
+        tags: &[Tag],
+    ) -> Result<String, ArweaveError> {
+        let item = DataItem::new_signed(wallet, data, tags)?;
+        let response = self
+            .http
+            .post(format!("{}/tx", bundler_url))
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .body(item.as_bytes().to_vec())
+            .send()
+            .await?;
+
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ArweaveError::UploadRejected {
+                status: status.as_u16(),
+                body: response.text().await.unwrap_or_default(),
+            });
+        }
+
+        let receipt: BundlerReceipt = response.json().await?;
+        if receipt.id != item.id {
+            return Err(ArweaveError::UploadRejected {
+                status: status.as_u16(),
+                body: format!("bundler returned id {} for data item {}", receipt.id, item.id),
+            });
+        }
+        Ok(item.id)
+    }
+
+    async fn get_text(&self, url: &str) -> Result<String, ArweaveError> {
+        Ok(self.http.get(url).send().await?.error_for_status()?.text().await?)
+    }
+}
@@ -0,0 +1,173 @@
+//! In-process stand-in for an Arweave gateway and bundler, for tests and local
+//! development. Serves uploaded data back at `GET /{id}` so the executor can
+//! fetch code published through it.
+
+use crate::arweave_tx::{b64url_decode, b64url_encode, DataItem, Tag, Transaction};
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+#[derive(Debug, Clone)]
+pub struct StoredUpload {
+    pub data: Vec<u8>,
+    pub tags: Vec<Tag>,
+}
+
+type Store = Arc<Mutex<HashMap<String, StoredUpload>>>;
+
+pub struct MockGateway {
+    url: String,
+    store: Store,
+    handle: JoinHandle<()>,
+}
+
+impl MockGateway {
+    /// Binds to an ephemeral localhost port and starts serving
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let store = Store::default();
+
+        let app = Router::new()
+            .route("/tx_anchor", get(tx_anchor))
+            .route("/price/{size}", get(price))
+            .route("/tx", post(post_transaction))
+            .route("/bundler/tx", post(post_data_item))
+            .route("/{id}", get(get_data))
+            .with_state(store.clone());
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        Ok(Self { url, store, handle })
+    }
+
+    /// Gateway URL to pass to `ArweaveClient::new`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Bundler URL to pass to `ArweaveClient::with_bundler`
+    pub fn bundler_url(&self) -> String {
+        format!("{}/bundler", self.url)
+    }
+
+    /// Seeds the gateway with data under a fixed ID, e.g. a code module fixture
+    pub fn insert(&self, id: &str, data: Vec<u8>, tags: Vec<Tag>) {
+        self.store
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), StoredUpload { data, tags });
+    }
+
+    pub fn get(&self, id: &str) -> Option<StoredUpload> {
+        self.store.lock().unwrap().get(id).cloned()
+    }
+}
+
+impl Drop for MockGateway {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn tx_anchor() -> String {
+    b64url_encode(&rand::random::<[u8; 32]>())
+}
+
+async fn price(Path(_size): Path<u64>) -> &'static str {
+    "0"
+}
+
+async fn post_transaction(State(store): State<Store>, Json(tx): Json<Transaction>) -> Response {
+    if let Err(e) = tx.verify() {
+        return (StatusCode::BAD_REQUEST, e.to_string()).into_response();
+    }
+    let upload = match (b64url_decode(&tx.data), tx.decoded_tags()) {
+        (Ok(data), Ok(tags)) => StoredUpload { data, tags },
+        _ => return (StatusCode::BAD_REQUEST, "Malformed transaction").into_response(),
+    };
+    store.lock().unwrap().insert(tx.id, upload);
+    StatusCode::OK.into_response()
+}
+
+async fn post_data_item(State(store): State<Store>, body: Bytes) -> Response {
+    match DataItem::parse(&body).and_then(|item| item.verify().map(|_| item)) {
+        Ok(item) => {
+            let id = item.id.clone();
+            store.lock().unwrap().insert(
+                item.id,
+                StoredUpload {
+                    data: item.data,
+                    tags: item.tags,
+                },
+            );
+            Json(serde_json::json!({ "id": id })).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    }
+}
+
+async fn get_data(State(store): State<Store>, Path(id): Path<String>) -> Response {
+    let Some(upload) = store.lock().unwrap().get(&id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let content_type = upload
+        .tags
+        .iter()
+        .find(|tag| tag.name.eq_ignore_ascii_case("Content-Type"))
+        .map(|tag| tag.value.clone())
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    ([(header::CONTENT_TYPE, content_type)], upload.data).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arweave::ArweaveClient;
+    use crate::arweave_tx::ArweaveWallet;
+
+    fn wallet() -> ArweaveWallet {
+        ArweaveWallet::from_jwk(include_str!("../testdata/arweave/wallet.json")).unwrap()
+    }
+
+    #[tokio::test]
+    async fn upload_round_trip() {
+        let gateway = MockGateway::start().await.unwrap();
+        let tags = [Tag::new("Content-Type", "application/json"), Tag::new("Type", "Code-Module")];
+        let data = br#"{"code":"return 1"}"#;
+
+        let direct = ArweaveClient::new(Some(gateway.url().to_string())).with_wallet(wallet());
+        let bundled = ArweaveClient::new(Some(gateway.url().to_string()))
+            .with_wallet(wallet())
+            .with_bundler(gateway.bundler_url());
+        for client in [direct, bundled] {
+            let id = client.upload(data, &tags).await.unwrap();
+            let stored = gateway.get(&id).unwrap();
+            assert_eq!(stored.data, data);
+            assert_eq!(stored.tags, tags);
+            assert_eq!(client.get_transaction_data_json(&id).await.unwrap()["code"], "return 1");
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_tampered_data_item() {
+        let gateway = MockGateway::start().await.unwrap();
+        let mut item = DataItem::new_signed(&wallet(), b"data", &[]).unwrap().as_bytes().to_vec();
+        *item.last_mut().unwrap() ^= 1;
+        let response = reqwest::Client::new()
+            .post(format!("{}/tx", gateway.bundler_url()))
+            .body(item)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,678 @@
+use crate::arweave::ArweaveError;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rsa::pss::{BlindedSigningKey, Signature, VerifyingKey};
+use rsa::signature::{RandomizedSigner, SignatureEncoding, Verifier};
+use rsa::{BigUint, RsaPrivateKey, RsaPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha384};
+use std::path::Path;
+
+/// Arweave chunks transaction data into pieces of at most 256 KiB for the data root
+const MAX_CHUNK_SIZE: usize = 256 * 1024;
+const MIN_CHUNK_SIZE: usize = 32 * 1024;
+/// Byte offsets in the merkle tree are encoded as 32 byte big-endian notes
+const NOTE_SIZE: usize = 32;
+/// ANS-104 signature type for Arweave (RSA-PSS 4096) keys
+const ARWEAVE_SIGNATURE_TYPE: u16 = 1;
+const ARWEAVE_OWNER_LENGTH: usize = 512;
+
+pub fn b64url_encode(data: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+pub fn b64url_decode(data: &str) -> Result<Vec<u8>, ArweaveError> {
+    URL_SAFE_NO_PAD
+        .decode(data)
+        .map_err(|e| ArweaveError::EncodingError(e.to_string()))
+}
+
+/// A name/value pair attached to an Arweave transaction or bundled data item
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tag {
+    pub name: String,
+    pub value: String,
+}
+
+impl Tag {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Jwk {
+    kty: String,
+    n: String,
+    e: String,
+    d: String,
+    p: String,
+    q: String,
+}
+
+/// RSA key loaded from an Arweave JWK keyfile
+pub struct ArweaveWallet {
+    key: RsaPrivateKey,
+    owner: Vec<u8>,
+}
+
+impl ArweaveWallet {
+    pub fn from_jwk(jwk: &str) -> Result<Self, ArweaveError> {
+        let jwk: Jwk = serde_json::from_str(jwk)?;
+        if jwk.kty != "RSA" {
+            return Err(ArweaveError::WalletError(format!(
+                "Unsupported JWK key type: {}",
+                jwk.kty
+            )));
+        }
+
+        let decode = |field: &str| -> Result<BigUint, ArweaveError> {
+            Ok(BigUint::from_bytes_be(&b64url_decode(field)?))
+        };
+        let key = RsaPrivateKey::from_components(
+            decode(&jwk.n)?,
+            decode(&jwk.e)?,
+            decode(&jwk.d)?,
+            vec![decode(&jwk.p)?, decode(&jwk.q)?],
+        )
+        .map_err(|e| ArweaveError::WalletError(e.to_string()))?;
+
+        Ok(Self {
+            owner: b64url_decode(&jwk.n)?,
+            key,
+        })
+    }
+
+    pub fn from_jwk_file(path: impl AsRef<Path>) -> Result<Self, ArweaveError> {
+        let jwk = std::fs::read_to_string(path.as_ref()).map_err(|e| {
+            ArweaveError::WalletError(format!(
+                "Failed to read keyfile {}: {}",
+                path.as_ref().display(),
+                e
+            ))
+        })?;
+        Self::from_jwk(&jwk)
+    }
+
+    /// The public modulus, which Arweave uses as the transaction owner
+    pub fn owner(&self) -> &[u8] {
+        &self.owner
+    }
+
+    /// The wallet address: base64url(sha256(owner))
+    pub fn address(&self) -> String {
+        b64url_encode(&Sha256::digest(&self.owner))
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        let signing_key = BlindedSigningKey::<Sha256>::new_with_salt_len(self.key.clone(), 32);
+        signing_key
+            .sign_with_rng(&mut rand::thread_rng(), message)
+            .to_vec()
+    }
+}
+
+fn verify_signature(owner: &[u8], message: &[u8], signature: &[u8]) -> Result<(), ArweaveError> {
+    let public_key = RsaPublicKey::new(BigUint::from_bytes_be(owner), BigUint::from(65537u32))
+        .map_err(|e| ArweaveError::WalletError(e.to_string()))?;
+    let signature = Signature::try_from(signature)
+        .map_err(|e| ArweaveError::WalletError(e.to_string()))?;
+    VerifyingKey::<Sha256>::new_with_salt_len(public_key, 32)
+        .verify(message, &signature)
+        .map_err(|_| ArweaveError::WalletError("Invalid signature".to_string()))
+}
+
+/// Input to Arweave's deep hash: either a blob of bytes or a nested list
+pub enum DeepHashItem {
+    Blob(Vec<u8>),
+    List(Vec<DeepHashItem>),
+}
+
+impl From<&[u8]> for DeepHashItem {
+    fn from(data: &[u8]) -> Self {
+        DeepHashItem::Blob(data.to_vec())
+    }
+}
+
+impl From<&str> for DeepHashItem {
+    fn from(data: &str) -> Self {
+        DeepHashItem::Blob(data.as_bytes().to_vec())
+    }
+}
+
+pub fn deep_hash(item: &DeepHashItem) -> Vec<u8> {
+    match item {
+        DeepHashItem::Blob(data) => {
+            let tag = Sha384::digest(format!("blob{}", data.len()));
+            let mut tagged = tag.to_vec();
+            tagged.extend_from_slice(&Sha384::digest(data));
+            Sha384::digest(&tagged).to_vec()
+        }
+        DeepHashItem::List(items) => {
+            let mut acc = Sha384::digest(format!("list{}", items.len())).to_vec();
+            for item in items {
+                let mut pair = acc;
+                pair.extend_from_slice(&deep_hash(item));
+                acc = Sha384::digest(&pair).to_vec();
+            }
+            acc
+        }
+    }
+}
+
+struct MerkleNode {
+    id: Vec<u8>,
+    max_byte_range: usize,
+}
+
+fn sha256_concat(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(Sha256::digest(part));
+    }
+    hasher.finalize().to_vec()
+}
+
+fn int_to_note(value: usize) -> [u8; NOTE_SIZE] {
+    let mut note = [0u8; NOTE_SIZE];
+    note[NOTE_SIZE - 8..].copy_from_slice(&(value as u64).to_be_bytes());
+    note
+}
+
+/// Computes the merkle root over the data chunks, as required for v2 transactions
+pub fn data_root(data: &[u8]) -> Vec<u8> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+
+    let mut leaves = Vec::new();
+    let mut rest = data;
+    let mut cursor = 0;
+    while rest.len() >= MAX_CHUNK_SIZE {
+        let mut chunk_size = MAX_CHUNK_SIZE;
+        let next_chunk_size = rest.len() - MAX_CHUNK_SIZE;
+        if next_chunk_size > 0 && next_chunk_size < MIN_CHUNK_SIZE {
+            chunk_size = rest.len().div_ceil(2);
+        }
+        let (chunk, remainder) = rest.split_at(chunk_size);
+        cursor += chunk.len();
+        leaves.push(leaf(chunk, cursor));
+        rest = remainder;
+    }
+    leaves.push(leaf(rest, cursor + rest.len()));
+
+    let mut layer = leaves;
+    while layer.len() > 1 {
+        let mut next = Vec::with_capacity(layer.len().div_ceil(2));
+        let mut nodes = layer.into_iter();
+        while let Some(left) = nodes.next() {
+            match nodes.next() {
+                Some(right) => next.push(MerkleNode {
+                    id: sha256_concat(&[&left.id, &right.id, &int_to_note(left.max_byte_range)]),
+                    max_byte_range: right.max_byte_range,
+                }),
+                None => next.push(left),
+            }
+        }
+        layer = next;
+    }
+    layer.remove(0).id
+}
+
+fn leaf(chunk: &[u8], max_byte_range: usize) -> MerkleNode {
+    let data_hash = Sha256::digest(chunk);
+    MerkleNode {
+        id: sha256_concat(&[&data_hash, &int_to_note(max_byte_range)]),
+        max_byte_range,
+    }
+}
+
+/// A format 2 Arweave transaction in the JSON shape accepted by `POST /tx`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub format: u8,
+    pub id: String,
+    pub last_tx: String,
+    pub owner: String,
+    pub tags: Vec<Tag>,
+    pub target: String,
+    pub quantity: String,
+    pub data: String,
+    pub data_size: String,
+    pub data_root: String,
+    pub reward: String,
+    pub signature: String,
+}
+
+impl Transaction {
+    /// Builds and signs a data transaction. Tags are given in plain text and
+    /// base64url encoded here, as the gateway expects.
+    pub fn new_signed(
+        wallet: &ArweaveWallet,
+        data: &[u8],
+        tags: &[Tag],
+        last_tx: &str,
+        reward: &str,
+    ) -> Result<Self, ArweaveError> {
+        let mut tx = Self {
+            format: 2,
+            id: String::new(),
+            last_tx: last_tx.to_string(),
+            owner: b64url_encode(wallet.owner()),
+            tags: tags
+                .iter()
+                .map(|tag| Tag {
+                    name: b64url_encode(tag.name.as_bytes()),
+                    value: b64url_encode(tag.value.as_bytes()),
+                })
+                .collect(),
+            target: String::new(),
+            quantity: "0".to_string(),
+            data: b64url_encode(data),
+            data_size: data.len().to_string(),
+            data_root: b64url_encode(&data_root(data)),
+            reward: reward.to_string(),
+            signature: String::new(),
+        };
+
+        let signature = wallet.sign(&tx.signature_data()?);
+        tx.id = b64url_encode(&Sha256::digest(&signature));
+        tx.signature = b64url_encode(&signature);
+        Ok(tx)
+    }
+
+    fn signature_data(&self) -> Result<Vec<u8>, ArweaveError> {
+        let mut tags = Vec::with_capacity(self.tags.len());
+        for tag in &self.tags {
+            tags.push(DeepHashItem::List(vec![
+                DeepHashItem::Blob(b64url_decode(&tag.name)?),
+                DeepHashItem::Blob(b64url_decode(&tag.value)?),
+            ]));
+        }
+
+        Ok(deep_hash(&DeepHashItem::List(vec![
+            self.format.to_string().as_str().into(),
+            DeepHashItem::Blob(b64url_decode(&self.owner)?),
+            DeepHashItem::Blob(b64url_decode(&self.target)?),
+            self.quantity.as_str().into(),
+            self.reward.as_str().into(),
+            DeepHashItem::Blob(b64url_decode(&self.last_tx)?),
+            DeepHashItem::List(tags),
+            self.data_size.as_str().into(),
+            DeepHashItem::Blob(b64url_decode(&self.data_root)?),
+        ])))
+    }
+
+    /// Checks the signature against the owner and that the id matches it
+    pub fn verify(&self) -> Result<(), ArweaveError> {
+        let signature = b64url_decode(&self.signature)?;
+        if b64url_encode(&Sha256::digest(&signature)) != self.id {
+            return Err(ArweaveError::WalletError("Transaction id does not match signature".to_string()));
+        }
+        verify_signature(&b64url_decode(&self.owner)?, &self.signature_data()?, &signature)
+    }
+
+    pub fn decoded_tags(&self) -> Result<Vec<Tag>, ArweaveError> {
+        self.tags
+            .iter()
+            .map(|tag| {
+                Ok(Tag {
+                    name: String::from_utf8_lossy(&b64url_decode(&tag.name)?).into_owned(),
+                    value: String::from_utf8_lossy(&b64url_decode(&tag.value)?).into_owned(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// An ANS-104 data item, the format bundlers accept in place of a full transaction
+pub struct DataItem {
+    pub id: String,
+    pub tags: Vec<Tag>,
+    pub data: Vec<u8>,
+    signature: Vec<u8>,
+    owner: Vec<u8>,
+    target: Vec<u8>,
+    anchor: Vec<u8>,
+    tag_bytes: Vec<u8>,
+    bytes: Vec<u8>,
+}
+
+fn data_item_signature_data(owner: &[u8], target: &[u8], anchor: &[u8], tag_bytes: &[u8], data: &[u8]) -> Vec<u8> {
+    deep_hash(&DeepHashItem::List(vec![
+        "dataitem".into(),
+        "1".into(),
+        ARWEAVE_SIGNATURE_TYPE.to_string().as_str().into(),
+        owner.into(),
+        target.into(),
+        anchor.into(),
+        tag_bytes.into(),
+        data.into(),
+    ]))
+}
+
+impl DataItem {
+    pub fn new_signed(wallet: &ArweaveWallet, data: &[u8], tags: &[Tag]) -> Result<Self, ArweaveError> {
+        if wallet.owner().len() != ARWEAVE_OWNER_LENGTH {
+            return Err(ArweaveError::WalletError(format!(
+                "Bundled data items require a 4096 bit key, got {} bits",
+                wallet.owner().len() * 8
+            )));
+        }
+
+        let tag_bytes = encode_tags(tags);
+        let signature = wallet.sign(&data_item_signature_data(wallet.owner(), &[], &[], &tag_bytes, data));
+
+        let mut bytes = Vec::with_capacity(2 + 2 * ARWEAVE_OWNER_LENGTH + 18 + tag_bytes.len() + data.len());
+        bytes.extend_from_slice(&ARWEAVE_SIGNATURE_TYPE.to_le_bytes());
+        bytes.extend_from_slice(&signature);
+        bytes.extend_from_slice(wallet.owner());
+        // No target and no anchor
+        bytes.push(0);
+        bytes.push(0);
+        bytes.extend_from_slice(&(tags.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&(tag_bytes.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&tag_bytes);
+        bytes.extend_from_slice(data);
+
+        Ok(Self {
+            id: b64url_encode(&Sha256::digest(&signature)),
+            tags: tags.to_vec(),
+            data: data.to_vec(),
+            signature,
+            owner: wallet.owner().to_vec(),
+            target: Vec::new(),
+            anchor: Vec::new(),
+            tag_bytes,
+            bytes,
+        })
+    }
+
+    /// Parses a serialized data item. Only Arweave signature types are supported.
+    pub fn parse(bytes: &[u8]) -> Result<Self, ArweaveError> {
+        let invalid = || ArweaveError::EncodingError("Malformed data item".to_string());
+        let signature_type = u16::from_le_bytes(bytes.get(0..2).ok_or_else(invalid)?.try_into().unwrap());
+        if signature_type != ARWEAVE_SIGNATURE_TYPE {
+            return Err(ArweaveError::EncodingError(format!(
+                "Unsupported data item signature type: {}",
+                signature_type
+            )));
+        }
+
+        let signature = bytes.get(2..2 + ARWEAVE_OWNER_LENGTH).ok_or_else(invalid)?;
+        let owner = bytes.get(2 + ARWEAVE_OWNER_LENGTH..2 + 2 * ARWEAVE_OWNER_LENGTH).ok_or_else(invalid)?;
+        let mut cursor = 2 + 2 * ARWEAVE_OWNER_LENGTH;
+        let mut optional_field = || -> Result<Vec<u8>, ArweaveError> {
+            // Target and anchor are each a presence byte optionally followed by 32 bytes
+            match bytes.get(cursor) {
+                Some(0) => {
+                    cursor += 1;
+                    Ok(Vec::new())
+                }
+                Some(1) => {
+                    let field = bytes.get(cursor + 1..cursor + 33).ok_or_else(invalid)?;
+                    cursor += 33;
+                    Ok(field.to_vec())
+                }
+                _ => Err(invalid()),
+            }
+        };
+        let target = optional_field()?;
+        let anchor = optional_field()?;
+        let read_u64 = |at: usize| -> Result<usize, ArweaveError> {
+            let value = u64::from_le_bytes(bytes.get(at..at + 8).ok_or_else(invalid)?.try_into().unwrap());
+            usize::try_from(value).map_err(|_| invalid())
+        };
+        let tag_bytes_len = read_u64(cursor + 8)?;
+        cursor += 16;
+        // The length comes from the item itself, so it may point anywhere
+        let tags_end = cursor.checked_add(tag_bytes_len).ok_or_else(invalid)?;
+        let tag_bytes = bytes.get(cursor..tags_end).ok_or_else(invalid)?;
+        let data = bytes.get(tags_end..).ok_or_else(invalid)?;
+
+        Ok(Self {
+            id: b64url_encode(&Sha256::digest(signature)),
+            tags: decode_tags(tag_bytes)?,
+            data: data.to_vec(),
+            signature: signature.to_vec(),
+            owner: owner.to_vec(),
+            target,
+            anchor,
+            tag_bytes: tag_bytes.to_vec(),
+            bytes: bytes.to_vec(),
+        })
+    }
+
+    /// Checks the signature against the owner
+    pub fn verify(&self) -> Result<(), ArweaveError> {
+        let signature_data =
+            data_item_signature_data(&self.owner, &self.target, &self.anchor, &self.tag_bytes, &self.data);
+        verify_signature(&self.owner, &signature_data, &self.signature)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+fn write_avro_long(out: &mut Vec<u8>, value: i64) {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    while zigzag >= 0x80 {
+        out.push((zigzag as u8 & 0x7f) | 0x80);
+        zigzag >>= 7;
+    }
+    out.push(zigzag as u8);
+}
+
+fn read_avro_long(data: &[u8], cursor: &mut usize) -> Result<i64, ArweaveError> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *data
+            .get(*cursor)
+            .ok_or_else(|| ArweaveError::EncodingError("Truncated tag data".to_string()))?;
+        *cursor += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 63 {
+            return Err(ArweaveError::EncodingError("Invalid tag length".to_string()));
+        }
+    }
+    Ok(((value >> 1) as i64) ^ -((value & 1) as i64))
+}
+
+/// Tags in data items are an Avro array of `{ name: bytes, value: bytes }` records
+fn encode_tags(tags: &[Tag]) -> Vec<u8> {
+    let mut out = Vec::new();
+    if tags.is_empty() {
+        return out;
+    }
+    write_avro_long(&mut out, tags.len() as i64);
+    for tag in tags {
+        for field in [&tag.name, &tag.value] {
+            write_avro_long(&mut out, field.len() as i64);
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+    write_avro_long(&mut out, 0);
+    out
+}
+
+fn decode_tags(data: &[u8]) -> Result<Vec<Tag>, ArweaveError> {
+    let truncated = || ArweaveError::EncodingError("Truncated tag data".to_string());
+    let mut tags = Vec::new();
+    let mut cursor = 0;
+    while cursor < data.len() {
+        let count = read_avro_long(data, &mut cursor)?;
+        if count == 0 {
+            break;
+        }
+        if count < 0 {
+            // A negative block count is followed by the block size in bytes
+            read_avro_long(data, &mut cursor)?;
+        }
+        for _ in 0..count.unsigned_abs() {
+            let mut fields = [String::new(), String::new()];
+            for field in fields.iter_mut() {
+                let len = usize::try_from(read_avro_long(data, &mut cursor)?)
+                    .map_err(|_| ArweaveError::EncodingError("Invalid tag length".to_string()))?;
+                let end = cursor.checked_add(len).ok_or_else(truncated)?;
+                let bytes = data.get(cursor..end).ok_or_else(truncated)?;
+                *field = String::from_utf8_lossy(bytes).into_owned();
+                cursor = end;
+            }
+            let [name, value] = fields;
+            tags.push(Tag { name, value });
+        }
+    }
+    Ok(tags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Vectors come from a Node port of arweave-js's deepHash and merkle
+    // chunking and of arbundles' data item layout, signed with Node's crypto
+    const WALLET: &str = include_str!("../testdata/arweave/wallet.json");
+    const DATA_ITEM: &[u8] = include_bytes!("../testdata/arweave/data_item.bin");
+    const TRANSACTION: &str = include_str!("../testdata/arweave/transaction.json");
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Bytes 0, 1, ..., 250, 0, 1, ...
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn deep_hash_matches_arweave_js() {
+        assert_eq!(
+            hex(&deep_hash(&"hello".into())),
+            "33ab2407a6c328c0bc1bbe5971f49af5c1908985f83c3d2bd89a9e221dd8b068dc61ce968ba3f9ab12d5361ba3944382"
+        );
+        assert_eq!(
+            hex(&deep_hash(&DeepHashItem::List(Vec::new()))),
+            "a69e7d37fdc7f040a9ec16aae84de24fab4a653dac4de0bd247e36bab9fe45d9289c5a04a893c95285812f5cefc9707a"
+        );
+        let nested = DeepHashItem::List(vec![
+            "a".into(),
+            DeepHashItem::List(vec!["".into(), "bc".into()]),
+            DeepHashItem::Blob(vec![0, 1, 2]),
+        ]);
+        assert_eq!(
+            hex(&deep_hash(&nested)),
+            "87934b8de3b7845c14bc8358044899d610d15f294332eace28b87f94b0f17d23877d7b40fd5329be02fc22e9f132adae"
+        );
+    }
+
+    #[test]
+    fn data_root_matches_arweave_js() {
+        assert!(data_root(&[]).is_empty());
+        let cases = [
+            (1, "1edff26615c60435197cbbf8383eb07bb164ae37290e918fc1064466010955f9"),
+            // Exactly one full chunk
+            (MAX_CHUNK_SIZE, "82dcbb281d9b68b169ece1b1b95db005e5f7362a694b5b4d56530e66bc88ab9a"),
+            // A last chunk under MIN_CHUNK_SIZE splits the data in two halves
+            (MAX_CHUNK_SIZE + 1, "9092a437a41601c50cfd6414c5e3579e7c0acb47e9ee6c9434da1be9736538f0"),
+            (600_000, "3b9a56afd7a67130990a883698bb31d6820b3392e15addf5c73ff5eaab5e0e8c"),
+        ];
+        for (len, expected) in cases {
+            assert_eq!(hex(&data_root(&pattern(len))), expected, "{} bytes", len);
+        }
+    }
+
+    #[test]
+    fn wallet_address_from_jwk() {
+        let wallet = ArweaveWallet::from_jwk(WALLET).unwrap();
+        assert_eq!(wallet.owner().len(), ARWEAVE_OWNER_LENGTH);
+        assert_eq!(wallet.address(), "CjKU-0HRNaOajtlOoQDeuNaxWj6kGdeq8HWy2WZFZiA");
+    }
+
+    #[test]
+    fn tags_encode_as_avro() {
+        assert!(encode_tags(&[]).is_empty());
+        assert_eq!(encode_tags(&[Tag::new("a", "b")]), [0x02, 0x02, b'a', 0x02, b'b', 0x00]);
+
+        // Lengths of 64 and up take two bytes once zigzag encoded
+        let long = "x".repeat(64);
+        let encoded = encode_tags(&[Tag::new(&long, "")]);
+        assert_eq!(encoded[..3], [0x02, 0x80, 0x01]);
+        assert_eq!(decode_tags(&encoded).unwrap(), [Tag::new(&long, "")]);
+
+        // Avro writers may emit a block as a negative count followed by its size
+        let mut blocked = vec![0x01, 0x0c];
+        blocked.extend_from_slice(&[0x02, b'a', 0x02, b'b']);
+        blocked.push(0x00);
+        assert_eq!(decode_tags(&blocked).unwrap(), [Tag::new("a", "b")]);
+    }
+
+    #[test]
+    fn parses_and_verifies_arbundles_data_item() {
+        let item = DataItem::parse(DATA_ITEM).unwrap();
+        assert_eq!(item.id, "Nd_NwHNhyQSOn1r69TJ2sxLEjGhHSikS6hujwjuuetc");
+        assert_eq!(item.tags, [Tag::new("Content-Type", "application/json"), Tag::new("App-Name", "FunctionLayer")]);
+        assert_eq!(item.data, br#"{"code":"return 42"}"#);
+        assert_eq!(item.anchor, [7u8; 32]);
+        item.verify().unwrap();
+
+        let mut tampered = DATA_ITEM.to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(DataItem::parse(&tampered).unwrap().verify().is_err());
+    }
+
+    #[test]
+    fn rejects_lengths_past_the_end() {
+        // No target, then the anchor, then the tag count and the tag bytes' length
+        let tag_bytes_len_at = 2 + 2 * ARWEAVE_OWNER_LENGTH + 1 + 33 + 8;
+        let mut item = DATA_ITEM.to_vec();
+        item[tag_bytes_len_at..tag_bytes_len_at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(DataItem::parse(&item), Err(ArweaveError::EncodingError(_))));
+
+        // A negative field length, and one that overflows the cursor
+        assert!(decode_tags(&[0x02, 0x01, b'a']).is_err());
+        let mut huge = vec![0x02];
+        write_avro_long(&mut huge, i64::MAX);
+        assert!(decode_tags(&huge).is_err());
+    }
+
+    #[test]
+    fn signed_data_item_round_trips() {
+        let wallet = ArweaveWallet::from_jwk(WALLET).unwrap();
+        let tags = [Tag::new("Type", "Code-Module")];
+        let item = DataItem::new_signed(&wallet, b"data", &tags).unwrap();
+        let parsed = DataItem::parse(item.as_bytes()).unwrap();
+        assert_eq!(parsed.id, item.id);
+        assert_eq!(parsed.tags, tags);
+        assert_eq!(parsed.data, b"data");
+        parsed.verify().unwrap();
+    }
+
+    #[test]
+    fn verifies_arweave_js_transaction() {
+        let tx: Transaction = serde_json::from_str(TRANSACTION).unwrap();
+        tx.verify().unwrap();
+        assert_eq!(b64url_decode(&tx.data_root).unwrap(), data_root(&pattern(1000)));
+        assert_eq!(tx.decoded_tags().unwrap()[1], Tag::new("App-Name", "FunctionLayer"));
+
+        let mut tampered = tx.clone();
+        tampered.reward = "1".to_string();
+        assert!(tampered.verify().is_err());
+    }
+
+    #[test]
+    fn signed_transaction_verifies() {
+        let wallet = ArweaveWallet::from_jwk(WALLET).unwrap();
+        let tx = Transaction::new_signed(&wallet, b"data", &[Tag::new("a", "b")], "", "0").unwrap();
+        tx.verify().unwrap();
+        assert_eq!(tx.decoded_tags().unwrap(), [Tag::new("a", "b")]);
+    }
+}
@@ -0,0 +1,88 @@
+use clap::Parser;
+use function_layer::aggregator::Aggregator;
+use function_layer::aggregator_server::AggregatorService;
+use function_layer::config::{Config, ConfigOverrides, LogFormat};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use dotenv::dotenv;
+use function_layer::telemetry;
+use tracing::{error, info};
+
+/// Compares operator responses and declares a result per task once a quorum agrees
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Path to a TOML or YAML config file (defaults to function_layer.toml if present)
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Ethereum RPC endpoint, overrides RPC_URL
+    #[arg(long)]
+    rpc_url: Option<String>,
+    /// ServiceManager contract address, overrides EXECUTOR_CONTRACT
+    #[arg(long)]
+    contract: Option<String>,
+    /// Matching responses needed for a result, overrides aggregator.quorum
+    #[arg(long)]
+    quorum: Option<usize>,
+    /// Block to start watching from, overrides aggregator.start_block
+    #[arg(long)]
+    from_block: Option<u64>,
+    /// Accept signed responses from operators on this address, overrides aggregator.listen_addr
+    #[arg(long)]
+    listen: Option<String>,
+    /// Log output, pretty or json; overrides LOG_FORMAT
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    dotenv().ok();
+    let cli = Cli::parse();
+
+    // The aggregator only reads the chain unless it submits BLS responses, so
+    // operator keys are not required
+    let overrides = ConfigOverrides {
+        rpc_url: cli.rpc_url,
+        contract_address: cli.contract,
+        log_format: cli.log_format,
+        ..Default::default()
+    };
+    let mut config = match Config::resolve(cli.config.as_deref(), &overrides) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if cli.quorum.is_some() {
+        config.aggregator.quorum = cli.quorum;
+    }
+    if cli.from_block.is_some() {
+        config.aggregator.start_block = cli.from_block;
+    }
+    if cli.listen.is_some() {
+        config.aggregator.listen_addr = cli.listen;
+    }
+    let _telemetry = telemetry::init(&config.logging, "function_layer_aggregator")?;
+
+    let mut aggregator = Aggregator::new(&config).await?;
+    if let Some(listen_addr) = config.aggregator.listen_addr.clone() {
+        let service = Arc::new(AggregatorService::new(&config, aggregator.tallies()).await?);
+        tokio::spawn(async move {
+            if let Err(e) = service.serve(&listen_addr).await {
+                error!("Response endpoint stopped: {:#}", e);
+                std::process::exit(1);
+            }
+        });
+    }
+    info!("Starting response aggregator...");
+    loop {
+        if let Err(e) = aggregator.run().await {
+            error!("Error in aggregator: {:#}", e);
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            info!("continuing...");
+        }
+    }
+}
@@ -1,58 +1,668 @@
 // mod function_layer;
-use function_layer::config::Config;
+use clap::{Parser, Subcommand};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{Address, Bytes, U256, U64};
+use ethers::utils::hex;
+use function_layer::admin::AdminApi;
+use function_layer::arweave::FunctionMetadata;
+use function_layer::arweave_tx::ArweaveWallet;
+use function_layer::bls::BlsKeyPair;
+use function_layer::config::{Config, ConfigOverrides, LogFormat};
+use function_layer::dry_run::{self, DryRunInputs};
+use function_layer::executor::{error_response, CodeModule, ExecutionFailed, ExecutionLogs, FetchedCode};
+use function_layer::js_executor::JsExecutor;
+use function_layer::npm::{self, NpmInstaller};
 use function_layer::listener::EventListener;
+use function_layer::registry::{FunctionRef, Manifest, Registry};
+use function_layer::retry::unix_now;
+use function_layer::{metrics, shutdown, telemetry};
+use function_layer::task_store::{TaskState, TaskStore};
+use function_layer::registration::{
+    OperatorRegistration, RegistrationStatus, SignatureWithSaltAndExpiry, DEFAULT_SIGNATURE_TTL,
+};
+use function_layer::{ArweaveClient, ContractClient};
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use dotenv::dotenv;
-use env_logger::{Builder, Env};
+use tracing::{error, info, warn};
+
+/// Operator node for the function layer AVS
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Path to a TOML or YAML config file (defaults to function_layer.toml if present)
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+    /// Ethereum RPC endpoint, overrides RPC_URL
+    #[arg(long, global = true)]
+    rpc_url: Option<String>,
+    /// ServiceManager contract address, overrides EXECUTOR_CONTRACT
+    #[arg(long, global = true)]
+    contract: Option<String>,
+    /// Operator address, overrides OPERATOR_ADDRESS
+    #[arg(long, global = true)]
+    operator_address: Option<String>,
+    /// Arweave gateway used to fetch code, overrides ARWEAVE_GATEWAY_URL
+    #[arg(long, global = true)]
+    arweave_gateway: Option<String>,
+    /// Comma separated list of languages to execute
+    #[arg(long, global = true, value_delimiter = ',')]
+    languages: Option<Vec<String>>,
+    /// Log output, pretty or json; overrides LOG_FORMAT
+    #[arg(long, global = true)]
+    log_format: Option<LogFormat>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+impl Cli {
+    fn overrides(&self) -> ConfigOverrides {
+        ConfigOverrides {
+            rpc_url: self.rpc_url.clone(),
+            contract_address: self.contract.clone(),
+            operator_address: self.operator_address.clone(),
+            arweave_gateway_url: self.arweave_gateway.clone(),
+            supported_languages: self.languages.clone(),
+            log_format: self.log_format,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Listen for compute requests and execute them (the default)
+    Run,
+    /// Run a CodeModule through the runtime as an operator would for a task,
+    /// without a chain, and print the response it would sign
+    #[command(alias = "exec-local")]
+    DryRun {
+        /// Path to a JSON file with `imports` and `function`
+        #[arg(conflicts_with = "code_uri", required_unless_present = "code_uri")]
+        code: Option<PathBuf>,
+        /// Arweave ID or registry://<name>@<version> to fetch the CodeModule from instead
+        #[arg(long)]
+        code_uri: Option<String>,
+        #[arg(short, long, default_value = "js")]
+        language: String,
+        #[command(flatten)]
+        inputs: DryRunArgs,
+    },
+    /// Install a CodeModule's dependencies from the registry, filling the npm
+    /// and node_modules caches so operators can run it offline
+    FetchDeps {
+        code: PathBuf,
+    },
+    /// Upload a CodeModule JSON file to Arweave and print its ID
+    Publish {
+        code: PathBuf,
+        #[arg(short, long, default_value = "js")]
+        language: String,
+        /// Function name published as a tag
+        #[arg(long)]
+        name: Option<String>,
+        /// Function version published as a tag
+        #[arg(long)]
+        version: Option<String>,
+    },
+    /// Create a new compute task on the ServiceManager
+    CreateTask {
+        /// Arweave ID of already published code
+        #[arg(long, conflicts_with = "code_file", required_unless_present = "code_file")]
+        code_uri: Option<String>,
+        /// CodeModule JSON file to publish first
+        #[arg(long)]
+        code_file: Option<PathBuf>,
+        #[arg(short, long, default_value = "js")]
+        language: String,
+        /// Number of operator responses requested
+        #[arg(short, long, default_value_t = 1)]
+        responses: u64,
+        /// Key paying for the task, defaults to the operator key
+        #[arg(long, env = "PRIVATE_KEY", hide_env_values = true)]
+        private_key: Option<String>,
+    },
+    /// Show the on-chain state of a task and the responses submitted so far
+    Status {
+        task_index: u64,
+        /// Block to start searching for TaskResponded events from
+        #[arg(long, default_value_t = 0)]
+        from_block: u64,
+    },
+    /// Register the operator to the AVS, signing the AVSDirectory digest with the operator key
+    Register {
+        /// Only report the current registration status
+        #[arg(long)]
+        check: bool,
+        /// How long the registration signature stays valid, in seconds
+        #[arg(long, default_value_t = DEFAULT_SIGNATURE_TTL.as_secs())]
+        expiry_secs: u64,
+        /// Use a signature produced elsewhere instead (hex encoded)
+        #[arg(long, requires_all = ["salt", "expiry"])]
+        signature: Option<String>,
+        /// Hex encoded 32 byte salt used with --signature
+        #[arg(long, requires = "signature")]
+        salt: Option<String>,
+        /// Expiry timestamp used with --signature
+        #[arg(long, requires = "signature")]
+        expiry: Option<u64>,
+    },
+    /// Deregister the operator from the AVS
+    Deregister,
+    /// Generate a BN254 key for BLS response signing
+    BlsKeygen,
+    /// Register the configured BLS public key with the ServiceManager
+    BlsRegister,
+    /// Inspect the local task database
+    Tasks {
+        #[command(subcommand)]
+        command: TasksCommand,
+    },
+    /// Resolve and publish function registry manifests
+    Registry {
+        #[command(subcommand)]
+        command: RegistryCommand,
+    },
+    /// Execute the compute requests emitted in a past block range
+    Replay {
+        #[arg(long)]
+        from_block: u64,
+        /// Last block to replay, defaults to the current block
+        #[arg(long)]
+        to_block: Option<u64>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum RegistryCommand {
+    /// Print what a registry://<name>@<version> reference resolves to
+    Resolve { reference: String },
+    /// Sign a registry manifest JSON file and upload it to Arweave, printing its ID
+    Publish {
+        manifest: PathBuf,
+        /// Key of the registry publisher, whose address operators set as registry.signer
+        #[arg(long, env = "REGISTRY_PRIVATE_KEY", hide_env_values = true)]
+        private_key: String,
+    },
+}
+
+/// The task a dry run answers; seeds the clock and randomness in deterministic mode
+#[derive(clap::Args, Debug)]
+struct DryRunArgs {
+    #[arg(long, default_value_t = 0)]
+    task_index: u32,
+    /// Block the task was created in
+    #[arg(long, default_value_t = 0)]
+    block: u32,
+    /// Timestamp of that block, in seconds
+    #[arg(long, default_value_t = 0)]
+    timestamp: u64,
+    /// Number of operator responses the task requests
+    #[arg(long, default_value_t = 1)]
+    responses: u64,
+}
+
+#[derive(Subcommand, Debug)]
+enum TasksCommand {
+    /// List the most recently updated tasks
+    List {
+        /// Only list tasks in this state (discovered, fetching, executing, executed, submitting, confirmed, sent_to_aggregator, failed, dead_letter, skipped, non_deterministic)
+        #[arg(long)]
+        state: Option<TaskState>,
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Show a task with its result, transaction and state history
+    Show { task_index: u32 },
+    /// Print what the latest run of a task wrote to stdout and stderr
+    Logs { task_index: u32 },
+    /// Print the execution receipt of the latest run of a task as JSON
+    Receipt { task_index: u32 },
+    /// Count tasks per state
+    Summary,
+    /// List tasks that ran out of retry attempts
+    DeadLetter {
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Retry failed or dead-lettered tasks right away
+    Requeue {
+        task_indexes: Vec<u32>,
+        /// Requeue every task in the dead-letter list
+        #[arg(long, conflicts_with = "task_indexes")]
+        all: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    Builder::from_env(Env::default().default_filter_or("info"))
-        .init();
     dotenv().ok(); // Load .env file at start
-    // Initialize configuration
-    let config = Config::new(
-        &std::env::var("RPC_URL").expect("ETH_NODE_URL must be set in .env"),
-        &std::env::var("EXECUTOR_CONTRACT").expect("CONTRACT_ADDRESS must be set in .env"),
-        &std::env::var("OPERATOR_ADDRESS").expect("OPERATOR_ADDRESS must be set in .env")
-    );
-
-    println!("Starting compute request listener...");
-    println!("Supported languages: {:?}", config.supported_languages);
-    println!("Operator address: {}", config.operator_address);
-    let mut listener = EventListener::new(config).await?;
+    let cli = Cli::parse();
+    let overrides = cli.overrides();
+    let command = cli.command.unwrap_or(Command::Run);
+
+    // Initialize configuration. Local commands don't need chain settings, so
+    // they skip validation.
+    let config = match &command {
+        Command::DryRun { .. }
+        | Command::FetchDeps { .. }
+        | Command::Publish { .. }
+        | Command::BlsKeygen
+        | Command::Registry { .. }
+        | Command::Tasks { .. } => {
+            Config::resolve(cli.config.as_deref(), &overrides)
+        }
+        _ => Config::load(cli.config.as_deref(), &overrides),
+    };
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let _telemetry = telemetry::init(&config.logging, "function_layer")?;
+
+    match command {
+        Command::Run => run(config).await,
+        Command::DryRun { code, code_uri, language, inputs } => {
+            let (code, code_uri) = match (code, code_uri) {
+                (_, Some(code_uri)) => (dry_run::fetch_code_module(&config, &code_uri, &language).await?, code_uri),
+                (Some(code), None) => (read_code(&code)?, String::new()),
+                (None, None) => unreachable!("clap requires a code file or --code-uri"),
+            };
+            let inputs = DryRunInputs {
+                task_index: inputs.task_index,
+                code_uri,
+                task_created_block: inputs.block,
+                block_timestamp: inputs.timestamp,
+                response_count: inputs.responses,
+            };
+            print_dry_run(&config, &code, &language, &inputs).await
+        }
+        Command::FetchDeps { code } => {
+            let code_module = read_code_module(&code)?;
+            if code_module.dependencies.is_empty() {
+                println!("{} has no dependencies", code.display());
+                return Ok(());
+            }
+            let node_modules = NpmInstaller::new(config.runtimes.js.clone())
+                .node_modules(&code_module.dependencies, code_module.lockfile.as_ref(), false)
+                .await?;
+            println!("{}", node_modules.display());
+            Ok(())
+        }
+        Command::Publish { code, language, name, version } => {
+            let id = publish(&config, &code, FunctionMetadata { language, name, version }).await?;
+            println!("{}", id);
+            Ok(())
+        }
+        Command::CreateTask { code_uri, code_file, language, responses, private_key } => {
+            let code_uri = match (code_uri, code_file) {
+                (Some(code_uri), _) => code_uri,
+                (None, Some(code_file)) => {
+                    let metadata = FunctionMetadata { language: language.clone(), name: None, version: None };
+                    let id = publish(&config, &code_file, metadata).await?;
+                    println!("Published code: {}", id);
+                    id
+                }
+                (None, None) => unreachable!("clap requires one of --code-uri or --code-file"),
+            };
+            let key = private_key.unwrap_or_else(|| config.operator_private_key.expose_secret().to_string());
+            let client = ContractClient::from_config_with_key(&config, &key).await?;
+            let (task_index, receipt) = client.create_task(&code_uri, &language, U256::from(responses)).await?;
+            println!("Created task {} in transaction {:?}", task_index, receipt.transaction_hash);
+            Ok(())
+        }
+        Command::Status { task_index, from_block } => status(&config, task_index, from_block).await,
+        Command::Register { check, expiry_secs, signature, salt, expiry } => {
+            let registration = OperatorRegistration::new(&config).await?;
+            let status = registration.status().await?;
+            print_registration_status(&status);
+            if check {
+                return Ok(());
+            }
+
+            let receipt = match (signature, salt, expiry) {
+                (Some(signature), Some(salt), Some(expiry)) => {
+                    let salt: [u8; 32] = hex::decode(salt.trim_start_matches("0x"))?
+                        .try_into()
+                        .map_err(|_| "salt must be 32 bytes")?;
+                    let signature = Bytes::from(hex::decode(signature.trim_start_matches("0x"))?);
+                    registration
+                        .submit(SignatureWithSaltAndExpiry { signature, salt, expiry: U256::from(expiry) })
+                        .await?
+                }
+                _ => registration.register(Duration::from_secs(expiry_secs)).await?,
+            };
+            println!("Registered operator {:?} in transaction {:?}", status.operator, receipt.transaction_hash);
+            Ok(())
+        }
+        Command::Deregister => {
+            let registration = OperatorRegistration::new(&config).await?;
+            let receipt = registration.deregister().await?;
+            println!("Deregistered operator {:?} in transaction {:?}", registration.operator(), receipt.transaction_hash);
+            Ok(())
+        }
+        Command::BlsKeygen => {
+            let key = BlsKeyPair::random();
+            let pubkey_g1 = key.public_g1();
+            let pubkey_g2 = key.public_g2();
+            println!("BLS_PRIVATE_KEY={}", key.secret_hex());
+            println!("Public key G1: ({}, {})", pubkey_g1.x, pubkey_g1.y);
+            println!("Public key G2: ({:?}, {:?})", pubkey_g2.x, pubkey_g2.y);
+            Ok(())
+        }
+        Command::BlsRegister => {
+            let key = BlsKeyPair::from_hex(config.bls.private_key.expose_secret())
+                .map_err(|e| format!("{} (set bls.private_key or BLS_PRIVATE_KEY)", e))?;
+            let client = ContractClient::from_config(&config).await?;
+            if let Some(index) = client.bls_operator_index(client.signer_address()).await? {
+                println!("Operator {:?} already has a BLS key at index {}", client.signer_address(), index);
+                return Ok(());
+            }
+            let proof = key.sign_registration(client.signer_address());
+            let receipt = client.register_bls_pubkey(key.public_g1(), key.public_g2(), proof).await?;
+            println!("Registered BLS key for {:?} in transaction {:?}", client.signer_address(), receipt.transaction_hash);
+            Ok(())
+        }
+        Command::Tasks { command } => tasks(&config, command),
+        Command::Registry { command } => registry(&config, command).await,
+        Command::Replay { from_block, to_block } => {
+            let mut listener = EventListener::new(config).await?;
+            listener.replay(U64::from(from_block), to_block.map(U64::from)).await?;
+            Ok(())
+        }
+    }
+}
+
+async fn run(config: Config) -> Result<(), Box<dyn Error>> {
+    info!("Starting compute request listener...");
+    info!("Supported languages: {:?}", config.supported_languages);
+    info!("Operator address: {}", config.operator_address);
+    let js_executor = JsExecutor::new(config.runtimes.js.clone());
+    remove_stale_run_dirs(&js_executor);
+    let shutdown = shutdown::listen_for_signals();
+    if let Some(listen_addr) = config.metrics.listen_addr.clone() {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(&listen_addr).await {
+                error!("Metrics endpoint stopped: {:#}", e);
+            }
+        });
+    }
+    let admin_config = config.clone();
+    let mut listener = EventListener::new(config).await?.with_shutdown(shutdown.clone());
+    if let Some(listen_addr) = admin_config.admin.listen_addr.clone() {
+        let api = Arc::new(AdminApi::new(&admin_config, listener.status(), listener.store())?);
+        tokio::spawn(async move {
+            if let Err(e) = api.serve(&listen_addr).await {
+                error!("Admin API stopped: {:#}", e);
+            }
+        });
+    }
+
+    // Refuse to start if the operator can't possibly do useful work
+    let report = listener.check_health().await;
+    info!("Health check:\n{}", report);
+    if report.is_fatal() {
+        error!("Refusing to start: fix the fatal checks above");
+        std::process::exit(1);
+    }
+
     // Start listening for events
-    println!("Listening for compute requests...");
-    
-    // Run indefinitely, catching any errors
-    loop {
+    info!("Listening for compute requests...");
+
+    // Run until SIGINT/SIGTERM, catching any errors
+    while !shutdown.is_cancelled() {
         if let Err(e) = listener.start_listening().await {
-            eprintln!("Error in listener: {:#}", e);
+            error!("Error in listener: {:#}", e);
             // Optional: Add a small delay before retrying
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-            println!("continuing...");
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_secs(1)) => info!("continuing..."),
+                _ = shutdown.cancelled() => {}
+            }
         }
     }
-    // Ok(())
+
+    listener.close();
+    remove_stale_run_dirs(&js_executor);
+    info!("Shut down cleanly");
+    Ok(())
 }
 
+fn remove_stale_run_dirs(js_executor: &JsExecutor) {
+    match js_executor.remove_stale_run_dirs() {
+        Ok(0) => {}
+        Ok(removed) => info!("Removed {} leftover run directories", removed),
+        Err(e) => warn!("Failed to clean the JS work directory: {}", e),
+    }
+}
+
+fn read_code_module(path: &Path) -> Result<CodeModule, Box<dyn Error>> {
+    Ok(read_code(path)?.module)
+}
+
+/// A local code module file, kept as read so the receipt hashes the same bytes
+/// an upload of it would
+fn read_code(path: &Path) -> Result<FetchedCode, Box<dyn Error>> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(FetchedCode { code_uri: String::new(), module: serde_json::from_slice(&bytes)?, bytes, output: None })
+}
 
-// use function_layer::js_executor::JsExecutor;
-// use function_layer::executor::CodeModule;
+async fn print_dry_run(
+    config: &Config,
+    code: &FetchedCode,
+    language: &str,
+    inputs: &DryRunInputs,
+) -> Result<(), Box<dyn Error>> {
+    let run = match dry_run::dry_run(config, code, language, inputs).await {
+        Ok(run) => run,
+        Err(e) => {
+            if let Some(failed) = e.downcast_ref::<ExecutionFailed>() {
+                print_logs(&failed.logs);
+            }
+            if let Some(response) = error_response(&inputs.task(language), &e) {
+                println!("Error response: {}", response.to_response_string());
+            }
+            return Err(e.into());
+        }
+    };
+    print_logs(&run.execution.logs);
+    let usage = &run.execution.usage;
+    println!("Runtime: {} {} ({} ms)", run.execution.runtime.name, run.execution.runtime.version, usage.wall_time_ms);
+    println!("Result: {:?}", run.execution.result);
+    println!("Response string: {}", run.response_string);
+    println!("Receipt hash: {:?}", run.receipt.hash());
+    println!("Signed message: {:?}", run.message_hash);
+    Ok(())
+}
 
-// #[tokio::main]
-// async fn main() -> eyre::Result<()> {
-//     // Create a simple JavaScript function that adds two numbers
-//     let code_module = CodeModule {
-//         imports: String::new(), // No imports needed for this simple example
-//         function: String::from("() => { return 5 + 3; }"),
-//     };
+async fn publish(config: &Config, code: &Path, metadata: FunctionMetadata) -> Result<String, Box<dyn Error>> {
+    let code_module = read_code_module(code)?;
+    // Operators would refuse it anyway
+    npm::check_pinned(&code_module.dependencies, code_module.lockfile.as_ref())?;
+    Ok(arweave_uploader(config)?.upload_code_module(&code_module, &metadata).await?)
+}
 
-//     // Create executor and run the code
-//     let executor = JsExecutor::new();
-//     let result = executor.execute(&code_module).await?;
-    
-//     println!("Result: {:?}", result); // Should print Result: Uint(8)
-    
-//     Ok(())
-// }
\ No newline at end of file
+fn arweave_uploader(config: &Config) -> Result<ArweaveClient, Box<dyn Error>> {
+    let wallet_path = config
+        .arweave
+        .wallet_path
+        .as_ref()
+        .ok_or("arweave.wallet_path (or ARWEAVE_WALLET_PATH) must be set to publish")?;
+    let mut client = ArweaveClient::new(Some(config.arweave.gateway_url.clone()))
+        .with_wallet(ArweaveWallet::from_jwk_file(wallet_path)?);
+    if let Some(bundler_url) = &config.arweave.bundler_url {
+        client = client.with_bundler(bundler_url.clone());
+    }
+    Ok(client)
+}
+
+async fn registry(config: &Config, command: RegistryCommand) -> Result<(), Box<dyn Error>> {
+    match command {
+        RegistryCommand::Resolve { reference } => {
+            let function = FunctionRef::parse(&reference).ok_or("expected registry://<name>@<version>")??;
+            let entry = Registry::new(config).resolve(&function).await?;
+            println!("{}", function);
+            println!("  Code: {}", entry.code_uri);
+            println!("  Language: {}", entry.language);
+            println!("  Output: {}", entry.output);
+        }
+        RegistryCommand::Publish { manifest, private_key } => {
+            let contents = std::fs::read_to_string(&manifest)
+                .map_err(|e| format!("Failed to read {}: {}", manifest.display(), e))?;
+            let manifest: Manifest = serde_json::from_str(&contents)?;
+            let wallet = private_key.parse::<LocalWallet>()?;
+            let signed = manifest.sign(&wallet).await?;
+            let data = serde_json::to_vec(&signed)?;
+            let id = arweave_uploader(config)?.upload(&data, &signed.tags(wallet.address())).await?;
+            println!("Published registry manifest signed by {:?}: {}", wallet.address(), id);
+        }
+    }
+    Ok(())
+}
+
+fn print_logs(logs: &ExecutionLogs) {
+    if !logs.stdout.is_empty() {
+        println!("stdout:\n{}", logs.stdout.trim_end());
+    }
+    if !logs.stderr.is_empty() {
+        println!("stderr:\n{}", logs.stderr.trim_end());
+    }
+    if logs.truncated {
+        println!("(logs truncated)");
+    }
+}
+
+fn print_registration_status(status: &RegistrationStatus) {
+    println!("Operator {:?}", status.operator);
+    println!("  AVS (ServiceManager): {:?}", status.avs);
+    println!("  AVSDirectory: {:?}", status.avs_directory);
+    println!("  EigenLayer operator: {}", status.eigenlayer_operator);
+    println!("  Registered with AVSDirectory: {}", status.registered_with_avs_directory);
+    println!("  Registered with ServiceManager: {}", status.registered_with_service_manager);
+}
+
+async fn status(config: &Config, task_index: u64, from_block: u64) -> Result<(), Box<dyn Error>> {
+    let client = ContractClient::from_config(config).await?;
+    let operator: Address = config.operator_address.parse()?;
+    let task_index = U256::from(task_index);
+    let status = client.task_status(task_index, operator, U64::from(from_block)).await?;
+
+    if task_index >= U256::from(status.latest_task_num) {
+        println!("Task {} does not exist (latest task number is {})", task_index, status.latest_task_num);
+        return Ok(());
+    }
+    println!("Task {}", task_index);
+    println!("  Task hash: {:?}", status.task_hash);
+    println!("  Submission count: {}", status.submission_count);
+    println!("  Responded by this operator: {}", status.responded);
+
+    let responses = client.task_responses(task_index, U64::from(from_block)).await?;
+    println!("  Responses ({}):", responses.len());
+    for response in responses {
+        println!("    {:?}: {}", response.operator, response.response_string);
+    }
+    Ok(())
+}
+
+fn tasks(config: &Config, command: TasksCommand) -> Result<(), Box<dyn Error>> {
+    let store = TaskStore::open(&config.store.path)?;
+    match command {
+        TasksCommand::List { state, limit } => {
+            for record in store.list(state, limit)? {
+                println!(
+                    "{:>6}  {:<17}  {}  {}",
+                    record.task_index,
+                    record.state.to_string(),
+                    record.updated_at,
+                    record.error.or(record.result).unwrap_or_default()
+                );
+            }
+        }
+        TasksCommand::Show { task_index } => {
+            let Some(record) = store.get(task_index)? else {
+                println!("Task {} is not in {}", task_index, config.store.path.display());
+                return Ok(());
+            };
+            println!("Task {}", record.task_index);
+            println!("  Code: {} ({})", record.code_arweave_uri, record.language);
+            println!("  Responses requested: {}", record.response_count);
+            println!("  Created in block: {}", record.task_created_block);
+            println!("  State: {}", record.state);
+            if let Some(result) = &record.result {
+                println!("  Result: {}", result);
+            }
+            if let Some(tx_hash) = &record.tx_hash {
+                println!("  Transaction: {}", tx_hash);
+            }
+            if let Some(error) = &record.error {
+                println!("  Error: {}", error);
+            }
+            if let Some(stored) = store.receipt(task_index)? {
+                println!("  Receipt: {:?}", stored.receipt_hash);
+                if let Some(arweave_id) = &stored.arweave_id {
+                    println!("  Receipt upload: {}", arweave_id);
+                }
+            }
+            println!("  History:");
+            for transition in store.history(task_index)? {
+                println!(
+                    "    {}  {}{}",
+                    transition.at,
+                    transition.state,
+                    transition.detail.map(|detail| format!(": {}", detail)).unwrap_or_default()
+                );
+            }
+        }
+        TasksCommand::Logs { task_index } => match store.logs(task_index)? {
+            Some(logs) => print_logs(&logs),
+            None => println!("No logs for task {}", task_index),
+        },
+        TasksCommand::Receipt { task_index } => match store.receipt(task_index)? {
+            Some(stored) => println!("{}", serde_json::to_string_pretty(&stored)?),
+            None => println!("No receipt for task {}", task_index),
+        },
+        TasksCommand::Summary => {
+            for (state, count) in store.counts()? {
+                println!("{:<17} {}", state.to_string(), count);
+            }
+        }
+        TasksCommand::DeadLetter { limit } => {
+            for record in store.list(Some(TaskState::DeadLetter), limit)? {
+                println!(
+                    "{:>6}  {:<10}  {} attempts  {}  {}",
+                    record.task_index,
+                    record.failure_class.unwrap_or_default(),
+                    record.attempts,
+                    record.updated_at,
+                    record.error.unwrap_or_default()
+                );
+            }
+        }
+        TasksCommand::Requeue { task_indexes, all } => {
+            let task_indexes = if all {
+                store
+                    .list(Some(TaskState::DeadLetter), usize::MAX)?
+                    .into_iter()
+                    .map(|record| record.task_index)
+                    .collect()
+            } else {
+                task_indexes
+            };
+            if task_indexes.is_empty() {
+                return Err("Pass the task indexes to requeue, or --all".into());
+            }
+            let now = unix_now();
+            for task_index in task_indexes {
+                if store.requeue(task_index, now)? {
+                    println!("Requeued task {}", task_index);
+                } else {
+                    println!("Task {} is not failed or in the dead-letter list", task_index);
+                }
+            }
+        }
+    }
+    Ok(())
+}
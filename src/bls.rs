@@ -0,0 +1,536 @@
+//! BLS signatures over BN254, compatible with EigenLayer's `BN254` library.
+//!
+//! Operators sign the same response digest as the ECDSA path
+//! (`keccak256(abi.encodePacked(responseString, taskIndex))`), hashed to G1 with
+//! try-and-increment. Signatures live in G1 and public keys in both G1 and G2, so
+//! the aggregate can be checked on-chain with a single pairing.
+
+use crate::respond::{response_message_hash, Task};
+use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G1Projective, G2Affine, G2Projective};
+use ark_ec::pairing::Pairing;
+use ark_ec::{AffineRepr, CurveGroup};
+use ark_ff::{BigInteger, Field, One, PrimeField, Zero};
+use ethers::types::{Address, U256};
+use ethers::utils::{hex, keccak256};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BlsError {
+    #[error("Invalid BLS private key: {0}")]
+    InvalidKey(String),
+    #[error("Point is not on the BN254 curve")]
+    InvalidPoint,
+    #[error("{0:?} already signed a different response to this task")]
+    ConflictingResponse(Address),
+}
+
+/// G1 point in the `BN254.G1Point` layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct G1Point {
+    pub x: U256,
+    pub y: U256,
+}
+
+/// G2 point in the `BN254.G2Point` layout, where each coordinate is `[c1, c0]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct G2Point {
+    pub x: [U256; 2],
+    pub y: [U256; 2],
+}
+
+fn fq_to_u256(value: Fq) -> U256 {
+    U256::from_big_endian(&value.into_bigint().to_bytes_be())
+}
+
+fn u256_to_fq(value: U256) -> Fq {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    Fq::from_be_bytes_mod_order(&bytes)
+}
+
+impl From<G1Affine> for G1Point {
+    fn from(point: G1Affine) -> Self {
+        // The contract represents the point at infinity as (0, 0)
+        match point.xy() {
+            Some((x, y)) => Self { x: fq_to_u256(*x), y: fq_to_u256(*y) },
+            None => Self { x: U256::zero(), y: U256::zero() },
+        }
+    }
+}
+
+impl TryFrom<G1Point> for G1Affine {
+    type Error = BlsError;
+
+    fn try_from(point: G1Point) -> Result<Self, BlsError> {
+        if point.x.is_zero() && point.y.is_zero() {
+            return Ok(G1Affine::identity());
+        }
+        let affine = G1Affine::new_unchecked(u256_to_fq(point.x), u256_to_fq(point.y));
+        if !affine.is_on_curve() || !affine.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(BlsError::InvalidPoint);
+        }
+        Ok(affine)
+    }
+}
+
+impl From<G2Affine> for G2Point {
+    fn from(point: G2Affine) -> Self {
+        match point.xy() {
+            Some((x, y)) => Self {
+                x: [fq_to_u256(x.c1), fq_to_u256(x.c0)],
+                y: [fq_to_u256(y.c1), fq_to_u256(y.c0)],
+            },
+            None => Self { x: [U256::zero(); 2], y: [U256::zero(); 2] },
+        }
+    }
+}
+
+impl TryFrom<G2Point> for G2Affine {
+    type Error = BlsError;
+
+    fn try_from(point: G2Point) -> Result<Self, BlsError> {
+        if point.x.iter().chain(point.y.iter()).all(|c| c.is_zero()) {
+            return Ok(G2Affine::identity());
+        }
+        let x = Fq2::new(u256_to_fq(point.x[1]), u256_to_fq(point.x[0]));
+        let y = Fq2::new(u256_to_fq(point.y[1]), u256_to_fq(point.y[0]));
+        let affine = G2Affine::new_unchecked(x, y);
+        if !affine.is_on_curve() || !affine.is_in_correct_subgroup_assuming_on_curve() {
+            return Err(BlsError::InvalidPoint);
+        }
+        Ok(affine)
+    }
+}
+
+impl G1Point {
+    pub fn into_tokens(self) -> (U256, U256) {
+        (self.x, self.y)
+    }
+}
+
+impl G2Point {
+    pub fn into_tokens(self) -> ([U256; 2], [U256; 2]) {
+        (self.x, self.y)
+    }
+}
+
+/// Maps a digest to G1 the way `BN254.hashToG1` does: starting from the digest
+/// as x, increment until x^3 + 3 has the square root beta^((p + 1) / 4).
+pub fn hash_to_g1(digest: [u8; 32]) -> G1Affine {
+    let mut exponent = Fq::MODULUS;
+    exponent.add_with_carry(&1u64.into());
+    exponent.div2();
+    exponent.div2();
+
+    let mut x = Fq::from_be_bytes_mod_order(&digest);
+    loop {
+        let beta = x * x * x + Fq::from(3u64);
+        let y = beta.pow(exponent);
+        if y * y == beta {
+            return G1Affine::new_unchecked(x, y);
+        }
+        x += Fq::one();
+    }
+}
+
+/// A BN254 key pair used for BLS response signing
+pub struct BlsKeyPair {
+    secret: Fr,
+    public_g1: G1Affine,
+    public_g2: G2Affine,
+}
+
+impl BlsKeyPair {
+    fn from_secret(secret: Fr) -> Result<Self, BlsError> {
+        if secret.is_zero() {
+            return Err(BlsError::InvalidKey("key must not be zero".to_string()));
+        }
+        Ok(Self {
+            secret,
+            public_g1: (G1Affine::generator() * secret).into_affine(),
+            public_g2: (G2Affine::generator() * secret).into_affine(),
+        })
+    }
+
+    /// Parses a hex encoded 32 byte secret, reduced modulo the group order
+    pub fn from_hex(secret: &str) -> Result<Self, BlsError> {
+        let bytes = hex::decode(secret.trim_start_matches("0x"))
+            .map_err(|e| BlsError::InvalidKey(e.to_string()))?;
+        if bytes.len() != 32 {
+            return Err(BlsError::InvalidKey(format!("expected 32 bytes, got {}", bytes.len())));
+        }
+        Self::from_secret(Fr::from_be_bytes_mod_order(&bytes))
+    }
+
+    pub fn random() -> Self {
+        loop {
+            let bytes: [u8; 32] = rand::random();
+            if let Ok(key) = Self::from_secret(Fr::from_be_bytes_mod_order(&bytes)) {
+                return key;
+            }
+        }
+    }
+
+    pub fn secret_hex(&self) -> String {
+        format!("0x{}", hex::encode(self.secret.into_bigint().to_bytes_be()))
+    }
+
+    pub fn public_g1(&self) -> G1Point {
+        self.public_g1.into()
+    }
+
+    pub fn public_g2(&self) -> G2Point {
+        self.public_g2.into()
+    }
+
+    pub fn sign(&self, digest: [u8; 32]) -> G1Point {
+        (hash_to_g1(digest) * self.secret).into_affine().into()
+    }
+
+    /// Proof of possession passed to `registerBlsPublicKey` by `operator`
+    pub fn sign_registration(&self, operator: Address) -> G1Point {
+        self.sign(registration_message_hash(operator, self.public_g1()))
+    }
+}
+
+/// `blsRegistrationMessageHash`: keccak256(abi.encodePacked(operator, pubkeyG1.X, pubkeyG1.Y))
+pub fn registration_message_hash(operator: Address, public_g1: G1Point) -> [u8; 32] {
+    let mut message = Vec::with_capacity(84);
+    message.extend_from_slice(operator.as_bytes());
+    for coordinate in [public_g1.x, public_g1.y] {
+        let mut bytes = [0u8; 32];
+        coordinate.to_big_endian(&mut bytes);
+        message.extend_from_slice(&bytes);
+    }
+    keccak256(message)
+}
+
+/// Checks e(signature, g2) == e(H(digest), public_key)
+pub fn verify(digest: [u8; 32], signature: G1Point, public_key: G2Point) -> Result<bool, BlsError> {
+    let signature = G1Affine::try_from(signature)?;
+    let public_key = G2Affine::try_from(public_key)?;
+    Ok(Bn254::pairing(signature, G2Affine::generator()) == Bn254::pairing(hash_to_g1(digest), public_key))
+}
+
+/// The check `respondToTaskWithBLS` makes, as in `BLSSignatureChecker`: with
+/// gamma = keccak256(digest, apk, apkG2, sigma) mod r, checks
+/// e(sigma + gamma * apk, -g2) * e(H(digest) + gamma * g1, apkG2) == 1
+pub fn verify_aggregate(digest: [u8; 32], apk: G1Point, apk_g2: G2Point, sigma: G1Point) -> Result<bool, BlsError> {
+    let mut packed = digest.to_vec();
+    let coordinates = [apk.x, apk.y, apk_g2.x[0], apk_g2.x[1], apk_g2.y[0], apk_g2.y[1], sigma.x, sigma.y];
+    for coordinate in coordinates {
+        let mut bytes = [0u8; 32];
+        coordinate.to_big_endian(&mut bytes);
+        packed.extend_from_slice(&bytes);
+    }
+    let gamma = Fr::from_be_bytes_mod_order(&keccak256(packed));
+
+    let apk = G1Affine::try_from(apk)?;
+    let apk_g2 = G2Affine::try_from(apk_g2)?;
+    let sigma = G1Affine::try_from(sigma)?;
+    let left = (sigma + apk * gamma).into_affine();
+    let right = (hash_to_g1(digest) + G1Affine::generator() * gamma).into_affine();
+    Ok(Bn254::pairing(left, -G2Affine::generator()) + Bn254::pairing(right, apk_g2) == Zero::zero())
+}
+
+pub fn aggregate_signatures(signatures: &[G1Point]) -> Result<G1Point, BlsError> {
+    let mut sum = G1Projective::zero();
+    for signature in signatures {
+        sum += G1Affine::try_from(*signature)?;
+    }
+    Ok(sum.into_affine().into())
+}
+
+pub fn aggregate_public_keys(public_keys: &[G2Point]) -> Result<G2Point, BlsError> {
+    let mut sum = G2Projective::zero();
+    for public_key in public_keys {
+        sum += G2Affine::try_from(*public_key)?;
+    }
+    Ok(sum.into_affine().into())
+}
+
+/// A response signed off-chain with the operator's BLS key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlsSignedResponse {
+    pub task: Task,
+    pub response_string: String,
+    pub operator: Address,
+    pub signature: G1Point,
+}
+
+impl BlsSignedResponse {
+    pub fn new(key: &BlsKeyPair, operator: Address, task: Task, response_string: String) -> Self {
+        let digest = response_message_hash(&response_string, task.request_id.into());
+        Self {
+            signature: key.sign(digest),
+            task,
+            response_string,
+            operator,
+        }
+    }
+
+    pub fn digest(&self) -> [u8; 32] {
+        response_message_hash(&self.response_string, self.task.request_id.into())
+    }
+}
+
+/// Everything `respondToTaskWithBLS` needs for a single submission
+#[derive(Debug, Clone)]
+pub struct AggregatedResponse {
+    pub task: Task,
+    pub response_string: String,
+    /// Bit i is set when the operator at `blsOperators[i]` signed
+    pub signer_bitmap: U256,
+    pub signers: Vec<Address>,
+    pub apk_g2: G2Point,
+    pub sigma: G1Point,
+}
+
+struct SignerShare {
+    operator_index: u8,
+    public_key: G2Point,
+    signature: G1Point,
+}
+
+/// Verified signatures for one task, grouped by the response they sign
+#[derive(Default)]
+pub struct BlsTaskAggregation {
+    task: Option<Task>,
+    responses: BTreeMap<String, BTreeMap<Address, SignerShare>>,
+}
+
+impl BlsTaskAggregation {
+    /// Adds an already verified share and returns how many operators signed this
+    /// response. An operator only counts toward one response per task.
+    pub fn add(
+        &mut self,
+        response: &BlsSignedResponse,
+        operator_index: u8,
+        public_key: G2Point,
+    ) -> Result<usize, BlsError> {
+        let conflicting = self
+            .responses
+            .iter()
+            .any(|(response_string, signers)| *response_string != response.response_string && signers.contains_key(&response.operator));
+        if conflicting {
+            return Err(BlsError::ConflictingResponse(response.operator));
+        }
+        self.task.get_or_insert_with(|| response.task.clone());
+        let signers = self.responses.entry(response.response_string.clone()).or_default();
+        signers.insert(
+            response.operator,
+            SignerShare {
+                operator_index,
+                public_key,
+                signature: response.signature,
+            },
+        );
+        Ok(signers.len())
+    }
+
+    /// Operators that signed `response_string`
+    pub fn signers(&self, response_string: &str) -> Vec<Address> {
+        self.responses.get(response_string).map_or_else(Vec::new, |signers| signers.keys().copied().collect())
+    }
+
+    /// Drops the shares of `operators`, e.g. those that responded on their own
+    /// and can't be part of an aggregated response anymore
+    pub fn remove(&mut self, operators: &[Address]) {
+        for signers in self.responses.values_mut() {
+            signers.retain(|operator, _| !operators.contains(operator));
+        }
+    }
+
+    pub fn aggregate(&self, response_string: &str) -> Result<Option<AggregatedResponse>, BlsError> {
+        let (Some(task), Some(signers)) = (&self.task, self.responses.get(response_string)) else {
+            return Ok(None);
+        };
+
+        let mut signer_bitmap = U256::zero();
+        for share in signers.values() {
+            signer_bitmap |= U256::one() << share.operator_index;
+        }
+        let public_keys: Vec<G2Point> = signers.values().map(|share| share.public_key).collect();
+        let signatures: Vec<G1Point> = signers.values().map(|share| share.signature).collect();
+
+        Ok(Some(AggregatedResponse {
+            task: task.clone(),
+            response_string: response_string.to_string(),
+            signer_bitmap,
+            signers: signers.keys().copied().collect(),
+            apk_g2: aggregate_public_keys(&public_keys)?,
+            sigma: aggregate_signatures(&signatures)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u256(decimal: &str) -> U256 {
+        U256::from_dec_str(decimal).unwrap()
+    }
+
+    fn digest(hex_digest: &str) -> [u8; 32] {
+        hex::decode(hex_digest).unwrap().try_into().unwrap()
+    }
+
+    fn sum_g1(points: &[G1Point]) -> G1Point {
+        let mut sum = G1Projective::zero();
+        for point in points {
+            sum += G1Affine::try_from(*point).unwrap();
+        }
+        sum.into_affine().into()
+    }
+
+    /// Points from `BN254.hashToG1`, worked out by following its loop with the
+    /// contract's `findYFromX` exponent
+    #[test]
+    fn hash_to_g1_matches_bn254_sol() {
+        let cases = [
+            // The response digest of ("42", 7), on the curve at the first try
+            (
+                "d462eef05cab6ad06d86815cdcd462026bed95d98b095e26f92f7f2329e8dc6f",
+                "8512152520420639177881372748025410076540222426574808215059201841074917402451",
+                "9203042244917113317089571395395194511792133536092816829597312008001410774637",
+            ),
+            // Two increments before x^3 + 3 is a square
+            (
+                "3429fcd4c14bff5164f8e0570a984d57fe2abab6be8620e0e34952a784b215b5",
+                "1706210960091111171293102546707261558207884314227261825733133509773848025200",
+                "17893351125564394712449273527878938206585891528111512043081972502516465411052",
+            ),
+            // Reduced modulo the field order first
+            (
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "6350874878119819312338956282401532409788428879151445726012394534686998597020",
+                "9382425333525343773979589293970912874995880615911074345993039062358358671562",
+            ),
+            // 0 isn't on the curve, 1 is the generator
+            ("0000000000000000000000000000000000000000000000000000000000000000", "1", "2"),
+        ];
+        for (input, x, y) in cases {
+            assert_eq!(G1Point::from(hash_to_g1(digest(input))), G1Point { x: u256(x), y: u256(y) }, "{}", input);
+        }
+    }
+
+    /// The digest `respondToTaskWithBLS` hashes to G1, with the task index packed as a uint256
+    #[test]
+    fn response_digest_matches_contract() {
+        assert_eq!(
+            response_message_hash("42", 7u32.into()),
+            digest("d462eef05cab6ad06d86815cdcd462026bed95d98b095e26f92f7f2329e8dc6f")
+        );
+    }
+
+    /// `BN254.generatorG2()` is `([G2x1, G2x0], [G2y1, G2y0])`
+    #[test]
+    fn g2_coordinates_are_c1_c0() {
+        let generator = G2Point::from(G2Affine::generator());
+        assert_eq!(
+            generator,
+            G2Point {
+                x: [
+                    u256("11559732032986387107991004021392285783925812861821192530917403151452391805634"),
+                    u256("10857046999023057135944570762232829481370756359578518086990519993285655852781"),
+                ],
+                y: [
+                    u256("4082367875863433681332203403145435568316851327593401208105741076214120093531"),
+                    u256("8495653923123431417604973247489272438418190587263600148770280649306958101930"),
+                ],
+            }
+        );
+        assert_eq!(G2Affine::try_from(generator).unwrap(), G2Affine::generator());
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let key = BlsKeyPair::random();
+        let message = response_message_hash("42", 7u32.into());
+        let signature = key.sign(message);
+        assert!(verify(message, signature, key.public_g2()).unwrap());
+        assert!(!verify(response_message_hash("43", 7u32.into()), signature, key.public_g2()).unwrap());
+        assert!(!verify(message, signature, BlsKeyPair::random().public_g2()).unwrap());
+
+        let restored = BlsKeyPair::from_hex(&key.secret_hex()).unwrap();
+        assert_eq!(restored.public_g2(), key.public_g2());
+        assert!(BlsKeyPair::from_hex(&format!("0x{}", "00".repeat(32))).is_err());
+    }
+
+    #[test]
+    fn registration_proof_binds_operator() {
+        let key = BlsKeyPair::random();
+        let operator = Address::from_low_u64_be(1);
+        let proof = key.sign_registration(operator);
+        let message = registration_message_hash(operator, key.public_g1());
+        assert!(verify(message, proof, key.public_g2()).unwrap());
+        let other = registration_message_hash(Address::from_low_u64_be(2), key.public_g1());
+        assert!(!verify(other, proof, key.public_g2()).unwrap());
+    }
+
+    #[test]
+    fn aggregate_verifies_like_the_contract() {
+        let keys: Vec<BlsKeyPair> = (0..3).map(|_| BlsKeyPair::random()).collect();
+        let message = response_message_hash("42", 7u32.into());
+        let signatures: Vec<G1Point> = keys.iter().map(|key| key.sign(message)).collect();
+        let apk = sum_g1(&keys.iter().map(BlsKeyPair::public_g1).collect::<Vec<_>>());
+        let apk_g2 = aggregate_public_keys(&keys.iter().map(BlsKeyPair::public_g2).collect::<Vec<_>>()).unwrap();
+        let sigma = aggregate_signatures(&signatures).unwrap();
+        assert!(verify(message, sigma, apk_g2).unwrap());
+        assert!(verify_aggregate(message, apk, apk_g2, sigma).unwrap());
+
+        // A signer missing from sigma but counted in the keys
+        let partial = aggregate_signatures(&signatures[..2]).unwrap();
+        assert!(!verify_aggregate(message, apk, apk_g2, partial).unwrap());
+        assert!(!verify_aggregate(response_message_hash("43", 7u32.into()), apk, apk_g2, sigma).unwrap());
+    }
+
+    fn task() -> Task {
+        Task {
+            code_arweave_uri: "code".to_string(),
+            language: "js".to_string(),
+            response_count: U256::from(2),
+            task_created_block: 1,
+            request_id: 7,
+        }
+    }
+
+    #[test]
+    fn aggregation_sets_signer_bits() {
+        let task = task();
+        let keys: Vec<BlsKeyPair> = (0..2).map(|_| BlsKeyPair::random()).collect();
+        let mut aggregation = BlsTaskAggregation::default();
+        for (index, key) in [3u8, 5].into_iter().zip(&keys) {
+            let operator = Address::from_low_u64_be(index.into());
+            let response = BlsSignedResponse::new(key, operator, task.clone(), "42".to_string());
+            aggregation.add(&response, index, key.public_g2()).unwrap();
+        }
+        let aggregated = aggregation.aggregate("42").unwrap().unwrap();
+        assert_eq!(aggregated.signer_bitmap, U256::from(0b101000));
+        let message = response_message_hash("42", 7u32.into());
+        assert!(verify(message, aggregated.sigma, aggregated.apk_g2).unwrap());
+        assert!(aggregation.aggregate("43").unwrap().is_none());
+    }
+
+    #[test]
+    fn an_operator_signs_one_response_per_task() {
+        let key = BlsKeyPair::random();
+        let operator = Address::from_low_u64_be(1);
+        let mut aggregation = BlsTaskAggregation::default();
+        let first = BlsSignedResponse::new(&key, operator, task(), "42".to_string());
+        assert_eq!(aggregation.add(&first, 0, key.public_g2()).unwrap(), 1);
+        // Sending the same response again still counts once
+        assert_eq!(aggregation.add(&first, 0, key.public_g2()).unwrap(), 1);
+
+        let second = BlsSignedResponse::new(&key, operator, task(), "43".to_string());
+        assert!(matches!(aggregation.add(&second, 0, key.public_g2()), Err(BlsError::ConflictingResponse(o)) if o == operator));
+        assert!(aggregation.aggregate("43").unwrap().is_none());
+        assert_eq!(aggregation.signers("42"), [operator]);
+
+        aggregation.remove(&[operator]);
+        assert!(aggregation.signers("42").is_empty());
+    }
+}
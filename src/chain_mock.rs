@@ -0,0 +1,209 @@
+//! In-process stand-in for a node running the service manager, for tests.
+//! Answers the JSON-RPC calls `ContractClient` and the aggregator make from a
+//! `ChainState` the test sets up, and mines every transaction at once.
+
+use crate::bls::G2Point;
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Json, Router};
+use ethers::abi::{Abi, Function, Token};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Block, Bytes, FeeHistory, Transaction, TransactionReceipt, H256, U256, U64};
+use ethers::utils::{keccak256, rlp};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+pub const CHAIN_ID: u64 = 31337;
+
+#[derive(Debug, Default)]
+pub struct ChainState {
+    pub operators: BTreeSet<Address>,
+    pub task_hashes: BTreeMap<u32, H256>,
+    /// What `allTaskResponses` returns, by operator and task
+    pub responses: BTreeMap<(Address, u32), Bytes>,
+    /// Operators with a BLS key, in signer bitmap order
+    pub bls_operators: Vec<(Address, G2Point)>,
+    /// Call data of every transaction sent, and whether it succeeded
+    pub transactions: Vec<(Bytes, bool)>,
+    receipts: BTreeMap<H256, bool>,
+}
+
+type Shared = Arc<Mutex<ChainState>>;
+
+pub struct MockChain {
+    url: String,
+    state: Shared,
+    handle: JoinHandle<()>,
+}
+
+impl MockChain {
+    /// Binds to an ephemeral localhost port and starts serving
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        let state = Shared::default();
+        let app = Router::new().route("/", post(rpc)).with_state(state.clone());
+        let handle = tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(Self { url, state, handle })
+    }
+
+    /// RPC URL to put in `Config::rpc_url`
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn state(&self) -> std::sync::MutexGuard<'_, ChainState> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl Drop for MockChain {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn abi() -> Abi {
+    serde_json::from_str(include_str!("../abi/abi.json")).expect("abi/abi.json is valid")
+}
+
+fn function_for(abi: &Abi, data: &[u8]) -> Result<Function, String> {
+    let selector = data.get(..4).ok_or("call data has no selector")?;
+    abi.functions()
+        .find(|function| function.short_signature() == selector)
+        .cloned()
+        .ok_or_else(|| format!("unknown selector 0x{}", ethers::utils::hex::encode(selector)))
+}
+
+async fn rpc(State(state): State<Shared>, Json(request): Json<Value>) -> Json<Value> {
+    let params = request["params"].as_array().cloned().unwrap_or_default();
+    let result = handle(&mut state.lock().unwrap(), request["method"].as_str().unwrap_or_default(), &params);
+    Json(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": request["id"], "result": result }),
+        Err(message) => json!({ "jsonrpc": "2.0", "id": request["id"], "error": { "code": 3, "message": message } }),
+    })
+}
+
+fn handle(state: &mut ChainState, method: &str, params: &[Value]) -> Result<Value, String> {
+    let hex = |value: u64| json!(U64::from(value));
+    match method {
+        "eth_chainId" => Ok(hex(CHAIN_ID)),
+        "eth_blockNumber" => Ok(hex(1)),
+        "eth_getTransactionCount" => Ok(hex(state.transactions.len() as u64)),
+        "eth_estimateGas" => Ok(hex(1_000_000)),
+        "eth_gasPrice" => Ok(hex(1_000_000_000)),
+        "eth_getBlockByNumber" => Ok(json!(Block::<H256> {
+            number: Some(U64::one()),
+            base_fee_per_gas: Some(U256::from(1_000_000_000u64)),
+            ..Block::default()
+        })),
+        "eth_feeHistory" => Ok(json!(FeeHistory {
+            base_fee_per_gas: vec![U256::from(1_000_000_000u64)],
+            gas_used_ratio: vec![0.5],
+            oldest_block: U256::one(),
+            reward: vec![vec![U256::from(1_000_000_000u64)]],
+        })),
+        "eth_call" => {
+            let call_request = &params[0];
+            let data = if call_request["input"].is_null() { &call_request["data"] } else { &call_request["input"] };
+            let data: Bytes = serde_json::from_value(data.clone()).map_err(|e| e.to_string())?;
+            Ok(json!(Bytes::from(call(state, &data)?)))
+        }
+        "eth_sendRawTransaction" => {
+            let raw: Bytes = serde_json::from_value(params[0].clone()).map_err(|e| e.to_string())?;
+            let (transaction, _) =
+                TypedTransaction::decode_signed(&rlp::Rlp::new(&raw)).map_err(|e| e.to_string())?;
+            let data = transaction.data().cloned().unwrap_or_default();
+            let succeeded = send(state, &data);
+            let hash = H256::from(keccak256(&raw));
+            state.transactions.push((data, succeeded));
+            state.receipts.insert(hash, succeeded);
+            Ok(json!(hash))
+        }
+        "eth_getTransactionByHash" => {
+            let hash: H256 = serde_json::from_value(params[0].clone()).map_err(|e| e.to_string())?;
+            Ok(match state.receipts.contains_key(&hash) {
+                true => json!(Transaction { hash, block_number: Some(U64::one()), ..Transaction::default() }),
+                false => Value::Null,
+            })
+        }
+        "eth_getTransactionReceipt" => {
+            let hash: H256 = serde_json::from_value(params[0].clone()).map_err(|e| e.to_string())?;
+            Ok(match state.receipts.get(&hash) {
+                Some(&succeeded) => json!(TransactionReceipt {
+                    transaction_hash: hash,
+                    block_number: Some(U64::one()),
+                    status: Some(U64::from(succeeded as u64)),
+                    ..TransactionReceipt::default()
+                }),
+                None => Value::Null,
+            })
+        }
+        _ => Err(format!("{} is not supported by the mock", method)),
+    }
+}
+
+/// Answers the view functions the operator and aggregator read
+fn call(state: &ChainState, data: &[u8]) -> Result<Vec<u8>, String> {
+    let function = function_for(&abi(), data)?;
+    let inputs = function.decode_input(&data[4..]).map_err(|e| e.to_string())?;
+    let address = |i: usize| inputs[i].clone().into_address().expect("an address argument");
+    let uint = |i: usize| inputs[i].clone().into_uint().expect("a uint argument");
+    let bls_position = |operator: Address| state.bls_operators.iter().position(|(o, _)| *o == operator);
+    let output = match function.name.as_str() {
+        "operatorRegistered" => Token::Bool(state.operators.contains(&address(0))),
+        "allTaskHashes" => {
+            let hash = state.task_hashes.get(&uint(0).as_u32()).copied().unwrap_or_default();
+            Token::FixedBytes(hash.as_bytes().to_vec())
+        }
+        "allTaskResponses" => {
+            let response = state.responses.get(&(address(0), uint(1).as_u32())).cloned().unwrap_or_default();
+            Token::Bytes(response.to_vec())
+        }
+        "blsOperatorIndex" => Token::Uint(U256::from(bls_position(address(0)).map_or(0, |i| i + 1))),
+        "getBlsPubkeyG2" => {
+            let position = bls_position(address(0)).ok_or("no BLS key registered")?;
+            let (x, y) = state.bls_operators[position].1.into_tokens();
+            let coordinates = |c: [U256; 2]| Token::FixedArray(c.map(Token::Uint).to_vec());
+            Token::Tuple(vec![coordinates(x), coordinates(y)])
+        }
+        name => return Err(format!("{} is not supported by the mock", name)),
+    };
+    Ok(ethers::abi::encode(&[output]))
+}
+
+/// Applies a transaction, returning whether it succeeded. Only
+/// `respondToTaskWithBLS` is modelled, with the contract's check that no
+/// signer already responded.
+fn send(state: &mut ChainState, data: &[u8]) -> bool {
+    let Ok(function) = function_for(&abi(), data) else {
+        return false;
+    };
+    if function.name != "respondToTaskWithBLS" {
+        return true;
+    }
+    let Ok(inputs) = function.decode_input(&data[4..]) else {
+        return false;
+    };
+    let task_index = inputs[1].clone().into_uint().unwrap_or_default().as_u32();
+    let bitmap = inputs[3].clone().into_uint().unwrap_or_default();
+    let signers: Vec<Address> = state
+        .bls_operators
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| bitmap.bit(*i))
+        .map(|(_, (operator, _))| *operator)
+        .collect();
+    if signers.iter().any(|signer| state.responses.contains_key(&(*signer, task_index))) {
+        return false;
+    }
+    for signer in signers {
+        state.responses.insert((signer, task_index), Bytes::from(vec![1]));
+    }
+    true
+}
@@ -1,24 +1,991 @@
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::Address;
 use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Languages the executor knows how to run
+pub const KNOWN_LANGUAGES: &[&str] = &["js", "go"];
+
+/// Config file picked up from the working directory when no path is given
+pub const DEFAULT_CONFIG_FILE: &str = "function_layer.toml";
+
+/// A key or token from the config. `Debug` doesn't print it, so logging the
+/// config can't leak it.
+#[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0.is_empty() { "\"\"" } else { "<redacted>" })
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read config file {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("Failed to parse config file {path}: {message}")]
+    Parse { path: PathBuf, message: String },
+    #[error("Unsupported config file extension for {0} (expected .toml, .yaml or .yml)")]
+    UnsupportedFormat(PathBuf),
+    #[error("Invalid value for environment variable {name}: {message}")]
+    Env { name: String, message: String },
+    #[error("Invalid configuration:\n  - {}", .0.join("\n  - "))]
+    Invalid(Vec<String>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArweaveConfig {
+    pub gateway_url: String,
+    /// Uploads go to this bundler instead of the gateway when set
+    pub bundler_url: Option<String>,
+    /// JWK keyfile used to sign uploads
+    pub wallet_path: Option<PathBuf>,
+}
+
+impl Default for ArweaveConfig {
+    fn default() -> Self {
+        Self {
+            gateway_url: "https://arweave.net".to_string(),
+            bundler_url: None,
+            wallet_path: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsEngine {
+    /// A separate `node` process, with dependencies installed through `npm`
+    #[default]
+    Node,
+    /// QuickJS embedded in the operator binary; no imports. Needs the `quickjs` feature.
+    Quickjs,
+}
+
+impl std::str::FromStr for JsEngine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "node" => Ok(JsEngine::Node),
+            "quickjs" => Ok(JsEngine::Quickjs),
+            _ => Err(format!("unknown JS engine {:?}, expected node or quickjs", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsRuntimeConfig {
+    pub engine: JsEngine,
+    pub node_path: String,
+    pub npm_path: String,
+    /// Directory under which each execution gets its own temp dir
+    pub work_dir: PathBuf,
+    /// npm's package cache, which dependencies are resolved from
+    pub npm_cache_dir: PathBuf,
+    /// Registry or local mirror to fetch packages from, instead of npm's default
+    pub npm_registry: Option<String>,
+    /// Resolve dependencies only from `npm_cache_dir`, never the network
+    pub npm_offline: bool,
+    /// Installed `node_modules` trees, one per distinct set of dependencies
+    pub modules_cache_dir: PathBuf,
+    /// How long `npm install` may take before it is killed
+    pub npm_install_timeout_secs: u64,
+    /// Run code with a fixed clock, seeded randomness and no timers or I/O,
+    /// so every operator computes the same result
+    pub deterministic: bool,
+    /// Heap limit for the quickjs engine
+    pub memory_limit_bytes: usize,
+    /// Approximate number of bytecode instructions the quickjs engine may run
+    pub max_instructions: u64,
+}
+
+impl Default for JsRuntimeConfig {
+    fn default() -> Self {
+        Self {
+            engine: JsEngine::Node,
+            node_path: "node".to_string(),
+            npm_path: "npm".to_string(),
+            work_dir: PathBuf::from("temp/js"),
+            npm_cache_dir: PathBuf::from("temp/npm-cache"),
+            npm_registry: None,
+            npm_offline: true,
+            modules_cache_dir: PathBuf::from("temp/node_modules"),
+            npm_install_timeout_secs: 300,
+            deterministic: false,
+            memory_limit_bytes: 64 * 1024 * 1024,
+            max_instructions: 1_000_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuntimesConfig {
+    pub js: JsRuntimeConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Wall clock limit for a single code run
+    pub max_code_run_duration_secs: u64,
+    /// Upper bound on captured stdout/stderr per run
+    pub max_output_bytes: usize,
+    /// How often the listener polls for new blocks
+    pub poll_interval_ms: u64,
+    /// How long shutdown waits for the task in progress before giving up on it
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_code_run_duration_secs: 10,
+            max_output_bytes: 1024 * 1024,
+            poll_interval_ms: 1000,
+            shutdown_timeout_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// Refuse to start when the RPC reports a different chain
+    pub expected_chain_id: Option<u64>,
+    /// Minimum operator balance, in ETH, below which tasks are not taken
+    pub min_balance_eth: String,
+    /// How often the listener re-runs the health check
+    pub check_interval_secs: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            expected_chain_id: None,
+            min_balance_eth: "0.01".to_string(),
+            check_interval_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AggregatorConfig {
+    /// Matching responses needed to declare a result; defaults to the task's responseCount
+    pub quorum: Option<usize>,
+    /// Block to start watching from, defaults to the current block
+    pub start_block: Option<u64>,
+    /// Address the aggregator accepts signed responses on, e.g. 0.0.0.0:8090
+    pub listen_addr: Option<String>,
+    /// Key that submits aggregated BLS responses on-chain
+    #[serde(skip_serializing)]
+    pub private_key: SecretString,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StoreConfig {
+    /// SQLite database recording every task and its state changes
+    pub path: PathBuf,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("data/tasks.db"),
+        }
+    }
+}
+
+/// How often and how fast a failed task is retried
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first; 1 disables retries
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each further attempt
+    pub initial_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+}
+
+impl RetryPolicy {
+    fn new(max_attempts: u32, initial_backoff_secs: u64) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff_secs,
+            max_backoff_secs: 600,
+        }
+    }
+
+    /// Delay before retrying after `attempts` failed attempts, or `None` once
+    /// they are used up
+    pub fn backoff(&self, attempts: u32) -> Option<std::time::Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        let secs = self.initial_backoff_secs.saturating_mul(factor).min(self.max_backoff_secs);
+        Some(std::time::Duration::from_secs(secs))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3, 10)
+    }
+}
+
+/// Retry policy per failure class
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Fetching code from the Arweave gateway
+    pub gateway: RetryPolicy,
+    /// Installing the code's npm dependencies
+    pub npm: RetryPolicy,
+    /// Reading the chain or submitting the response
+    pub rpc: RetryPolicy,
+    /// Sending the response to the aggregator
+    pub aggregator: RetryPolicy,
+    /// The code itself failing, which is unlikely to change on a retry
+    pub execution: RetryPolicy,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            gateway: RetryPolicy::new(5, 10),
+            npm: RetryPolicy::new(3, 30),
+            rpc: RetryPolicy::new(5, 5),
+            aggregator: RetryPolicy::new(5, 5),
+            execution: RetryPolicy::new(1, 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable, colored lines
+    #[default]
+    Pretty,
+    /// One JSON object per line, with the fields of the enclosing spans
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format {:?}, expected pretty or json", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// OTLP/HTTP collector to export spans to, e.g. http://localhost:4318.
+    /// Needs the `otlp` feature.
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Address `/metrics` is served on, e.g. 0.0.0.0:9100; disabled when unset
+    pub listen_addr: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Address the admin API is served on, e.g. 127.0.0.1:8091; disabled when unset
+    pub listen_addr: Option<String>,
+    /// Bearer token for the task and pause endpoints
+    #[serde(skip_serializing)]
+    pub token: SecretString,
+    /// `/ready` fails once the listener is further behind the chain head than this
+    pub max_lag_blocks: u64,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            listen_addr: None,
+            token: SecretString::default(),
+            max_lag_blocks: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GossipConfig {
+    /// Base URL of the aggregator receiving signed responses
+    pub aggregator_url: Option<String>,
+    /// Also call respondToTask when responses are sent to the aggregator
+    pub submit_on_chain: bool,
+}
+
+impl Default for GossipConfig {
+    fn default() -> Self {
+        Self {
+            aggregator_url: None,
+            submit_on_chain: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegistryConfig {
+    /// Arweave ID of the signed registry manifest that `registry://` code URIs resolve against
+    pub manifest_uri: Option<String>,
+    /// Address the manifest must be signed by
+    pub signer: Option<String>,
+    /// Where fetched manifests are kept
+    pub cache_dir: PathBuf,
+}
+
+impl Default for RegistryConfig {
+    fn default() -> Self {
+        Self {
+            manifest_uri: None,
+            signer: None,
+            cache_dir: PathBuf::from("temp/registry"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ErrorResponsesConfig {
+    /// Submit an `error:<class>:<hash>` response for tasks that fail for good,
+    /// e.g. because the code throws or times out
+    pub submit: bool,
+}
+
+impl Default for ErrorResponsesConfig {
+    fn default() -> Self {
+        Self { submit: true }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsistencyConfig {
+    /// How many times each task is run; results are only submitted when every
+    /// run gives the same response
+    pub runs: u32,
+    /// Submit a `non_deterministic` error response when runs disagree, instead
+    /// of nothing
+    pub submit_error: bool,
+}
+
+impl Default for ConsistencyConfig {
+    fn default() -> Self {
+        Self { runs: 1, submit_error: false }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReceiptsConfig {
+    /// Also upload each execution receipt to Arweave; needs `arweave.wallet_path`
+    pub upload: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlsConfig {
+    /// Send BLS signed responses to the aggregator instead of calling respondToTask
+    pub enabled: bool,
+    /// Hex encoded BN254 secret key
+    #[serde(skip_serializing)]
+    pub private_key: SecretString,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub rpc_url: String,
     pub contract_address: String,
     pub supported_languages: Vec<String>,
     pub operator_address: String,
+    #[serde(skip_serializing)]
+    pub operator_private_key: SecretString,
+    pub abi_path: PathBuf,
+    pub arweave: ArweaveConfig,
+    pub runtimes: RuntimesConfig,
+    pub limits: LimitsConfig,
+    pub health: HealthConfig,
+    pub aggregator: AggregatorConfig,
+    pub store: StoreConfig,
+    pub retry: RetryConfig,
+    pub metrics: MetricsConfig,
+    pub admin: AdminConfig,
+    pub logging: LoggingConfig,
+    pub gossip: GossipConfig,
+    pub bls: BlsConfig,
+    pub receipts: ReceiptsConfig,
+    pub consistency: ConsistencyConfig,
+    pub error_responses: ErrorResponsesConfig,
+    pub registry: RegistryConfig,
 }
 
-impl Config {
-    pub fn new(rpc_url: &str, contract_address: &str, operator_address: &str) -> Self {
+impl Default for Config {
+    fn default() -> Self {
         Self {
-            rpc_url: rpc_url.to_string(),
-            contract_address: contract_address.to_string(),
+            rpc_url: "http://localhost:3001".to_string(),
+            contract_address: String::new(),
             supported_languages: vec!["js".to_string(), "go".to_string()],
-            operator_address: operator_address.to_string(),
+            operator_address: String::new(),
+            operator_private_key: SecretString::default(),
+            abi_path: PathBuf::from("abi/abi.json"),
+            arweave: ArweaveConfig::default(),
+            runtimes: RuntimesConfig::default(),
+            limits: LimitsConfig::default(),
+            health: HealthConfig::default(),
+            aggregator: AggregatorConfig::default(),
+            store: StoreConfig::default(),
+            retry: RetryConfig::default(),
+            metrics: MetricsConfig::default(),
+            admin: AdminConfig::default(),
+            logging: LoggingConfig::default(),
+            gossip: GossipConfig::default(),
+            bls: BlsConfig::default(),
+            receipts: ReceiptsConfig::default(),
+            consistency: ConsistencyConfig::default(),
+            error_responses: ErrorResponsesConfig::default(),
+            registry: RegistryConfig::default(),
+        }
+    }
+}
+
+/// Values given on the command line, applied on top of file and env settings
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOverrides {
+    pub rpc_url: Option<String>,
+    pub contract_address: Option<String>,
+    pub operator_address: Option<String>,
+    pub arweave_gateway_url: Option<String>,
+    pub supported_languages: Option<Vec<String>>,
+    pub log_format: Option<LogFormat>,
+}
+
+impl Config {
+    /// Builds the config from defaults, then the config file, then environment
+    /// variables, then CLI overrides, and validates the result.
+    ///
+    /// When `path` is `None`, `FUNCTION_LAYER_CONFIG` or `function_layer.toml`
+    /// in the working directory is used if present.
+    pub fn load(path: Option<&Path>, overrides: &ConfigOverrides) -> Result<Self, ConfigError> {
+        let config = Self::resolve(path, overrides)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Same layering as `load`, without validation. Used by commands that only
+    /// need part of the config, e.g. running code locally without a chain.
+    pub fn resolve(path: Option<&Path>, overrides: &ConfigOverrides) -> Result<Self, ConfigError> {
+        let path = path
+            .map(Path::to_path_buf)
+            .or_else(|| env::var("FUNCTION_LAYER_CONFIG").ok().map(PathBuf::from))
+            .or_else(|| {
+                let default = PathBuf::from(DEFAULT_CONFIG_FILE);
+                default.exists().then_some(default)
+            });
+
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+        config.apply_env()?;
+        config.apply_overrides(overrides);
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let parse_error = |message: String| ConfigError::Parse {
+            path: path.to_path_buf(),
+            message,
+        };
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(&contents).map_err(|e| parse_error(e.to_string())),
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&contents).map_err(|e| parse_error(e.to_string()))
+            }
+            _ => Err(ConfigError::UnsupportedFormat(path.to_path_buf())),
+        }
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Some(value) = env_var("RPC_URL")? {
+            self.rpc_url = value;
+        }
+        if let Some(value) = env_var("EXECUTOR_CONTRACT")? {
+            self.contract_address = value;
+        }
+        if let Some(value) = env_var("OPERATOR_ADDRESS")? {
+            self.operator_address = value;
+        }
+        if let Some(value) = env_var::<String>("OPERATOR_PRIVATE_KEY")? {
+            self.operator_private_key = SecretString::new(value);
+        }
+        if let Some(value) = env_var::<String>("SUPPORTED_LANGUAGES")? {
+            self.supported_languages = value.split(',').map(|s| s.trim().to_string()).collect();
+        }
+        if let Some(value) = env_var("ABI_PATH")? {
+            self.abi_path = value;
+        }
+        if let Some(value) = env_var("ARWEAVE_GATEWAY_URL")? {
+            self.arweave.gateway_url = value;
+        }
+        if let Some(value) = env_var("ARWEAVE_BUNDLER_URL")? {
+            self.arweave.bundler_url = Some(value);
+        }
+        if let Some(value) = env_var("ARWEAVE_WALLET_PATH")? {
+            self.arweave.wallet_path = Some(value);
+        }
+        if let Some(value) = env_var("JS_ENGINE")? {
+            self.runtimes.js.engine = value;
+        }
+        if let Some(value) = env_var("NODE_BINARY")? {
+            self.runtimes.js.node_path = value;
+        }
+        if let Some(value) = env_var("NPM_BINARY")? {
+            self.runtimes.js.npm_path = value;
+        }
+        if let Some(value) = env_var("NPM_REGISTRY")? {
+            self.runtimes.js.npm_registry = Some(value);
+        }
+        if let Some(value) = env_var("NPM_OFFLINE")? {
+            self.runtimes.js.npm_offline = value;
+        }
+        if let Some(value) = env_var("JS_DETERMINISTIC")? {
+            self.runtimes.js.deterministic = value;
+        }
+        if let Some(value) = env_var("EXPECTED_CHAIN_ID")? {
+            self.health.expected_chain_id = Some(value);
+        }
+        if let Some(value) = env_var("MIN_BALANCE_ETH")? {
+            self.health.min_balance_eth = value;
+        }
+        if let Some(value) = env_var("MAX_CODE_RUN_DURATION")? {
+            self.limits.max_code_run_duration_secs = value;
+        }
+        if let Some(value) = env_var("TASK_DB_PATH")? {
+            self.store.path = value;
+        }
+        if let Some(value) = env_var("AGGREGATOR_LISTEN_ADDR")? {
+            self.aggregator.listen_addr = Some(value);
+        }
+        if let Some(value) = env_var("METRICS_LISTEN_ADDR")? {
+            self.metrics.listen_addr = Some(value);
+        }
+        if let Some(value) = env_var("LOG_FORMAT")? {
+            self.logging.format = value;
+        }
+        if let Some(value) = env_var("OTEL_EXPORTER_OTLP_ENDPOINT")? {
+            self.logging.otlp_endpoint = Some(value);
+        }
+        if let Some(value) = env_var("ADMIN_LISTEN_ADDR")? {
+            self.admin.listen_addr = Some(value);
+        }
+        if let Some(value) = env_var::<String>("ADMIN_TOKEN")? {
+            self.admin.token = SecretString::new(value);
+        }
+        if let Some(value) = env_var::<String>("AGGREGATOR_PRIVATE_KEY")? {
+            self.aggregator.private_key = SecretString::new(value);
+        }
+        if let Some(value) = env_var("BLS_ENABLED")? {
+            self.bls.enabled = value;
+        }
+        if let Some(value) = env_var::<String>("BLS_PRIVATE_KEY")? {
+            self.bls.private_key = SecretString::new(value);
+        }
+        if let Some(value) = env_var("AGGREGATOR_URL")? {
+            self.gossip.aggregator_url = Some(value);
+        }
+        if let Some(value) = env_var("REGISTRY_MANIFEST_URI")? {
+            self.registry.manifest_uri = Some(value);
+        }
+        if let Some(value) = env_var("REGISTRY_SIGNER")? {
+            self.registry.signer = Some(value);
+        }
+        if let Some(value) = env_var("ERROR_RESPONSES_SUBMIT")? {
+            self.error_responses.submit = value;
+        }
+        if let Some(value) = env_var("CONSISTENCY_RUNS")? {
+            self.consistency.runs = value;
+        }
+        if let Some(value) = env_var("RECEIPTS_UPLOAD")? {
+            self.receipts.upload = value;
+        }
+        if let Some(value) = env_var("GOSSIP_SUBMIT_ON_CHAIN")? {
+            self.gossip.submit_on_chain = value;
+        }
+        Ok(())
+    }
+
+    fn apply_overrides(&mut self, overrides: &ConfigOverrides) {
+        if let Some(value) = &overrides.rpc_url {
+            self.rpc_url = value.clone();
+        }
+        if let Some(value) = &overrides.contract_address {
+            self.contract_address = value.clone();
+        }
+        if let Some(value) = &overrides.operator_address {
+            self.operator_address = value.clone();
+        }
+        if let Some(value) = &overrides.arweave_gateway_url {
+            self.arweave.gateway_url = value.clone();
+        }
+        if let Some(value) = &overrides.supported_languages {
+            self.supported_languages = value.clone();
+        }
+        if let Some(value) = overrides.log_format {
+            self.logging.format = value;
+        }
+    }
+
+    /// Checks every setting and reports all problems at once
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut errors = Vec::new();
+
+        check_url(&mut errors, "rpc_url", &self.rpc_url);
+        if self.contract_address.parse::<Address>().is_err() {
+            errors.push(format!(
+                "contract_address ({:?}) is not a valid address; set it in the config file or EXECUTOR_CONTRACT",
+                self.contract_address
+            ));
+        }
+        let operator_address = self.operator_address.parse::<Address>();
+        if operator_address.is_err() {
+            errors.push(format!(
+                "operator_address ({:?}) is not a valid address; set it in the config file or OPERATOR_ADDRESS",
+                self.operator_address
+            ));
+        }
+        match self.operator_private_key.expose_secret().parse::<LocalWallet>() {
+            Ok(wallet) => {
+                if let Ok(address) = operator_address {
+                    if wallet.address() != address {
+                        errors.push(format!(
+                            "operator_address {:?} does not match the address of the operator private key {:?}",
+                            address,
+                            wallet.address()
+                        ));
+                    }
+                }
+            }
+            Err(_) => errors.push(
+                "operator_private_key is missing or not a valid hex key; set OPERATOR_PRIVATE_KEY".to_string(),
+            ),
+        }
+
+        if self.supported_languages.is_empty() {
+            errors.push("supported_languages must not be empty".to_string());
+        }
+        for lang in &self.supported_languages {
+            if !KNOWN_LANGUAGES.contains(&lang.as_str()) {
+                errors.push(format!(
+                    "supported_languages contains unknown language {:?} (known: {})",
+                    lang,
+                    KNOWN_LANGUAGES.join(", ")
+                ));
+            }
+        }
+
+        if !self.abi_path.is_file() {
+            errors.push(format!("abi_path {} does not exist", self.abi_path.display()));
+        }
+
+        check_url(&mut errors, "arweave.gateway_url", &self.arweave.gateway_url);
+        if let Some(bundler_url) = &self.arweave.bundler_url {
+            check_url(&mut errors, "arweave.bundler_url", bundler_url);
+        }
+        if let Some(wallet_path) = &self.arweave.wallet_path {
+            if !wallet_path.is_file() {
+                errors.push(format!(
+                    "arweave.wallet_path {} does not exist",
+                    wallet_path.display()
+                ));
+            }
+        }
+
+        if self.runtimes.js.node_path.is_empty() {
+            errors.push("runtimes.js.node_path must not be empty".to_string());
+        }
+        if self.runtimes.js.npm_path.is_empty() {
+            errors.push("runtimes.js.npm_path must not be empty".to_string());
+        }
+        if self.runtimes.js.npm_install_timeout_secs == 0 {
+            errors.push("runtimes.js.npm_install_timeout_secs must be greater than 0".to_string());
+        }
+        if let Some(registry) = &self.runtimes.js.npm_registry {
+            check_url(&mut errors, "runtimes.js.npm_registry", registry);
+        }
+        if self.runtimes.js.engine == JsEngine::Quickjs {
+            if cfg!(not(feature = "quickjs")) {
+                errors.push("runtimes.js.engine is quickjs but this binary was built without the quickjs feature".to_string());
+            }
+            if self.runtimes.js.memory_limit_bytes == 0 {
+                errors.push("runtimes.js.memory_limit_bytes must be greater than 0".to_string());
+            }
+            if self.runtimes.js.max_instructions == 0 {
+                errors.push("runtimes.js.max_instructions must be greater than 0".to_string());
+            }
+        }
+
+        if self.limits.max_code_run_duration_secs == 0 {
+            errors.push("limits.max_code_run_duration_secs must be greater than 0".to_string());
+        }
+        if self.limits.max_output_bytes == 0 {
+            errors.push("limits.max_output_bytes must be greater than 0".to_string());
+        }
+        if self.limits.poll_interval_ms == 0 {
+            errors.push("limits.poll_interval_ms must be greater than 0".to_string());
+        }
+
+        if ethers::utils::parse_ether(&self.health.min_balance_eth).is_err() {
+            errors.push(format!(
+                "health.min_balance_eth ({:?}) is not a valid ETH amount",
+                self.health.min_balance_eth
+            ));
+        }
+        if self.aggregator.quorum == Some(0) {
+            errors.push("aggregator.quorum must be greater than 0".to_string());
+        }
+        if self.health.check_interval_secs == 0 {
+            errors.push("health.check_interval_secs must be greater than 0".to_string());
+        }
+        if self.store.path.as_os_str().is_empty() {
+            errors.push("store.path must not be empty".to_string());
+        }
+        for (class, policy) in [
+            ("gateway", &self.retry.gateway),
+            ("npm", &self.retry.npm),
+            ("rpc", &self.retry.rpc),
+            ("aggregator", &self.retry.aggregator),
+            ("execution", &self.retry.execution),
+        ] {
+            if policy.max_attempts == 0 {
+                errors.push(format!("retry.{}.max_attempts must be at least 1", class));
+            }
+        }
+        if let Some(listen_addr) = &self.aggregator.listen_addr {
+            if listen_addr.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(format!("aggregator.listen_addr ({:?}) is not a valid socket address", listen_addr));
+            }
+        }
+        if let Some(listen_addr) = &self.metrics.listen_addr {
+            if listen_addr.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(format!("metrics.listen_addr ({:?}) is not a valid socket address", listen_addr));
+            }
+        }
+        if let Some(listen_addr) = &self.admin.listen_addr {
+            if listen_addr.parse::<std::net::SocketAddr>().is_err() {
+                errors.push(format!("admin.listen_addr ({:?}) is not a valid socket address", listen_addr));
+            }
+            if self.admin.token.is_empty() {
+                errors.push("admin.token is missing; set ADMIN_TOKEN to serve the admin API".to_string());
+            }
+        }
+        if let Some(url) = &self.logging.otlp_endpoint {
+            check_url(&mut errors, "logging.otlp_endpoint", url);
+        }
+
+        match &self.gossip.aggregator_url {
+            Some(url) => check_url(&mut errors, "gossip.aggregator_url", url),
+            None if !self.gossip.submit_on_chain => errors.push(
+                "gossip.aggregator_url must be set when gossip.submit_on_chain is false".to_string(),
+            ),
+            None => {}
+        }
+        if self.bls.enabled {
+            if crate::bls::BlsKeyPair::from_hex(self.bls.private_key.expose_secret()).is_err() {
+                errors.push("bls.private_key is missing or not a valid 32 byte hex key; set BLS_PRIVATE_KEY".to_string());
+            }
+            if self.gossip.aggregator_url.is_none() {
+                errors.push("gossip.aggregator_url must be set when bls.enabled is true".to_string());
+            }
+        }
+
+        if self.registry.manifest_uri.is_some() {
+            match &self.registry.signer {
+                Some(signer) if signer.parse::<Address>().is_err() => {
+                    errors.push(format!("registry.signer ({:?}) is not a valid address", signer));
+                }
+                Some(_) => {}
+                None => errors.push("registry.signer must be set when registry.manifest_uri is".to_string()),
+            }
+        }
+        if self.consistency.runs == 0 {
+            errors.push("consistency.runs must be at least 1".to_string());
+        }
+        if self.receipts.upload && self.arweave.wallet_path.is_none() {
+            errors.push("arweave.wallet_path must be set when receipts.upload is true".to_string());
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(errors))
         }
     }
 
     pub fn is_language_supported(&self, lang: &str) -> bool {
         self.supported_languages.contains(&lang.to_string())
     }
-} 
\ No newline at end of file
+}
+
+/// Reads `name` from the environment, if it is set and not empty
+fn env_var<T: FromStr>(name: &str) -> Result<Option<T>, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    match env::var(name).ok().filter(|value| !value.is_empty()) {
+        Some(value) => value.parse().map(Some).map_err(|e: T::Err| ConfigError::Env {
+            name: name.to_string(),
+            message: e.to_string(),
+        }),
+        None => Ok(None),
+    }
+}
+
+fn check_url(errors: &mut Vec<String>, field: &str, value: &str) {
+    match reqwest::Url::parse(value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+        Ok(url) => errors.push(format!(
+            "{} ({}) must use http or https, not {}",
+            field,
+            value,
+            url.scheme()
+        )),
+        Err(e) => errors.push(format!("{} ({:?}) is not a valid URL: {}", field, value, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_redacts_secrets() {
+        let key = "0x59c6995e998f97a5a0044966f0945389dc9e86dae88c7a8412f4603b6b78690d";
+        let mut config = Config {
+            operator_private_key: SecretString::new(key.to_string()),
+            ..Config::default()
+        };
+        config.aggregator.private_key = SecretString::new(key.to_string());
+        config.bls.private_key = SecretString::new(key.to_string());
+        config.admin.token = SecretString::new("admin-token".to_string());
+
+        let debug = format!("{:?}", config);
+        assert!(!debug.contains(&key[2..]));
+        assert!(!debug.contains("admin-token"));
+        assert!(debug.contains("operator_private_key: <redacted>"));
+        assert_eq!(config.operator_private_key.expose_secret(), key);
+    }
+
+    /// Each layer only replaces what it sets: file, then env, then the command line
+    #[test]
+    fn file_env_and_overrides_layer() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("function_layer.toml");
+        std::fs::write(
+            &path,
+            r#"
+            rpc_url = "http://file:8545"
+            contract_address = "0x00000000000000000000000000000000000000f1"
+            operator_address = "0x00000000000000000000000000000000000000f2"
+
+            [runtimes.js]
+            npm_offline = false
+            "#,
+        )
+        .unwrap();
+        // The only test that touches these variables, so it can't race another
+        env::remove_var("RPC_URL");
+        env::set_var("EXECUTOR_CONTRACT", "0x00000000000000000000000000000000000000e1");
+        env::set_var("OPERATOR_ADDRESS", "0x00000000000000000000000000000000000000e2");
+        let overrides = ConfigOverrides {
+            operator_address: Some("0x00000000000000000000000000000000000000c2".to_string()),
+            ..ConfigOverrides::default()
+        };
+        let config = Config::resolve(Some(&path), &overrides);
+
+        env::set_var("NPM_OFFLINE", "sometimes");
+        let invalid = Config::resolve(Some(&path), &ConfigOverrides::default());
+        for name in ["EXECUTOR_CONTRACT", "OPERATOR_ADDRESS", "NPM_OFFLINE"] {
+            env::remove_var(name);
+        }
+
+        let config = config.unwrap();
+        assert_eq!(config.rpc_url, "http://file:8545");
+        assert!(!config.runtimes.js.npm_offline);
+        assert_eq!(config.contract_address, "0x00000000000000000000000000000000000000e1");
+        assert_eq!(config.operator_address, "0x00000000000000000000000000000000000000c2");
+        assert!(matches!(invalid, Err(ConfigError::Env { name, .. }) if name == "NPM_OFFLINE"));
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut config = Config::default();
+        config.metrics.listen_addr = Some("not an address".to_string());
+        config.admin.listen_addr = Some("127.0.0.1:9100".to_string());
+        config.bls.enabled = true;
+        config.registry.manifest_uri = Some("manifest".to_string());
+
+        let Err(ConfigError::Invalid(errors)) = config.validate() else {
+            panic!("the default config has no operator key, so it can't be valid");
+        };
+        for expected in [
+            "metrics.listen_addr (\"not an address\") is not a valid socket address",
+            "admin.token is missing; set ADMIN_TOKEN to serve the admin API",
+            "gossip.aggregator_url must be set when bls.enabled is true",
+            "registry.signer must be set when registry.manifest_uri is",
+            "operator_private_key is missing or not a valid hex key; set OPERATOR_PRIVATE_KEY",
+        ] {
+            assert!(errors.iter().any(|error| error == expected), "missing {:?} in {:#?}", expected, errors);
+        }
+    }
+}
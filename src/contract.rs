@@ -1,13 +1,16 @@
 use ethers::{
     prelude::*,
     providers::{Provider, Http},
-    types::{Bytes, U256},
     contract::Contract,
 };
 use serde::{Deserialize, Serialize};
-use ethers::abi::Abi;
+use ethers::abi::{Abi, Detokenize, RawLog};
+use crate::bls::{AggregatedResponse, G1Point, G2Point};
+use crate::config::Config;
+use crate::listener::{ComputeRequestCreated, TaskResponded, TaskRespondedWithBLS};
 use ethers::middleware::SignerMiddleware;
 use ethers::signers::Wallet;
+use std::collections::BTreeSet;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,7 +28,11 @@ impl ContractClient {
         abi: &str,
         private_key: &str,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let provider = Provider::<Http>::try_from(rpc_url)?;
+        let mut provider = Provider::<Http>::try_from(rpc_url)?;
+        // Anvil and local devnets mine at once, so don't wait 7s between receipt polls
+        if ethers::providers::is_local_endpoint(rpc_url) {
+            provider.set_interval(std::time::Duration::from_millis(100));
+        }
         let chain_id = provider.get_chainid().await?.as_u64();
         let wallet = private_key.parse::<LocalWallet>()?.with_chain_id(chain_id);
         
@@ -53,3 +60,261 @@ impl ContractClient {
         &self.provider  // Assuming you have a provider field in your struct
     }
 }
+
+/// On-chain view of a task, as reported by `status`
+#[derive(Debug)]
+pub struct TaskStatus {
+    pub task_hash: H256,
+    pub submission_count: U256,
+    pub responded: bool,
+    pub latest_task_num: u32,
+}
+
+impl ContractClient {
+    pub async fn from_config(config: &Config) -> eyre::Result<Self> {
+        Self::from_config_with_key(config, config.operator_private_key.expose_secret()).await
+    }
+
+    /// Same as `from_config` but sends transactions from another key, e.g. a task creator
+    pub async fn from_config_with_key(config: &Config, private_key: &str) -> eyre::Result<Self> {
+        let abi = std::fs::read_to_string(&config.abi_path)?;
+        Self::new(&config.contract_address, &config.rpc_url, &abi, private_key)
+            .await
+            .map_err(|e| eyre::eyre!("{}", e))
+    }
+
+    pub fn signer_address(&self) -> Address {
+        self.contract.client().address()
+    }
+
+    /// Calls `createNewTask` and returns the new task index from the emitted event
+    pub async fn create_task(
+        &self,
+        code_arweave_uri: &str,
+        language: &str,
+        response_count: U256,
+    ) -> eyre::Result<(U256, TransactionReceipt)> {
+        let call = self.contract.method::<_, (String, U256, String, u32)>(
+            "createNewTask",
+            (code_arweave_uri.to_string(), language.to_string(), response_count),
+        )?;
+        let receipt = send_and_confirm(call).await?;
+
+        let task_index = receipt
+            .logs
+            .iter()
+            .find_map(|log| {
+                <ComputeRequestCreated as EthEvent>::decode_log(&RawLog::from(log.clone())).ok()
+            })
+            .map(|event| event.request_id)
+            .ok_or_else(|| eyre::eyre!("createNewTask did not emit ComputeRequestCreated"))?;
+        Ok((task_index, receipt))
+    }
+
+    pub async fn task_status(&self, task_index: U256, operator: Address, from_block: U64) -> eyre::Result<TaskStatus> {
+        let task_hash: H256 = self
+            .contract
+            .method::<_, H256>("allTaskHashes", task_index)?
+            .call()
+            .await?;
+        let submission_count = self.submission_count(task_index, from_block).await?;
+        let responded = self.has_responded(task_index, operator).await?;
+        let latest_task_num: u32 = self
+            .contract
+            .method::<_, u32>("latestTaskNum", ())?
+            .call()
+            .await?;
+
+        Ok(TaskStatus {
+            task_hash,
+            submission_count,
+            responded,
+            latest_task_num,
+        })
+    }
+
+    /// All `TaskResponded` events for a task since `from_block`
+    pub async fn task_responses(&self, task_index: U256, from_block: U64) -> eyre::Result<Vec<TaskResponded>> {
+        let filter = Filter::new()
+            .address(self.contract.address())
+            .topic0(TaskResponded::signature())
+            .topic1(H256::from_uint(&task_index))
+            .from_block(from_block);
+        let logs = self.provider.get_logs(&filter).await?;
+        logs.into_iter()
+            .map(|log| {
+                <TaskResponded as EthEvent>::decode_log(&RawLog::from(log))
+                    .map_err(|e| eyre::eyre!("Failed to decode TaskResponded: {}", e))
+            })
+            .collect()
+    }
+
+    /// Waits for a sent transaction to be mined and checks it succeeded
+    pub async fn wait_for_receipt(&self, tx_hash: H256) -> eyre::Result<TransactionReceipt> {
+        let receipt = self.wait_for_mined(tx_hash).await?;
+        if receipt.status != Some(U64::one()) {
+            return Err(eyre::eyre!("Transaction {:?} reverted", tx_hash));
+        }
+        Ok(receipt)
+    }
+
+    /// Unix timestamp, in seconds, of a block
+    pub async fn block_timestamp(&self, block: u64) -> eyre::Result<u64> {
+        let block = self
+            .provider
+            .get_block(block)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Block {} not found", block))?;
+        Ok(block.timestamp.as_u64())
+    }
+
+    /// Waits for the transaction to be mined, whether or not it reverted
+    pub async fn wait_for_mined(&self, tx_hash: H256) -> eyre::Result<TransactionReceipt> {
+        PendingTransaction::new(tx_hash, &self.provider)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Transaction {:?} was dropped from the mempool", tx_hash))
+    }
+
+    /// Whether `operator` already has a response stored for the task
+    pub async fn has_responded(&self, task_index: U256, operator: Address) -> eyre::Result<bool> {
+        let response: Bytes = self
+            .contract
+            .method::<_, Bytes>("allTaskResponses", (operator, task_index))?
+            .call()
+            .await?;
+        Ok(!response.is_empty())
+    }
+
+    /// Number of operators that responded to a task. Deployments before
+    /// `taskSubmissionCount` was maintained always report 0, so the count is
+    /// also derived from the response events since `from_block` and the larger
+    /// of the two wins.
+    pub async fn submission_count(&self, task_index: U256, from_block: U64) -> eyre::Result<U256> {
+        let on_chain: U256 = self
+            .contract
+            .method::<_, U256>("getSubmissionCountByTaskIndex", task_index)?
+            .call()
+            .await?;
+
+        let filter = Filter::new()
+            .address(self.contract.address())
+            .topic0(vec![TaskResponded::signature(), TaskRespondedWithBLS::signature()])
+            .topic1(H256::from_uint(&task_index))
+            .from_block(from_block);
+        let mut responders = BTreeSet::new();
+        let mut bls_signers = U256::zero();
+        for log in self.provider.get_logs(&filter).await? {
+            let raw = RawLog::from(log);
+            if let Ok(event) = <TaskResponded as EthEvent>::decode_log(&raw) {
+                responders.insert(event.operator);
+            } else if let Ok(event) = <TaskRespondedWithBLS as EthEvent>::decode_log(&raw) {
+                // The contract rejects BLS signers that already responded
+                bls_signers += U256::from((0..256).filter(|&bit| event.signer_bitmap.bit(bit)).count());
+            }
+        }
+        let from_events = U256::from(responders.len()) + bls_signers;
+
+        Ok(on_chain.max(from_events))
+    }
+
+    pub async fn is_operator_registered(&self, operator: Address) -> eyre::Result<bool> {
+        Ok(self
+            .contract
+            .method::<_, bool>("operatorRegistered", operator)?
+            .call()
+            .await?)
+    }
+
+    /// Calls `registerOperatorToAVS` with an AVSDirectory registration signature
+    pub async fn register_operator(
+        &self,
+        operator: Address,
+        signature: Bytes,
+        salt: [u8; 32],
+        expiry: U256,
+    ) -> eyre::Result<TransactionReceipt> {
+        let call = self.contract.method::<_, ()>(
+            "registerOperatorToAVS",
+            (operator, (signature, salt, expiry)),
+        )?;
+        send_and_confirm(call).await
+    }
+
+    pub async fn deregister_operator(&self, operator: Address) -> eyre::Result<TransactionReceipt> {
+        let call = self
+            .contract
+            .method::<_, ()>("deregisterOperatorFromAVS", operator)?;
+        send_and_confirm(call).await
+    }
+
+    /// Calls `registerBlsPublicKey` from the signer, which must be a registered operator.
+    /// `signature` is the key's proof of possession, see `BlsKeyPair::sign_registration`.
+    pub async fn register_bls_pubkey(
+        &self,
+        pubkey_g1: G1Point,
+        pubkey_g2: G2Point,
+        signature: G1Point,
+    ) -> eyre::Result<TransactionReceipt> {
+        let call = self.contract.method::<_, ()>(
+            "registerBlsPublicKey",
+            (pubkey_g1.into_tokens(), pubkey_g2.into_tokens(), signature.into_tokens()),
+        )?;
+        send_and_confirm(call).await
+    }
+
+    /// Position of the operator in the contract's signer bitmap, if it registered a BLS key
+    pub async fn bls_operator_index(&self, operator: Address) -> eyre::Result<Option<u8>> {
+        let index: U256 = self
+            .contract
+            .method::<_, U256>("blsOperatorIndex", operator)?
+            .call()
+            .await?;
+        // The contract stores the index plus one so that zero means unregistered
+        Ok((!index.is_zero()).then(|| (index - 1).as_u32() as u8))
+    }
+
+    pub async fn bls_pubkey_g2(&self, operator: Address) -> eyre::Result<G2Point> {
+        let (x, y): ([U256; 2], [U256; 2]) = self
+            .contract
+            .method::<_, ([U256; 2], [U256; 2])>("getBlsPubkeyG2", operator)?
+            .call()
+            .await?;
+        Ok(G2Point { x, y })
+    }
+
+    /// Submits an aggregated BLS response with `respondToTaskWithBLS`
+    pub async fn respond_with_bls(&self, response: AggregatedResponse) -> eyre::Result<TransactionReceipt> {
+        let task = response.task;
+        let call = self.contract.method::<_, ()>(
+            "respondToTaskWithBLS",
+            (
+                (
+                    task.code_arweave_uri,
+                    task.response_count,
+                    task.language,
+                    task.task_created_block,
+                ),
+                U256::from(task.request_id),
+                response.response_string,
+                response.signer_bitmap,
+                response.apk_g2.into_tokens(),
+                response.sigma.into_tokens(),
+            ),
+        )?;
+        send_and_confirm(call).await
+    }
+}
+
+async fn send_and_confirm<D: Detokenize>(
+    call: ContractCall<SignerMiddleware<Provider<Http>, Wallet<k256::ecdsa::SigningKey>>, D>,
+) -> eyre::Result<TransactionReceipt> {
+    let pending = call.send().await?;
+    let tx_hash = pending.tx_hash();
+    let receipt = pending
+        .await?
+        .ok_or_else(|| eyre::eyre!("Transaction {:?} was dropped from the mempool", tx_hash))?;
+    if receipt.status != Some(U64::one()) {
+        return Err(eyre::eyre!("Transaction {:?} reverted", tx_hash));
+    }
+    Ok(receipt)
+}
@@ -0,0 +1,98 @@
+//! Runs a code module the way an operator would for a task, without a chain:
+//! the same runtime, limits, deterministic inputs and consistency runs, ending
+//! in the response string and the message the operator would sign.
+
+use crate::config::Config;
+use crate::executor::{check_output, Execution, Executor, FetchedCode, UnsupportedLanguage};
+use crate::js_executor::Determinism;
+use crate::receipt::ExecutionReceipt;
+use crate::respond::{receipt_message_hash, Task};
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{Address, H256, U256};
+use eyre::Result;
+
+/// The task a dry run pretends to answer. Only matters to code that reads the
+/// clock or randomness in deterministic mode, and to the receipt.
+#[derive(Debug, Clone)]
+pub struct DryRunInputs {
+    pub task_index: u32,
+    /// Arweave ID the code would be fetched from
+    pub code_uri: String,
+    pub task_created_block: u32,
+    /// Timestamp of `task_created_block`, the clock in deterministic mode
+    pub block_timestamp: u64,
+    pub response_count: u64,
+}
+
+impl Default for DryRunInputs {
+    fn default() -> Self {
+        Self {
+            task_index: 0,
+            code_uri: String::new(),
+            task_created_block: 0,
+            block_timestamp: 0,
+            response_count: 1,
+        }
+    }
+}
+
+impl DryRunInputs {
+    pub fn task(&self, language: &str) -> Task {
+        Task {
+            code_arweave_uri: self.code_uri.clone(),
+            language: language.to_string(),
+            response_count: U256::from(self.response_count),
+            task_created_block: self.task_created_block,
+            request_id: self.task_index,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DryRun {
+    pub task: Task,
+    pub execution: Execution,
+    /// What would be submitted as `responseString`
+    pub response_string: String,
+    pub receipt: ExecutionReceipt,
+    /// keccak256(abi.encodePacked(responseString, taskIndex, receiptHash)),
+    /// the message the operator would sign
+    pub message_hash: H256,
+}
+
+/// Fetches a code module from the configured gateway, resolving `registry://`
+/// references, as operators do for a task
+pub async fn fetch_code_module(config: &Config, code_uri: &str, language: &str) -> Result<FetchedCode> {
+    Executor::new(config.clone()).fetch_code(code_uri, language).await
+}
+
+/// Runs `code` as the answer to the task described by `inputs`
+pub async fn dry_run(config: &Config, code: &FetchedCode, language: &str, inputs: &DryRunInputs) -> Result<DryRun> {
+    if !config.is_language_supported(language) {
+        return Err(UnsupportedLanguage(language.to_string()).into());
+    }
+    let task = inputs.task(language);
+    let determinism = if config.runtimes.js.deterministic {
+        Determinism::for_task(&task, inputs.block_timestamp)
+    } else {
+        Determinism::default()
+    };
+
+    let execution = Executor::new(config.clone())
+        .run_code_consistent(language, &code.module, determinism)
+        .await?;
+    check_output(code.output, &execution.result)?;
+    let response_string = execution.result.to_response_string();
+    let receipt = ExecutionReceipt::new(&task, operator(config), code, &execution, &response_string);
+    let message_hash = H256::from(receipt_message_hash(&response_string, task.request_id.into(), receipt.hash()));
+    Ok(DryRun { task, execution, response_string, receipt, message_hash })
+}
+
+/// The configured operator, or the zero address when there is none
+fn operator(config: &Config) -> Address {
+    config
+        .operator_address
+        .parse()
+        .or_else(|_| config.operator_private_key.expose_secret().parse::<LocalWallet>().map(|wallet| wallet.address()))
+        .unwrap_or_default()
+}
@@ -0,0 +1,92 @@
+//! Response strings that report a task could not be answered, in the form
+//! `error:<class>:<hash>`, where `hash` is the first 4 bytes of the keccak256
+//! of a message describing the failure, hex encoded. Operators that fail the
+//! same way submit the same string, so failures can reach quorum like results.
+
+use ethers::utils::{hex, keccak256};
+use std::fmt;
+use std::str::FromStr;
+
+const PREFIX: &str = "error";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The code ran past its time or instruction limit
+    Timeout,
+    /// The code threw, crashed or returned something that isn't a valid result
+    RuntimeError,
+    /// No operator runs the task's language
+    UnsupportedLanguage,
+    /// The gateway has no data for the code's ID, or the data isn't JSON
+    FetchFailure,
+    /// The code module is malformed or its dependencies aren't pinned
+    InvalidCode,
+    /// Runs of the same task gave different results
+    NonDeterministic,
+}
+
+impl ErrorClass {
+    pub const ALL: [ErrorClass; 6] = [
+        ErrorClass::Timeout,
+        ErrorClass::RuntimeError,
+        ErrorClass::UnsupportedLanguage,
+        ErrorClass::FetchFailure,
+        ErrorClass::InvalidCode,
+        ErrorClass::NonDeterministic,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::Timeout => "timeout",
+            ErrorClass::RuntimeError => "runtime_error",
+            ErrorClass::UnsupportedLanguage => "unsupported_language",
+            ErrorClass::FetchFailure => "fetch_failure",
+            ErrorClass::InvalidCode => "invalid_code",
+            ErrorClass::NonDeterministic => "non_deterministic",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorResponse {
+    pub class: ErrorClass,
+    pub message_hash: [u8; 4],
+}
+
+impl ErrorResponse {
+    /// `message` should only hold what every operator sees the same way, not
+    /// local paths, limits or timings
+    pub fn new(class: ErrorClass, message: &str) -> Self {
+        let hash = keccak256(message.as_bytes());
+        Self { class, message_hash: [hash[0], hash[1], hash[2], hash[3]] }
+    }
+
+    /// The string submitted on-chain as `responseString`
+    pub fn to_response_string(&self) -> String {
+        format!("{}:{}:{}", PREFIX, self.class, hex::encode(self.message_hash))
+    }
+}
+
+impl FromStr for ErrorResponse {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{:?} is not an error response", s);
+        let mut parts = s.split(':');
+        let (Some(PREFIX), Some(class), Some(hash), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        let class = ErrorClass::ALL
+            .into_iter()
+            .find(|known| known.as_str() == class)
+            .ok_or_else(invalid)?;
+        let message_hash = hex::decode(hash).ok().and_then(|hash| hash.try_into().ok()).ok_or_else(invalid)?;
+        Ok(Self { class, message_hash })
+    }
+}
@@ -1,35 +1,98 @@
-use crate::config::Config;
-use ethers::types::U256;
+use crate::config::{Config, JsEngine, KNOWN_LANGUAGES};
+use ethers::types::{H256, U256, U64};
 use eyre::Result;
 use serde::{Deserialize, Serialize};
-use std::env;
-use crate::arweave::ArweaveClient;
-use crate::js_executor::JsExecutor;
-use std::sync::Arc;
+use thiserror::Error;
+use crate::arweave::{ArweaveClient, ArweaveError};
+use crate::arweave_tx::ArweaveWallet;
+use crate::bls::{BlsKeyPair, BlsSignedResponse};
+use crate::error_response::{ErrorClass, ErrorResponse};
+use crate::gossip::{GossipClient, SignedResponse};
+use crate::js_executor::{Determinism, DeterminismViolation, JsExecutor, NpmInstallError};
+use crate::metrics::METRICS;
+use crate::npm::{Dependencies, DependencyError};
+use crate::process;
+use crate::receipt::{ExecutionReceipt, ResourceUsage, RuntimeInfo};
+use crate::registry::{FunctionRef, OutputType, Registry, RegistryError};
+#[cfg(feature = "quickjs")]
+use crate::quickjs_executor::{LimitExceeded, QuickJsExecutor};
+use crate::retry::{unix_now, FailureClass, ResultExt, TaskError};
 use crate::contract::ContractClient;
 use crate::respond;
 use crate::respond::Task;
+use crate::task_store::{TaskState, TaskStore, TaskStoreError};
+use colored::*;
+use ethers::signers::Signer;
+use tracing::{error, info, info_span, warn, Instrument, Span};
+use std::sync::Arc;
 use ethers::utils::hex;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CodeModule {
     pub function: String,
     pub imports: String,
+    /// npm packages the imports use, pinned by version and integrity
+    #[serde(default, skip_serializing_if = "Dependencies::is_empty")]
+    pub dependencies: Dependencies,
+    /// The package-lock.json (lockfileVersion 2 or 3) that resolves
+    /// `dependencies`, transitive ones included. Required with dependencies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lockfile: Option<serde_json::Value>,
+}
+
+/// A code module along with the bytes it was parsed from
+#[derive(Debug)]
+pub struct FetchedCode {
+    /// Arweave ID the code was fetched from, with `registry://` references resolved
+    pub code_uri: String,
+    pub module: CodeModule,
+    /// What the receipt's `code_hash` covers, so anyone can check it against
+    /// the data on Arweave
+    pub bytes: Vec<u8>,
+    /// Output type the registry expects, for `registry://` code
+    pub output: Option<OutputType>,
 }
 
 pub struct Executor {
     pub config: Config,
     pub contract_address: String,
+    gossip: Option<GossipClient>,
+    store: Option<Arc<TaskStore>>,
+    registry: Registry,
 }
 
 impl Executor {
     pub fn new(config: Config) -> Self {
-        let contract_address = env::var("EXECUTOR_CONTRACT")
-            .expect("EXECUTOR_CONTRACT must be set in environment");
-        println!("Executor contract address: {}", contract_address);
+        let contract_address = config.contract_address.clone();
+        info!("Executor contract address: {}", contract_address);
+        let gossip = config.gossip.aggregator_url.as_deref().map(GossipClient::new);
+        let registry = Registry::new(&config);
         Self {
             config,
             contract_address,
+            gossip,
+            store: None,
+            registry,
+        }
+    }
+
+    /// Records every task and its state changes in `store`
+    pub fn with_store(mut self, store: Arc<TaskStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn store(&self) -> Option<&Arc<TaskStore>> {
+        self.store.as_ref()
+    }
+
+    /// Writes to the task store, if any. A failing write is logged and does not
+    /// stop the task.
+    fn record(&self, write: impl FnOnce(&TaskStore) -> Result<(), TaskStoreError>) {
+        if let Some(store) = &self.store {
+            if let Err(e) = write(store) {
+                warn!("{}", format!("Failed to update task store: {}", e).yellow());
+            }
         }
     }
 
@@ -41,11 +104,6 @@ impl Executor {
         res_count: U256,
         task_created_block: U256,
     ) -> Result<()> {
-        // Check if language is supported
-        if !self.config.is_language_supported(&code_lang) {
-            return Err(eyre::eyre!("Unsupported language: {}", code_lang));
-        }
- 
         // build the task struct
         let task = Task {
             code_arweave_uri: code_tx_id,
@@ -54,74 +112,573 @@ impl Executor {
             task_created_block: task_created_block.as_u32(),
             request_id: request_id.as_u32(),
         };
-        // Check current submission count from contract using request_id
-        let current_count = self.get_submission_count(request_id).await?;
-        if current_count >= res_count.as_u64() {
+        self.run_task(task).await
+    }
+
+    /// Runs a task end to end. A failure is recorded in the task store and
+    /// scheduled for retry according to the policy for its failure class.
+    #[tracing::instrument(
+        name = "task",
+        skip_all,
+        fields(
+            task_index = task.request_id,
+            language = %task.language,
+            code_uri = %task.code_arweave_uri,
+            tx_hash = tracing::field::Empty,
+        )
+    )]
+    pub async fn run_task(&self, task: Task) -> Result<()> {
+        // A task seen again after it was settled, e.g. on replay, keeps its record
+        if let Some(store) = &self.store {
+            match store.record_discovered(&task) {
+                Ok(true) => {}
+                Ok(false) => {
+                    info!("Task {}: already settled, leaving it as recorded", task.request_id);
+                    return Ok(());
+                }
+                Err(e) => warn!("{}", format!("Failed to update task store: {}", e).yellow()),
+            }
+        }
+
+        // Check if language is supported. Languages no operator runs get an
+        // error response; ones other operators may run are left to them.
+        let known = KNOWN_LANGUAGES.contains(&task.language.as_str());
+        if !self.config.is_language_supported(&task.language) && (known || !self.config.error_responses.submit) {
+            self.record(|store| store.set_state(task.request_id, TaskState::Skipped, Some("unsupported language")));
+            return Err(eyre::eyre!("Unsupported language: {}", task.language));
+        }
+
+        let Err(e) = self.execute_task(&task).await else {
             return Ok(());
+        };
+        let class = FailureClass::of(&e);
+        let attempts = match &self.store {
+            Some(store) => store.get(task.request_id).ok().flatten().map_or(0, |record| record.attempts) + 1,
+            None => 1,
+        };
+        let backoff = self.config.retry.policy(class).backoff(attempts);
+
+        // Out of retries: tell the requester why instead of going quiet
+        if backoff.is_none() && self.config.error_responses.submit {
+            if let Some(response) = error_response(&task, &e) {
+                match self.submit_error_response(&task, &e, response).await {
+                    Ok(()) => return Ok(()),
+                    Err(submit_error) => warn!("Task {}: failed to submit the error response: {:#}", task.request_id, submit_error),
+                }
+            }
         }
-        // get the code json from arweave
-        let arweave_client = ArweaveClient::new(None);
-        let code_json = arweave_client.get_transaction_data_json(&task.code_arweave_uri).await?;
-        // println!("Code JSON: {:?}", code_json);
-        
 
-        // return Ok(());
+        self.record(|store| {
+            match backoff {
+                Some(delay) => warn!(
+                    "{}",
+                    format!("Task {}: {} failure (attempt {}), retrying in {}s", task.request_id, class, attempts, delay.as_secs()).yellow()
+                ),
+                None => error!(
+                    "{}",
+                    format!("Task {}: {} failure (attempt {}), moved to the dead-letter list", task.request_id, class, attempts).red()
+                ),
+            }
+            let next_retry_at = backoff.map(|delay| unix_now() + delay.as_secs() as i64);
+            store.set_failed(task.request_id, &format!("{:#}", e), class.as_str(), attempts, next_retry_at)
+        });
+        Err(e)
+    }
 
-        // Parse code module from the retrieved JSON
-        let code_module: CodeModule = serde_json::from_value(code_json)?;
-        // println!("Code module: {:?}", code_module);
+    /// Signs and submits `response` for a task that failed with `error`
+    async fn submit_error_response(&self, task: &Task, error: &eyre::Report, response: ErrorResponse) -> Result<()> {
+        let response_string = response.to_response_string();
+        warn!("{}", format!("Task {}: {:#}, submitting {}", task.request_id, error, response_string).yellow());
+        self.record(|store| store.set_state(task.request_id, TaskState::Failed, Some(&format!("{:#}", error))));
+        self.record(|store| store.set_result(task.request_id, &response_string));
+        let client = ContractClient::from_config(&self.config).await?;
+        self.submit_result(&client, task, response_string, None).await
+    }
+
+    async fn execute_task(&self, task: &Task) -> Result<()> {
+        let request_id = U256::from(task.request_id);
+
+        // Skip tasks this operator already answered or that have all the responses they asked for
+        let client = ContractClient::from_config(&self.config).await.class(FailureClass::Rpc)?;
+        if client.has_responded(request_id, client.signer_address()).await.class(FailureClass::Rpc)? {
+            info!("Task {}: already responded, skipping", request_id);
+            self.record(|store| store.set_state(task.request_id, TaskState::Skipped, Some("already responded")));
+            return Ok(());
+        }
+        let current_count = client
+            .submission_count(request_id, U64::from(task.task_created_block))
+            .await
+            .class(FailureClass::Rpc)?;
+        if current_count >= task.response_count {
+            let detail = format!("{} of {} responses already submitted", current_count, task.response_count);
+            info!("Task {}: {}, skipping", request_id, detail);
+            self.record(|store| store.set_state(task.request_id, TaskState::Skipped, Some(&detail)));
+            return Ok(());
+        }
+
+        if !self.config.is_language_supported(&task.language) {
+            return Err(UnsupportedLanguage(task.language.clone()).into());
+        }
+
+        // get the code json from arweave
+        self.record(|store| store.set_state(task.request_id, TaskState::Fetching, None));
+        let code = self
+            .fetch_code(&task.code_arweave_uri, &task.language)
+            .instrument(info_span!("fetch"))
+            .await?;
+
+        let determinism = if self.config.runtimes.js.deterministic {
+            let timestamp = client
+                .block_timestamp(u64::from(task.task_created_block))
+                .await
+                .class(FailureClass::Rpc)?;
+            Determinism::for_task(task, timestamp)
+        } else {
+            Determinism::default()
+        };
 
         // Execute based on language
-        let result = match task.language.as_str() {
-            "js" => {
-                self.execute_js(&task, &code_module).await?
+        self.record(|store| store.set_state(task.request_id, TaskState::Executing, None));
+        let timer = METRICS.execution_duration.with_label_values(&[&task.language]).start_timer();
+        let result = self
+            .run_code_consistent(&task.language, &code.module, determinism)
+            .instrument(info_span!("execute"))
+            .await;
+        timer.observe_duration();
+        let outcome = match &result {
+            Ok(_) => "success",
+            Err(e) if e.is::<NonDeterministic>() => "non_deterministic",
+            Err(_) => "failure",
+        };
+        METRICS.tasks_executed.with_label_values(&[&task.language, outcome]).inc();
+        let execution = match result {
+            Ok(execution) => execution,
+            Err(e) if e.is::<NpmInstallError>() => Err(e).class(FailureClass::Npm)?,
+            Err(e) if e.is::<NonDeterministic>() => {
+                warn!("{}", format!("Task {}: {}", task.request_id, e).yellow());
+                self.record(|store| store.set_state(task.request_id, TaskState::NonDeterministic, Some(&e.to_string())));
+                if !self.config.consistency.submit_error {
+                    return Ok(());
+                }
+                let response_string = ErrorResponse::new(ErrorClass::NonDeterministic, NON_DETERMINISTIC_MESSAGE)
+                    .to_response_string();
+                self.record(|store| store.set_result(task.request_id, &response_string));
+                return self.submit_result(&client, task, response_string, None).await;
             }
-            "go" => {
-                self.execute_go(&task, &code_module).await?
+            Err(e) => {
+                if let Some(failed) = e.downcast_ref::<ExecutionFailed>() {
+                    self.record(|store| store.set_logs(task.request_id, &failed.logs));
+                }
+                return Err(e);
             }
-            _ => return Err(eyre::eyre!("Unsupported language")),
         };
+        self.record(|store| store.set_logs(task.request_id, &execution.logs));
+        check_output(code.output, &execution.result)?;
+        let response_string = execution.result.to_response_string();
+        self.record(|store| store.set_result(task.request_id, &response_string));
+
+        let receipt = ExecutionReceipt::new(task, client.signer_address(), &code, &execution, &response_string);
+        self.record(|store| store.set_receipt(task.request_id, &receipt));
+        if self.config.receipts.upload {
+            // The receipt is kept locally either way, so a failed upload doesn't fail the task
+            match self.upload_receipt(&receipt).await {
+                Ok(id) => {
+                    info!("Task {}: receipt uploaded to Arweave as {}", task.request_id, id);
+                    self.record(|store| store.set_receipt_upload(task.request_id, &id));
+                }
+                Err(e) => warn!("Task {}: failed to upload the receipt: {:#}", task.request_id, e),
+            }
+        }
 
         // Submit result to contract
-        self.submit_result(&task, &result).await?;
+        self.submit_result(&client, task, response_string, Some(receipt.hash())).await
+    }
 
-        Ok(())
+    async fn upload_receipt(&self, receipt: &ExecutionReceipt) -> Result<String> {
+        let wallet_path = self
+            .config
+            .arweave
+            .wallet_path
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("arweave.wallet_path must be set to upload receipts"))?;
+        let mut client = ArweaveClient::new(Some(self.config.arweave.gateway_url.clone()))
+            .with_wallet(ArweaveWallet::from_jwk_file(wallet_path)?);
+        if let Some(bundler_url) = &self.config.arweave.bundler_url {
+            client = client.with_bundler(bundler_url.clone());
+        }
+        Ok(client.upload(&receipt.to_bytes(), &receipt.tags()).await?)
     }
 
-    async fn get_submission_count(&self, request_id: U256) -> Result<u64> {
-        // Create contract client
-        let abi = std::fs::read_to_string("abi/abi.json")?;
-        let contract_client = ContractClient::new(
-            &self.contract_address,
-            &self.config.rpc_url,
-            &abi,
-            &env::var("OPERATOR_PRIVATE_KEY").expect("OPERATOR_PRIVATE_KEY must be set")
-        ).await.map_err(|e| eyre::eyre!("{}", e))?;
-
-        // Call the contract method to get submission count
-        let count: U256 = contract_client.contract()
-            .method::<_, U256>("getSubmissionCountByTaskIndex", request_id)?
-            .call()
-            .await?;
+    /// Fetches the code module at `code_uri`, resolving `registry://` references
+    /// first, along with the output type the registry expects of it
+    pub async fn fetch_code(&self, code_uri: &str, language: &str) -> Result<FetchedCode> {
+        let (code_uri, output) = match FunctionRef::parse(code_uri) {
+            None => (code_uri.to_string(), None),
+            Some(reference) => {
+                let reference = reference?;
+                let entry = match self.registry.resolve(&reference).await {
+                    Ok(entry) => entry,
+                    Err(e) if e.is::<ArweaveError>() => return Err(e).class(FailureClass::Gateway)?,
+                    Err(e) => return Err(e),
+                };
+                if entry.language != language {
+                    let message = format!("{} is registered as {}, not {}", reference, entry.language, language);
+                    return Err(InvalidCode(message).into());
+                }
+                info!("Resolved {} to {}", reference, entry.code_uri);
+                (entry.code_uri, Some(entry.output))
+            }
+        };
+        let bytes = ArweaveClient::new(Some(self.config.arweave.gateway_url.clone()))
+            .get_transaction_bytes(&code_uri)
+            .await
+            .class(FailureClass::Gateway)?;
+        let code_json: serde_json::Value = serde_json::from_slice(&bytes)
+            .map_err(ArweaveError::from)
+            .class(FailureClass::Gateway)?;
+
+        // Parse code module from the retrieved JSON
+        let module = serde_json::from_value(code_json).map_err(|e| InvalidCode(e.to_string()))?;
+        Ok(FetchedCode { code_uri, module, bytes, output })
+    }
+
+    /// Runs a code module through the runtime for `language`, without touching the chain
+    pub async fn run_code(&self, language: &str, code_module: &CodeModule) -> Result<Execution> {
+        self.run_code_with(language, code_module, Determinism::default()).await
+    }
 
-        Ok(count.as_u64())
+    /// Like `run_code`, with the clock and seed deterministic mode uses
+    pub async fn run_code_with(
+        &self,
+        language: &str,
+        code_module: &CodeModule,
+        determinism: Determinism,
+    ) -> Result<Execution> {
+        match language {
+            "js" => self.execute_js(code_module, determinism).await,
+            "go" => self.execute_go(code_module).await,
+            _ => Err(UnsupportedLanguage(language.to_string()).into()),
+        }
+    }
+
+    /// Runs the code `consistency.runs` times, each in a fresh run directory or
+    /// engine, and returns the first run if every run gave the same response
+    pub async fn run_code_consistent(
+        &self,
+        language: &str,
+        code_module: &CodeModule,
+        determinism: Determinism,
+    ) -> Result<Execution> {
+        let execution = self.run_code_with(language, code_module, determinism).await?;
+        let expected = execution.result.to_response_string();
+        for _ in 1..self.config.consistency.runs {
+            let response_string = self.run_code_with(language, code_module, determinism).await?.result.to_response_string();
+            if response_string != expected {
+                return Err(NonDeterministic { runs: self.config.consistency.runs, first: expected, other: response_string }.into());
+            }
+        }
+        Ok(execution)
     }
 
-    async fn execute_js(&self, task: &Task, code_module: &CodeModule) -> Result<ExecutionResult> {
-        let js_executor = JsExecutor::new();
-        let result = js_executor.execute(code_module).await?;
+    async fn execute_js(&self, code_module: &CodeModule, determinism: Determinism) -> Result<Execution> {
+        let config = self.config.runtimes.js.clone();
+        let limits = self.config.limits.clone();
+        let result = match config.engine {
+            JsEngine::Node => {
+                JsExecutor::new(config)
+                    .with_limits(limits)
+                    .with_determinism(determinism)
+                    .execute(code_module)
+                    .await?
+            }
+            #[cfg(feature = "quickjs")]
+            JsEngine::Quickjs => {
+                QuickJsExecutor::new(config)
+                    .with_limits(limits)
+                    .with_determinism(determinism)
+                    .execute(code_module)
+                    .await?
+            }
+            #[cfg(not(feature = "quickjs"))]
+            JsEngine::Quickjs => return Err(eyre::eyre!("Built without the quickjs feature")),
+        };
         Ok(result)
     }
 
-    async fn execute_go(&self, task: &Task, code_module: &CodeModule) -> Result<ExecutionResult> {
-        let result = ExecutionResult::Bool(true);
-        self.submit_result(&task, &result).await?;
+    async fn execute_go(&self, _code_module: &CodeModule) -> Result<Execution> {
+        Ok(Execution {
+            result: ExecutionResult::Bool(true),
+            logs: ExecutionLogs::default(),
+            runtime: RuntimeInfo { name: "go".to_string(), version: String::new() },
+            usage: ResourceUsage::default(),
+            lockfile_hash: None,
+            determinism: None,
+        })
+    }
+
+    /// Signs the response together with `receipt_hash`, if any, and submits
+    /// it. BLS responses are aggregated across operators, so they sign the
+    /// response alone and the receipt is only kept locally.
+    async fn submit_result(
+        &self,
+        client: &ContractClient,
+        task: &Task,
+        response_string: String,
+        receipt_hash: Option<H256>,
+    ) -> Result<()> {
+        if self.config.bls.enabled {
+            METRICS.submissions.with_label_values(&["bls", "sent"]).inc();
+            self.send_bls_response(task, response_string).await.class(FailureClass::Aggregator)?;
+            METRICS.submissions.with_label_values(&["bls", "accepted"]).inc();
+            self.record(|store| store.set_state(task.request_id, TaskState::SentToAggregator, None));
+            return Ok(());
+        }
+
+        let wallet = client.contract().client().signer().clone();
+        if let Some(gossip) = &self.gossip {
+            let signature = respond::create_signature_for_task(&wallet, &response_string, task, receipt_hash)
+                .await
+                .map_err(|e| eyre::eyre!("{}", e))?;
+            let response = SignedResponse {
+                task: task.clone(),
+                response_string: response_string.clone(),
+                operator: wallet.address(),
+                signature,
+            };
+            METRICS.submissions.with_label_values(&["gossip", "sent"]).inc();
+            match gossip.send_response(&response).await {
+                Ok(()) => {
+                    METRICS.submissions.with_label_values(&["gossip", "accepted"]).inc();
+                    info!("Sent signed response for task {} to the aggregator", task.request_id)
+                }
+                // The on-chain submission below still gets the response through
+                Err(e) if self.config.gossip.submit_on_chain => warn!("{:#}", e),
+                Err(e) => return Err(e).class(FailureClass::Aggregator)?,
+            }
+            if !self.config.gossip.submit_on_chain {
+                self.record(|store| store.set_state(task.request_id, TaskState::SentToAggregator, None));
+                return Ok(());
+            }
+        }
+
+        // Submit the result using respond module
+        self.record(|store| store.set_state(task.request_id, TaskState::Submitting, None));
+        let tx_hash = respond::send_response(client, &wallet, task.clone(), response_string, receipt_hash)
+            .await
+            .map_err(|e| eyre::eyre!("{}", e))
+            .class(FailureClass::Rpc)?;
+        Span::current().record("tx_hash", tracing::field::debug(tx_hash));
+        info!("Task {}: response sent in {:?}", task.request_id, tx_hash);
+        self.record(|store| store.set_tx_hash(task.request_id, &format!("{:?}", tx_hash)));
+        METRICS.submissions.with_label_values(&["chain", "sent"]).inc();
+        let receipt = client.wait_for_mined(tx_hash).await.class(FailureClass::Rpc)?;
+        METRICS.record_receipt(&receipt);
+        if receipt.status != Some(U64::one()) {
+            return Err(eyre::eyre!("Transaction {:?} reverted", tx_hash)).class(FailureClass::Rpc)?;
+        }
+        let detail = format!("{:?} mined in block {}", tx_hash, receipt.block_number.unwrap_or_default());
+        self.record(|store| store.set_state(task.request_id, TaskState::Confirmed, Some(&detail)));
+        Ok(())
+    }
+
+    /// Signs the response with the operator's BLS key and hands it to the
+    /// aggregator, which submits one aggregated response for all signers
+    async fn send_bls_response(&self, task: &Task, response_string: String) -> Result<()> {
+        let gossip = self
+            .gossip
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("gossip.aggregator_url must be set in BLS mode"))?;
+        let key = BlsKeyPair::from_hex(self.config.bls.private_key.expose_secret())?;
+        let operator = self.config.operator_address.parse()?;
+        let response = BlsSignedResponse::new(&key, operator, task.clone(), response_string);
+        gossip.send_bls_response(&response).await?;
+        info!("Sent BLS signed response for task {} to the aggregator", task.request_id);
+        Ok(())
+    }
+}
+
+/// What the code printed while it ran, kept apart from its result
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExecutionLogs {
+    pub stdout: String,
+    pub stderr: String,
+    /// Whether either stream went over `limits.max_output_bytes` and was cut short
+    pub truncated: bool,
+}
+
+impl ExecutionLogs {
+    pub fn is_empty(&self) -> bool {
+        self.stdout.is_empty() && self.stderr.is_empty()
+    }
+}
+
+/// A successful run: the result along with the logs and what went into the receipt
+#[derive(Debug)]
+pub struct Execution {
+    pub result: ExecutionResult,
+    pub logs: ExecutionLogs,
+    pub runtime: RuntimeInfo,
+    pub usage: ResourceUsage,
+    /// Hash of the lockfile the dependencies were installed from, if any
+    pub lockfile_hash: Option<H256>,
+    /// The clock and seed the code saw, in deterministic mode
+    pub determinism: Option<Determinism>,
+}
+
+/// Whether a gateway failure is down to the code rather than the gateway: it
+/// isn't there, or isn't JSON. Connection errors and 5xx stay with this
+/// operator, as another gateway may well have served the code.
+fn is_code_unavailable(error: &eyre::Report) -> bool {
+    match error.downcast_ref::<ArweaveError>() {
+        Some(ArweaveError::RequestError(e)) => e.status() == Some(reqwest::StatusCode::NOT_FOUND),
+        Some(ArweaveError::JsonError(_)) => true,
+        _ => false,
+    }
+}
+
+/// The error response for a task that failed with `error`, if the failure is
+/// down to the task rather than this operator
+pub fn error_response(task: &Task, error: &eyre::Report) -> Option<ErrorResponse> {
+    let (failure_class, error) = match error.downcast_ref::<TaskError>() {
+        Some(task_error) => (task_error.class, task_error.error()),
+        None => (FailureClass::Execution, error),
+    };
+    if failure_class == FailureClass::Gateway {
+        return is_code_unavailable(error).then(|| ErrorResponse::new(ErrorClass::FetchFailure, &task.code_arweave_uri));
+    }
+    // Only what every operator would see goes into the message: the first line
+    // of a stack trace, not the paths below it, and no limits or timings
+    let (class, message) = if let Some(e) = error.downcast_ref::<ExecutionFailed>() {
+        (ErrorClass::RuntimeError, e.message.lines().next().unwrap_or_default().to_string())
+    } else if let Some(e) = error.downcast_ref::<DeterminismViolation>() {
+        (ErrorClass::RuntimeError, e.to_string())
+    } else if let Some(e) = error.downcast_ref::<InvalidResult>() {
+        (ErrorClass::RuntimeError, e.to_string())
+    } else if error.is::<process::Timeout>() {
+        (ErrorClass::Timeout, String::new())
+    } else if let Some(e) = error.downcast_ref::<InvalidCode>() {
+        (ErrorClass::InvalidCode, e.to_string())
+    } else if let Some(e) = error.downcast_ref::<DependencyError>() {
+        (ErrorClass::InvalidCode, e.to_string())
+    } else if let Some(e @ (RegistryError::InvalidReference(_) | RegistryError::UnknownFunction { .. })) =
+        error.downcast_ref::<RegistryError>()
+    {
+        (ErrorClass::InvalidCode, e.to_string())
+    } else if let Some(e) = error.downcast_ref::<UnsupportedLanguage>() {
+        (ErrorClass::UnsupportedLanguage, e.0.clone())
+    } else if error.is::<NonDeterministic>() {
+        (ErrorClass::NonDeterministic, NON_DETERMINISTIC_MESSAGE.to_string())
+    } else {
+        return quickjs_error_response(error);
+    };
+    Some(ErrorResponse::new(class, &message))
+}
+
+#[cfg(feature = "quickjs")]
+fn quickjs_error_response(error: &eyre::Report) -> Option<ErrorResponse> {
+    match error.downcast_ref::<LimitExceeded>()? {
+        LimitExceeded::Duration(_) | LimitExceeded::Instructions(_) => Some(ErrorResponse::new(ErrorClass::Timeout, "")),
+        LimitExceeded::Cancelled => None,
+    }
+}
+
+#[cfg(not(feature = "quickjs"))]
+fn quickjs_error_response(_error: &eyre::Report) -> Option<ErrorResponse> {
+    None
+}
+
+/// Checks the result against the output type a registry function is registered with
+pub fn check_output(output: Option<OutputType>, result: &ExecutionResult) -> Result<(), InvalidResult> {
+    match output {
+        Some(output) if !output.matches(result) => Err(InvalidResult(format!("expected {} output", output))),
+        _ => Ok(()),
+    }
+}
+
+/// What every operator reports for a task whose runs disagree
+const NON_DETERMINISTIC_MESSAGE: &str = "runs of the task gave different results";
+
+/// Runs of the same task gave different responses
+#[derive(Error, Debug)]
+#[error("{runs} runs gave different results: {first:?} and {other:?}")]
+pub struct NonDeterministic {
+    pub runs: u32,
+    pub first: String,
+    pub other: String,
+}
+
+/// The code returned something that can't be submitted as a response
+#[derive(Error, Debug)]
+#[error("invalid result: {0}")]
+pub struct InvalidResult(pub String);
+
+/// The code module is malformed or breaks the rules for code modules
+#[derive(Error, Debug)]
+#[error("invalid code module: {0}")]
+pub struct InvalidCode(pub String);
+
+#[derive(Error, Debug)]
+#[error("Unsupported language: {0}")]
+pub struct UnsupportedLanguage(pub String);
+
+/// The code threw or didn't produce a result; carries the logs of the run
+#[derive(Error, Debug)]
+#[error("JavaScript execution failed: {message}")]
+pub struct ExecutionFailed {
+    pub message: String,
+    pub logs: ExecutionLogs,
+}
+
+#[derive(Debug)]
+pub enum ExecutionResult {
+    UintArray(Vec<U256>),
+    BoolArray(Vec<bool>),
+    Uint(U256),
+    Bool(bool),
+    Bytes(Vec<u8>),
+}
+
+impl ExecutionResult {
+    /// Converts the JSON value returned by JS code, whichever runtime ran it
+    pub fn from_json(value: serde_json::Value) -> Result<Self> {
+        let result = match value {
+            // Arrays: Only supports arrays of numbers (converted to U256) or booleans
+            serde_json::Value::Array(arr) => {
+                if arr.iter().all(|v| v.is_number()) {
+                    ExecutionResult::UintArray(
+                        arr.iter()
+                            .map(|v| {
+                                v.as_u64().map(U256::from).ok_or_else(|| {
+                                    InvalidResult(format!("{} is not an unsigned 64-bit integer", v))
+                                })
+                            })
+                            .collect::<Result<_, _>>()?,
+                    )
+                } else if arr.iter().all(|v| v.is_boolean()) {
+                    ExecutionResult::BoolArray(
+                        arr.iter()
+                            .map(|v| v.as_bool().unwrap())
+                            .collect(),
+                    )
+                } else {
+                    return Err(InvalidResult("unsupported array type".to_string()).into());
+                }
+            }
+            // Numbers: Only supports unsigned integers (converted to U256)
+            serde_json::Value::Number(n) => ExecutionResult::Uint(U256::from(n.as_u64().ok_or_else(|| {
+                InvalidResult("number conversion failed".to_string())
+            })?)),
+            // Booleans
+            serde_json::Value::Bool(b) => ExecutionResult::Bool(b),
+            // Everything else is unsupported
+            _ => return Err(InvalidResult("unsupported return type".to_string()).into()),
+        };
         Ok(result)
     }
 
-    async fn submit_result(&self, task: &Task, result: &ExecutionResult) -> Result<()> {
-        // Convert ExecutionResult to string
-        let response_string = match result {
+    /// The string submitted on-chain as `responseString`
+    pub fn to_response_string(&self) -> String {
+        match self {
             ExecutionResult::UintArray(arr) => arr.iter()
                 .map(|x| x.to_string())
                 .collect::<Vec<String>>()
@@ -133,26 +690,65 @@ impl Executor {
             ExecutionResult::Uint(val) => val.to_string(),
             ExecutionResult::Bool(val) => val.to_string(),
             ExecutionResult::Bytes(bytes) => hex::encode(bytes),
-        };
+        }
+    }
+}
 
-        // Submit the result using respond module
-        let _ = respond::respond_to_task(
-            &self.contract_address,
-            &self.config.rpc_url,
-            task.clone(),
-            response_string,
-        ).await.map_err(|e| eyre::eyre!("{}", e));
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arweave_mock::MockGateway;
+
+    fn task() -> Task {
+        Task {
+            code_arweave_uri: "code".to_string(),
+            language: "js".to_string(),
+            response_count: U256::one(),
+            task_created_block: 1,
+            request_id: 7,
+        }
     }
 
+    /// The error response a task would get if fetching its code from `gateway_url` failed for good
+    async fn fetch_error_response(gateway_url: String) -> Option<ErrorResponse> {
+        let mut config = Config::default();
+        config.arweave.gateway_url = gateway_url;
+        let error = Executor::new(config).fetch_code("code", "js").await.unwrap_err();
+        error_response(&task(), &error)
+    }
 
-}
+    #[test]
+    fn results_must_be_unsigned_integers() {
+        let result = ExecutionResult::from_json(serde_json::json!([1, 2, 3])).unwrap();
+        assert_eq!(result.to_response_string(), "1,2,3");
+        for value in ["[1.5]", "[-1]", "[18446744073709551616]", "1.5", "-1"] {
+            let error = ExecutionResult::from_json(serde_json::from_str(value).unwrap()).unwrap_err();
+            assert!(error.downcast_ref::<InvalidResult>().is_some(), "{}: {}", value, error);
+        }
+    }
 
-#[derive(Debug)]
-pub enum ExecutionResult {
-    UintArray(Vec<U256>),
-    BoolArray(Vec<bool>),
-    Uint(U256),
-    Bool(bool),
-    Bytes(Vec<u8>),
+    #[tokio::test]
+    async fn missing_or_invalid_code_is_a_fetch_failure() {
+        let gateway = MockGateway::start().await.unwrap();
+        let response = fetch_error_response(gateway.url().to_string()).await.unwrap();
+        assert_eq!(response, ErrorResponse::new(ErrorClass::FetchFailure, "code"));
+
+        gateway.insert("code", b"<html>not a code module</html>".to_vec(), Vec::new());
+        let response = fetch_error_response(gateway.url().to_string()).await.unwrap();
+        assert_eq!(response, ErrorResponse::new(ErrorClass::FetchFailure, "code"));
+    }
+
+    #[tokio::test]
+    async fn gateway_outages_stay_local() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let router = axum::Router::new()
+            .route("/{id}", axum::routing::get(|| async { axum::http::StatusCode::BAD_GATEWAY }));
+        let server = tokio::spawn(async move { axum::serve(listener, router).await });
+        assert_eq!(fetch_error_response(url.clone()).await, None);
+
+        server.abort();
+        let _ = server.await;
+        assert_eq!(fetch_error_response(url).await, None);
+    }
 }
@@ -0,0 +1,123 @@
+//! Operator side of the off-chain response channel: signed responses are
+//! posted to the aggregator's HTTP endpoint instead of (or as well as) being
+//! submitted with `respondToTask`.
+
+use crate::bls::BlsSignedResponse;
+use crate::respond::Task;
+use ethers::types::{Address, Bytes};
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A response signed with `create_signature_for_task`, as sent to the aggregator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedResponse {
+    pub task: Task,
+    pub response_string: String,
+    pub operator: Address,
+    pub signature: Bytes,
+}
+
+pub struct GossipClient {
+    aggregator_url: String,
+    http: reqwest::Client,
+}
+
+impl GossipClient {
+    pub fn new(aggregator_url: &str) -> Self {
+        Self {
+            aggregator_url: aggregator_url.trim_end_matches('/').to_string(),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("static client config is valid"),
+        }
+    }
+
+    pub async fn send_response(&self, response: &SignedResponse) -> Result<()> {
+        self.post("/responses", response).await
+    }
+
+    pub async fn send_bls_response(&self, response: &BlsSignedResponse) -> Result<()> {
+        self.post("/bls/responses", response).await
+    }
+
+    async fn post<T: Serialize>(&self, path: &str, body: &T) -> Result<()> {
+        let url = format!("{}{}", self.aggregator_url, path);
+        let reply = self.http.post(&url).json(body).send().await?;
+        if !reply.status().is_success() {
+            let status = reply.status();
+            return Err(eyre::eyre!(
+                "Aggregator rejected response at {}: {} {}",
+                url,
+                status,
+                reply.text().await.unwrap_or_default()
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregator_server::tests::{bls_operators, chain_with, serve, task};
+    use crate::bls::BlsKeyPair;
+    use crate::respond::create_signature_for_task;
+    use ethers::signers::{LocalWallet, Signer};
+
+    #[tokio::test]
+    async fn rejections_come_back_as_errors() {
+        let task = task();
+        let chain = chain_with(&task, &[]).await;
+        let operator = LocalWallet::new(&mut rand::thread_rng());
+        chain.state().operators.insert(operator.address());
+        let gossip = GossipClient::new(&format!("{}/", serve(&chain, 2).await));
+
+        let mut response = SignedResponse {
+            task: task.clone(),
+            response_string: "42".to_string(),
+            operator: operator.address(),
+            signature: create_signature_for_task(&operator, "42", &task, None).await.unwrap(),
+        };
+        gossip.send_response(&response).await.unwrap();
+        // The aggregator counts the operator once, so a resend is still accepted
+        gossip.send_response(&response).await.unwrap();
+
+        response.response_string = "43".to_string();
+        let error = gossip.send_response(&response).await.unwrap_err().to_string();
+        assert!(error.contains("400 Bad Request"), "{}", error);
+
+        let outsider = LocalWallet::new(&mut rand::thread_rng());
+        let response = SignedResponse {
+            task: task.clone(),
+            response_string: "42".to_string(),
+            operator: outsider.address(),
+            signature: create_signature_for_task(&outsider, "42", &task, None).await.unwrap(),
+        };
+        let error = gossip.send_response(&response).await.unwrap_err().to_string();
+        assert!(error.contains("403 Forbidden"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn bls_rejections_come_back_as_errors() {
+        let task = task();
+        let operators = bls_operators(2);
+        let chain = chain_with(&task, &operators).await;
+        let gossip = GossipClient::new(&serve(&chain, 2).await);
+        let (operator, key) = &operators[0];
+
+        gossip
+            .send_bls_response(&BlsSignedResponse::new(key, *operator, task.clone(), "42".to_string()))
+            .await
+            .unwrap();
+        let conflicting = BlsSignedResponse::new(key, *operator, task.clone(), "43".to_string());
+        let error = gossip.send_bls_response(&conflicting).await.unwrap_err().to_string();
+        assert!(error.contains("409 Conflict"), "{}", error);
+
+        let unregistered =
+            BlsSignedResponse::new(&BlsKeyPair::random(), Address::from_low_u64_be(0xff), task.clone(), "42".to_string());
+        let error = gossip.send_bls_response(&unregistered).await.unwrap_err().to_string();
+        assert!(error.contains("403 Forbidden"), "{}", error);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::config::Config;
+use crate::contract::ContractClient;
+use colored::*;
+use ethers::prelude::*;
+use ethers::types::{Address, U256};
+use ethers::utils::{format_ether, parse_ether};
+use eyre::Result;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    /// The operator can keep running but should not take on tasks
+    Degraded,
+    /// The operator must not start
+    Fatal,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HealthReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl HealthReport {
+    pub fn is_fatal(&self) -> bool {
+        self.checks.iter().any(|check| check.status == CheckStatus::Fatal)
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.status == CheckStatus::Ok)
+    }
+
+    /// Messages of every failed check, for logs and errors
+    pub fn problems(&self) -> Vec<String> {
+        self.checks
+            .iter()
+            .filter(|check| check.status != CheckStatus::Ok)
+            .map(|check| format!("{}: {}", check.name, check.message))
+            .collect()
+    }
+
+    fn push(&mut self, name: &'static str, status: CheckStatus, message: String) {
+        self.checks.push(CheckResult { name, status, message });
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Ok => "ok".green(),
+                CheckStatus::Degraded => "degraded".yellow(),
+                CheckStatus::Fatal => "fatal".red(),
+            };
+            writeln!(f, "  [{}] {}: {}", status, check.name, check.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// Verifies the operator is able to do useful work before and while it listens
+pub struct HealthChecker {
+    client: ContractClient,
+    operator_address: String,
+    expected_chain_id: Option<u64>,
+    min_balance: U256,
+}
+
+impl HealthChecker {
+    pub async fn new(config: &Config) -> Result<Self> {
+        let client = ContractClient::from_config(config).await?;
+        let min_balance = parse_ether(&config.health.min_balance_eth)?;
+        Ok(Self {
+            client,
+            operator_address: config.operator_address.clone(),
+            expected_chain_id: config.health.expected_chain_id,
+            min_balance,
+        })
+    }
+
+    pub async fn check(&self) -> HealthReport {
+        let mut report = HealthReport::default();
+        let provider = self.client.provider();
+
+        match provider.get_chainid().await {
+            Ok(chain_id) => match self.expected_chain_id {
+                Some(expected) if chain_id.as_u64() != expected => report.push(
+                    "chain id",
+                    CheckStatus::Fatal,
+                    format!("RPC is on chain {} but the config expects {}", chain_id, expected),
+                ),
+                _ => report.push("chain id", CheckStatus::Ok, chain_id.to_string()),
+            },
+            Err(e) => {
+                // Nothing else can be checked without the RPC
+                report.push("chain id", CheckStatus::Fatal, format!("RPC unreachable: {}", e));
+                return report;
+            }
+        }
+
+        let contract = self.client.contract().address();
+        match provider.get_code(contract, None).await {
+            Ok(code) if code.is_empty() => report.push(
+                "contract code",
+                CheckStatus::Fatal,
+                format!("no contract deployed at {:?}", contract),
+            ),
+            Ok(_) => report.push("contract code", CheckStatus::Ok, format!("{:?}", contract)),
+            Err(e) => report.push("contract code", CheckStatus::Fatal, e.to_string()),
+        }
+
+        let signer = self.client.signer_address();
+        match self.operator_address.parse::<Address>() {
+            Ok(address) if address == signer => {
+                report.push("operator key", CheckStatus::Ok, format!("{:?}", signer))
+            }
+            _ => report.push(
+                "operator key",
+                CheckStatus::Fatal,
+                format!(
+                    "OPERATOR_ADDRESS {} does not match the private key address {:?}",
+                    self.operator_address, signer
+                ),
+            ),
+        }
+
+        match self.client.is_operator_registered(signer).await {
+            Ok(true) => report.push("registration", CheckStatus::Ok, "registered".to_string()),
+            Ok(false) => report.push(
+                "registration",
+                CheckStatus::Degraded,
+                "operator is not registered, respondToTask would revert; run `register`".to_string(),
+            ),
+            Err(e) => report.push("registration", CheckStatus::Degraded, e.to_string()),
+        }
+
+        match provider.get_balance(signer, None).await {
+            Ok(balance) if balance < self.min_balance => report.push(
+                "gas balance",
+                CheckStatus::Degraded,
+                format!(
+                    "{} ETH is below the minimum of {} ETH",
+                    format_ether(balance),
+                    format_ether(self.min_balance)
+                ),
+            ),
+            Ok(balance) => report.push("gas balance", CheckStatus::Ok, format!("{} ETH", format_ether(balance))),
+            Err(e) => report.push("gas balance", CheckStatus::Degraded, e.to_string()),
+        }
+
+        report
+    }
+}
@@ -0,0 +1,28 @@
+// QuickJS has no console. This one collects what the code prints so it can be
+// kept as the run's logs, the way node's stdout and stderr are.
+(() => {
+    const stdout = [];
+    const stderr = [];
+    const format = (args) =>
+        args
+            .map((arg) => {
+                if (typeof arg === 'string') return arg;
+                try {
+                    return JSON.stringify(arg) ?? String(arg);
+                } catch {
+                    return String(arg);
+                }
+            })
+            .join(' ') + '\n';
+    const to = (lines) => (...args) => void lines.push(format(args));
+    globalThis.console = {
+        log: to(stdout),
+        info: to(stdout),
+        debug: to(stdout),
+        warn: to(stderr),
+        error: to(stderr),
+    };
+    Object.defineProperty(globalThis, '__logs', {
+        value: () => JSON.stringify({ stdout: stdout.join(''), stderr: stderr.join(''), truncated: false }),
+    });
+})();
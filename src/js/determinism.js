@@ -0,0 +1,152 @@
+// Loaded before the task's code in deterministic mode. Replaces every source
+// of nondeterminism with a fixed or seeded equivalent and turns I/O into
+// recorded violations, so operators running the same task get the same result.
+// __CLOCK_MS__ and __SEED__ are filled in by the executor.
+import { createRequire, syncBuiltinESMExports } from 'node:module';
+
+const require = createRequire(import.meta.url);
+const CLOCK_MS = __CLOCK_MS__;
+const SEED = '__SEED__';
+
+export const violations = [];
+
+function forbid(name) {
+    return function () {
+        const message = `${name} is not allowed in deterministic mode`;
+        violations.push(message);
+        throw new Error(message);
+    };
+}
+
+// sfc32 seeded from the first 16 bytes of the task hash
+const words = [0, 8, 16, 24].map((i) => parseInt(SEED.slice(i, i + 8), 16) >>> 0);
+let [a, b, c, d] = words;
+function nextUint32() {
+    const t = (((a + b) >>> 0) + d) >>> 0;
+    d = (d + 1) >>> 0;
+    a = b ^ (b >>> 9);
+    b = (c + (c << 3)) >>> 0;
+    c = (c << 21) | (c >>> 11);
+    c = (c + t) >>> 0;
+    return t;
+}
+for (let i = 0; i < 16; i++) nextUint32();
+
+function fillRandom(view) {
+    const bytes = new Uint8Array(view.buffer, view.byteOffset, view.byteLength);
+    for (let i = 0; i < bytes.length; i++) bytes[i] = nextUint32() & 0xff;
+    return view;
+}
+
+function randomUUID() {
+    const bytes = fillRandom(new Uint8Array(16));
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    const hex = Array.from(bytes, (byte) => byte.toString(16).padStart(2, '0')).join('');
+    return `${hex.slice(0, 8)}-${hex.slice(8, 12)}-${hex.slice(12, 16)}-${hex.slice(16, 20)}-${hex.slice(20)}`;
+}
+
+Math.random = () => nextUint32() / 4294967296;
+
+// Clock: fixed at the timestamp of the block the task was created in
+const RealDate = Date;
+function FixedDate(...args) {
+    if (!new.target) return new RealDate(CLOCK_MS).toString();
+    return args.length === 0 ? new RealDate(CLOCK_MS) : new RealDate(...args);
+}
+FixedDate.prototype = RealDate.prototype;
+FixedDate.now = () => CLOCK_MS;
+FixedDate.parse = RealDate.parse;
+FixedDate.UTC = RealDate.UTC;
+globalThis.Date = FixedDate;
+
+Object.defineProperty(globalThis.performance, 'now', { value: () => 0 });
+Object.defineProperty(globalThis.performance, 'timeOrigin', { value: CLOCK_MS });
+// These read the real clock and event loop without going through now()
+for (const name of ['eventLoopUtilization', 'mark', 'measure', 'timerify', 'toJSON']) {
+    Object.defineProperty(globalThis.performance, name, { value: forbid(`performance.${name}`) });
+}
+Object.defineProperty(globalThis.performance, 'nodeTiming', { get: forbid('performance.nodeTiming') });
+process.hrtime = Object.assign(() => [Math.floor(CLOCK_MS / 1000), (CLOCK_MS % 1000) * 1e6], {
+    bigint: () => BigInt(CLOCK_MS) * 1000000n,
+});
+process.uptime = () => 0;
+
+// Randomness from node:crypto and Web Crypto comes from the same generator
+const crypto = require('node:crypto');
+crypto.randomBytes = (size, callback) => {
+    const buffer = fillRandom(Buffer.alloc(size));
+    if (callback) return void callback(null, buffer);
+    return buffer;
+};
+function fillRange(view, offset = 0, size) {
+    const bytes = new Uint8Array(view.buffer, view.byteOffset, view.byteLength);
+    fillRandom(bytes.subarray(offset, size === undefined ? undefined : offset + size));
+    return view;
+}
+crypto.randomFillSync = fillRange;
+// Calls back at once rather than from the thread pool
+crypto.randomFill = (view, ...args) => {
+    const callback = args.pop();
+    callback(null, fillRange(view, ...args));
+};
+crypto.randomUUID = randomUUID;
+crypto.randomInt = (min, max) => {
+    if (max === undefined) [min, max] = [0, min];
+    return min + (nextUint32() % (max - min));
+};
+Object.defineProperty(globalThis.crypto, 'getRandomValues', { value: fillRandom });
+Object.defineProperty(globalThis.crypto, 'randomUUID', { value: randomUUID });
+
+// Key and prime generation draw from OpenSSL's generator, which can't be seeded
+const keyGeneration = [
+    'generateKey', 'generateKeySync', 'generateKeyPair', 'generateKeyPairSync', 'generatePrime',
+    'generatePrimeSync', 'createDiffieHellman', 'getDiffieHellman', 'createECDH',
+];
+for (const name of keyGeneration) {
+    crypto[name] = forbid(`crypto.${name}`);
+}
+Object.defineProperty(globalThis.crypto.subtle, 'generateKey', { value: forbid('crypto.subtle.generateKey') });
+
+// Timers would make the result depend on scheduling
+for (const name of ['setTimeout', 'setInterval', 'setImmediate']) {
+    globalThis[name] = forbid(name);
+}
+
+// No network, processes, threads or file access beyond loading the task's own
+// modules. Create stdout and stderr first, as Node builds them lazily from the
+// modules patched below.
+void process.stdout;
+void process.stderr;
+for (const name of ['fetch', 'WebSocket', 'EventSource', 'XMLHttpRequest']) {
+    if (name in globalThis) globalThis[name] = forbid(name);
+}
+// The module loader reads files through these, so they stay
+const loaderFs = new Set(['readFileSync', 'realpathSync', 'statSync', 'lstatSync', 'existsSync']);
+const blocked = [
+    'timers', 'timers/promises', 'fs', 'fs/promises', 'net', 'http', 'https', 'http2', 'dgram', 'dns',
+    'dns/promises', 'tls', 'child_process', 'worker_threads', 'cluster', 'os', 'vm', 'v8', 'inspector',
+    'perf_hooks',
+];
+for (const name of blocked) {
+    const module = require(`node:${name}`);
+    for (const key of Object.keys(module)) {
+        if (typeof module[key] !== 'function' || (name === 'fs' && loaderFs.has(key))) continue;
+        try {
+            module[key] = forbid(`${name}.${key}`);
+        } catch {
+            // read-only export, left as is
+        }
+    }
+}
+syncBuiltinESMExports();
+
+// The process itself: its id, working directory and resource use differ
+// between operators, and exiting early would skip the violation check in
+// harness.js
+for (const name of ['binding', 'exit', 'memoryUsage', 'cpuUsage', 'resourceUsage', 'cwd', 'chdir', 'kill']) {
+    process[name] = forbid(`process.${name}`);
+}
+for (const name of ['pid', 'ppid']) {
+    Object.defineProperty(process, name, { get: forbid(`process.${name}`) });
+}
@@ -0,0 +1,32 @@
+// The embedded engine's version of determinism.js. QuickJS has no timers,
+// network or file access to begin with, so only the clock and Math.random are
+// replaced. __CLOCK_MS__ and __SEED__ are filled in by the executor.
+(() => {
+    const CLOCK_MS = __CLOCK_MS__;
+    const SEED = '__SEED__';
+
+    // sfc32 seeded from the first 16 bytes of the task hash, as in determinism.js
+    let [a, b, c, d] = [0, 8, 16, 24].map((i) => parseInt(SEED.slice(i, i + 8), 16) >>> 0);
+    function nextUint32() {
+        const t = (((a + b) >>> 0) + d) >>> 0;
+        d = (d + 1) >>> 0;
+        a = b ^ (b >>> 9);
+        b = (c + (c << 3)) >>> 0;
+        c = (c << 21) | (c >>> 11);
+        c = (c + t) >>> 0;
+        return t;
+    }
+    for (let i = 0; i < 16; i++) nextUint32();
+    Math.random = () => nextUint32() / 4294967296;
+
+    const RealDate = Date;
+    function FixedDate(...args) {
+        if (!new.target) return new RealDate(CLOCK_MS).toString();
+        return args.length === 0 ? new RealDate(CLOCK_MS) : new RealDate(...args);
+    }
+    FixedDate.prototype = RealDate.prototype;
+    FixedDate.now = () => CLOCK_MS;
+    FixedDate.parse = RealDate.parse;
+    FixedDate.UTC = RealDate.UTC;
+    globalThis.Date = FixedDate;
+})();
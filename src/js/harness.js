@@ -0,0 +1,20 @@
+// Runs the task's code and reports its outcome. stdout and stderr are left to
+// the code and kept as its logs.
+import { report } from './result.js';
+import { violations } from './determinism.js';
+import { run } from './index.js';
+
+try {
+    const value = await run();
+    if (violations.length > 0) {
+        report({ outcome: 'violation', message: violations.join('; ') });
+    } else {
+        report({ outcome: 'ok', value });
+    }
+} catch (error) {
+    if (violations.length > 0) {
+        report({ outcome: 'violation', message: violations.join('; ') });
+    } else {
+        report({ outcome: 'error', message: String(error?.stack ?? error) });
+    }
+}
@@ -0,0 +1,13 @@
+// Delivers the outcome of a run to the operator through result.json, so the
+// code's own console output never mixes with it. Imported before
+// determinism.js, which disables fs for the code. The Node version and the
+// resources the run used go along with it for the execution receipt.
+import fs from 'node:fs';
+
+const writeFileSync = fs.writeFileSync;
+const resourceUsage = process.resourceUsage;
+const path = new URL('./result.json', import.meta.url);
+
+export function report(outcome) {
+    writeFileSync(path, JSON.stringify({ ...outcome, runtime: process.version, usage: resourceUsage() }));
+}
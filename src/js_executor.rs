@@ -1,33 +1,168 @@
-use crate::executor::{CodeModule, ExecutionResult};
-use ethers::types::U256;
+use crate::config::{JsRuntimeConfig, LimitsConfig};
+use crate::executor::{CodeModule, Execution, ExecutionFailed, ExecutionLogs, ExecutionResult, InvalidCode, InvalidResult};
+use crate::npm::NpmInstaller;
+use crate::process;
+use crate::receipt::{ResourceUsage, RuntimeInfo};
+use crate::respond::Task;
+use ethers::types::H256;
+use ethers::utils::{hex, keccak256};
 use eyre::Result;
-use std::process::Command;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
+use thiserror::Error;
 use tokio::fs::write;
+use tokio::process::Command;
+use tracing::{debug, info};
 
-pub struct JsExecutor;
+/// Installing the module's dependencies failed, as opposed to the code itself
+#[derive(Error, Debug)]
+#[error("npm install failed: {0}")]
+pub struct NpmInstallError(pub String);
+
+/// The code used an API that is disabled in deterministic mode
+#[derive(Error, Debug)]
+#[error("determinism violation: {0}")]
+pub struct DeterminismViolation(pub String);
+
+const DETERMINISM_PRELUDE: &str = include_str!("js/determinism.js");
+const RESULT_MODULE: &str = include_str!("js/result.js");
+const HARNESS: &str = include_str!("js/harness.js");
+
+/// How a run ended, as reported by the harness in result.json
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "lowercase")]
+enum Outcome {
+    Ok {
+        /// Missing when the function returned `undefined`
+        #[serde(default)]
+        value: serde_json::Value,
+    },
+    Error { message: String },
+    /// The code broke out of deterministic mode
+    Violation { message: String },
+}
+
+/// result.json: the outcome along with what the receipt records about the run
+#[derive(Debug, Deserialize)]
+struct Report {
+    #[serde(flatten)]
+    outcome: Outcome,
+    /// `process.version`
+    #[serde(default)]
+    runtime: String,
+    #[serde(default)]
+    usage: NodeUsage,
+}
+
+/// The parts of `process.resourceUsage()` that go into the receipt
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct NodeUsage {
+    /// Microseconds
+    #[serde(rename = "userCPUTime")]
+    user_cpu_time: u64,
+    #[serde(rename = "systemCPUTime")]
+    system_cpu_time: u64,
+    /// Kilobytes
+    #[serde(rename = "maxRSS")]
+    max_rss: u64,
+}
+
+/// What the code sees as the time and the source of randomness in deterministic mode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Determinism {
+    /// Value of `Date.now()`, in milliseconds
+    pub clock_ms: u64,
+    /// Seeds `Math.random` and crypto randomness
+    pub seed: H256,
+}
+
+impl Determinism {
+    /// The clock is the timestamp of the block the task was created in and the
+    /// seed is the task hash, so every operator derives the same values
+    pub fn for_task(task: &Task, block_timestamp: u64) -> Self {
+        Self {
+            clock_ms: block_timestamp * 1000,
+            seed: task.hash(),
+        }
+    }
+
+    fn prelude(&self) -> String {
+        self.fill(DETERMINISM_PRELUDE)
+    }
+
+    /// Substitutes the clock and seed into a prelude script
+    pub fn fill(&self, template: &str) -> String {
+        template
+            .replace("__CLOCK_MS__", &self.clock_ms.to_string())
+            .replace("__SEED__", &hex::encode(self.seed))
+    }
+}
+
+#[derive(Default)]
+pub struct JsExecutor {
+    config: JsRuntimeConfig,
+    limits: LimitsConfig,
+    determinism: Determinism,
+}
 
 impl JsExecutor {
-    pub fn new() -> Self {
-        Self
+    pub fn new(config: JsRuntimeConfig) -> Self {
+        Self { config, limits: LimitsConfig::default(), determinism: Determinism::default() }
+    }
+
+    /// Run time and output limits, the defaults unless set
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
     }
 
-    pub async fn execute(&self, code_module: &CodeModule) -> Result<ExecutionResult> {
+    /// Clock and seed used when `deterministic` is set in the runtime config
+    pub fn with_determinism(mut self, determinism: Determinism) -> Self {
+        self.determinism = determinism;
+        self
+    }
+
+    /// Removes run directories left in `work_dir`, and unfinished installs left
+    /// in `modules_cache_dir`, by a process that was killed mid-run. Only call
+    /// this while no code is running.
+    pub fn remove_stale_run_dirs(&self) -> std::io::Result<usize> {
+        let mut removed = 0;
+        for dir in [&self.config.work_dir, &self.config.modules_cache_dir] {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let entry = entry?;
+                // TempDir names its directories .tmpXXXXXX
+                if entry.file_name().to_string_lossy().starts_with(".tmp") && entry.file_type()?.is_dir() {
+                    std::fs::remove_dir_all(entry.path())?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+
+    pub async fn execute(&self, code_module: &CodeModule) -> Result<Execution> {
         // Check for .env usage in imports and function
         if code_module.imports.contains(".env") || code_module.function.contains(".env") {
-            return Err(eyre::eyre!("Usage of .env is not allowed for security reasons"));
+            return Err(InvalidCode("usage of .env is not allowed for security reasons".to_string()).into());
         }
 
-        // Ensure temp/js directory exists
-        std::fs::create_dir_all("temp/js")?;
+        // Ensure the work directory exists
+        std::fs::create_dir_all(&self.config.work_dir)?;
         
-        let temp_dir = TempDir::new_in("temp/js")?;
+        let temp_dir = TempDir::new_in(&self.config.work_dir)?;
         let temp_path = temp_dir.path();
         // println!("Temp path: {:?}", temp_path);
         // Create package.json
         let package_json = r#"{
-            "type": "module",
-            "dependencies": {}
+            "type": "module"
         }"#;
         write(temp_path.join("package.json"), package_json).await?;
 
@@ -39,82 +174,173 @@ impl JsExecutor {
         );
         write(temp_path.join("index.js"), js_code).await?;
 
-        // Create the executor script
-        let executor_code = r#"
-import { run } from './index.js';
-
-async function main() {
-    try {
-        const result = await run();
-        console.log(JSON.stringify(result));
-    } catch (error) {
-        console.error(error);
-        process.exit(1);
-    }
-}
-
-main();
-"#;
-        write(temp_path.join("executor.js"), executor_code).await?;
+        // Create the harness; outside deterministic mode nothing is disabled
+        let determinism = if self.config.deterministic {
+            self.determinism.prelude()
+        } else {
+            "export const violations = [];\n".to_string()
+        };
+        write(temp_path.join("determinism.js"), determinism).await?;
+        write(temp_path.join("result.js"), RESULT_MODULE).await?;
+        write(temp_path.join("executor.js"), HARNESS).await?;
 
-        // Install dependencies if any are specified
-        if !code_module.imports.is_empty() {
-            Command::new("npm")
-                .arg("install")
-                .current_dir(temp_path)
-                .status()?;
+        // Link in the shared install of the pinned dependencies, if any
+        let mut lockfile_hash = None;
+        if !code_module.dependencies.is_empty() {
+            let node_modules = NpmInstaller::new(self.config.clone())
+                .node_modules(&code_module.dependencies, code_module.lockfile.as_ref(), self.config.npm_offline)
+                .await?;
+            let lockfile = node_modules.with_file_name("package-lock.json");
+            lockfile_hash = Some(H256::from(keccak256(std::fs::read(lockfile)?)));
+            symlink_dir(&std::path::absolute(node_modules)?, &temp_path.join("node_modules"))?;
         }
 
         // Execute the JS code
-        let output = Command::new("node")
-            .arg("executor.js")
-            .current_dir(temp_path)
-            .output()?; 
-
-        println!("Output: {}", String::from_utf8_lossy(&output.stdout));
-
-        if !output.status.success() {
-            return Err(eyre::eyre!(
-                "JavaScript execution failed: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
+        let mut command = Command::new(&self.config.node_path);
+        command.arg("executor.js").current_dir(temp_path);
+        if self.config.deterministic {
+            // The operator's environment, timezone and locale would leak into results
+            command.env_clear().env("TZ", "UTC").env("LANG", "C");
         }
+        let timeout = Duration::from_secs(self.limits.max_code_run_duration_secs);
+        let started = Instant::now();
+        let output = process::run(&mut command, self.limits.max_output_bytes, timeout).await?;
+        let wall_time = started.elapsed();
 
-        // Parse the output
-        let result_str = String::from_utf8(output.stdout)?;
-        let result: serde_json::Value = serde_json::from_str(&result_str)?;
-
-        // Convert the result to ExecutionResult
-        let execution_result = match result {
-            // Arrays: Only supports arrays of numbers (converted to U256) or booleans
-            serde_json::Value::Array(arr) => {
-                if arr.iter().all(|v| v.is_number()) {
-                    ExecutionResult::UintArray(
-                        arr.iter()
-                            .map(|v| U256::from(v.as_u64().unwrap()))
-                            .collect(),
-                    )
-                } else if arr.iter().all(|v| v.is_boolean()) {
-                    ExecutionResult::BoolArray(
-                        arr.iter()
-                            .map(|v| v.as_bool().unwrap())
-                            .collect(),
-                    )
-                } else {
-                    return Err(eyre::eyre!("Unsupported array type"));
-                }
+        let logs = ExecutionLogs {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            truncated: output.truncated,
+        };
+        if !logs.is_empty() {
+            debug!("Code logs:\n{}{}", logs.stdout, logs.stderr);
+        }
+
+        // The harness writes the outcome to result.json; without it the process
+        // died before the code finished
+        let result_path = temp_path.join("result.json");
+        let report = match std::fs::metadata(&result_path) {
+            Ok(metadata) if metadata.len() > self.limits.max_output_bytes as u64 => {
+                return Err(InvalidResult(format!("result is larger than {} bytes", self.limits.max_output_bytes)).into());
+            }
+            Ok(_) => serde_json::from_slice::<Report>(&std::fs::read(&result_path)?)?,
+            Err(_) => {
+                let message = format!("exited with {} without a result", output.status);
+                return Err(ExecutionFailed { message, logs }.into());
             }
-            // Numbers: Only supports unsigned integers (converted to U256)
-            serde_json::Value::Number(n) => ExecutionResult::Uint(U256::from(n.as_u64().ok_or_else(|| {
-                eyre::eyre!("Number conversion failed")
-            })?)),
-            // Booleans
-            serde_json::Value::Bool(b) => ExecutionResult::Bool(b),
-            // Everything else is unsupported
-            _ => return Err(eyre::eyre!("Unsupported return type")),
+        };
+        info!("Output: {}", serde_json::to_string(&report.outcome)?);
+
+        let execution_result = match report.outcome {
+            Outcome::Ok { value } => ExecutionResult::from_json(value)?,
+            Outcome::Violation { message } => return Err(DeterminismViolation(message).into()),
+            Outcome::Error { message } => return Err(ExecutionFailed { message, logs }.into()),
+        };
+
+        let usage = ResourceUsage {
+            wall_time_ms: wall_time.as_millis() as u64,
+            cpu_time_ms: Some((report.usage.user_cpu_time + report.usage.system_cpu_time) / 1000),
+            max_rss_bytes: Some(report.usage.max_rss * 1024),
+            heap_bytes: None,
+            instructions: None,
+            stdout_bytes: output.stdout.len() as u64,
+            stderr_bytes: output.stderr.len() as u64,
         };
 
         // TempDir will be automatically cleaned up when it goes out of scope
-        Ok(execution_result)
+        Ok(Execution {
+            result: execution_result,
+            logs,
+            runtime: RuntimeInfo { name: "node".to_string(), version: report.runtime },
+            usage,
+            lockfile_hash,
+            determinism: self.config.deterministic.then_some(self.determinism),
+        })
+    }
+}
+
+#[cfg(unix)]
+fn symlink_dir(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink_dir(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_dir(target, link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run_deterministic(function: &str) -> Result<Execution> {
+        let work_dir = TempDir::new().unwrap();
+        let config = JsRuntimeConfig {
+            work_dir: work_dir.path().to_path_buf(),
+            deterministic: true,
+            ..JsRuntimeConfig::default()
+        };
+        let code_module = CodeModule {
+            function: function.to_string(),
+            imports: String::new(),
+            dependencies: Default::default(),
+            lockfile: None,
+        };
+        let determinism = Determinism { clock_ms: 1_700_000_000_000, seed: H256::repeat_byte(0x11) };
+        JsExecutor::new(config).with_determinism(determinism).execute(&code_module).await
+    }
+
+    #[tokio::test]
+    async fn clock_is_fixed() {
+        let execution = run_deterministic("async () => [Date.now(), performance.timeOrigin, performance.now()]").await.unwrap();
+        assert_eq!(execution.result.to_response_string(), "1700000000000,1700000000000,0");
+    }
+
+    /// The async randomFill draws from the same seeded generator as randomFillSync
+    #[tokio::test]
+    async fn random_fill_is_seeded() {
+        let function = "async () => { const crypto = await import('node:crypto'); \
+            const filled = await new Promise((resolve, reject) => crypto.randomFill(new Uint8Array(8), 2, 4, (e, b) => e ? reject(e) : resolve(b))); \
+            return [...filled, ...crypto.randomFillSync(new Uint8Array(4))]; }";
+        let first = run_deterministic(function).await.unwrap().result.to_response_string();
+        let second = run_deterministic(function).await.unwrap().result.to_response_string();
+        assert_eq!(first, second);
+        // Only bytes 2 to 5 of the first buffer are filled
+        let bytes: Vec<&str> = first.split(',').collect();
+        assert_eq!(bytes.len(), 12);
+        assert_eq!([bytes[0], bytes[1], bytes[6], bytes[7]], ["0"; 4], "{}", first);
+    }
+
+    #[tokio::test]
+    async fn escapes_are_violations() {
+        for (function, api) in [
+            ("async () => (await import('node:os')).hostname()", "os.hostname"),
+            ("async () => (await import('node:vm')).runInNewContext('1')", "vm.runInNewContext"),
+            ("async () => (await import('node:v8')).getHeapStatistics()", "v8.getHeapStatistics"),
+            ("async () => process.pid", "process.pid"),
+            ("async () => process.memoryUsage()", "process.memoryUsage"),
+            ("async () => process.binding('fs')", "process.binding"),
+            ("async () => { process.exit(0) }", "process.exit"),
+            ("async () => process.cwd()", "process.cwd"),
+            ("async () => process.cpuUsage()", "process.cpuUsage"),
+            ("async () => process.resourceUsage()", "process.resourceUsage"),
+            ("async () => performance.eventLoopUtilization()", "performance.eventLoopUtilization"),
+            ("async () => performance.mark('start').startTime", "performance.mark"),
+            ("async () => (await import('node:perf_hooks')).monitorEventLoopDelay()", "perf_hooks.monitorEventLoopDelay"),
+            (
+                "async () => (await import('node:crypto')).generateKeyPairSync('ed25519').publicKey.export({ format: 'jwk' })",
+                "crypto.generateKeyPairSync",
+            ),
+            (
+                "async () => { const { generateKeyPair } = await import('node:crypto'); \
+                    return new Promise((resolve, reject) => generateKeyPair('ed25519', {}, (e, k) => e ? reject(e) : resolve(k))); }",
+                "crypto.generateKeyPair",
+            ),
+            ("async () => crypto.subtle.generateKey({ name: 'HMAC', hash: 'SHA-256' }, true, ['sign'])", "crypto.subtle.generateKey"),
+        ] {
+            let error = run_deterministic(function).await.unwrap_err();
+            let violation = error.downcast_ref::<DeterminismViolation>().unwrap_or_else(|| panic!("{function}: {error}"));
+            assert!(violation.0.starts_with(api), "{function}: {violation}");
+        }
     }
 }
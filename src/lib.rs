@@ -2,15 +2,39 @@
 // #[path = "config.rs"]
 pub mod config;
 pub mod executor;
+pub mod error_response;
+pub mod dry_run;
 pub mod listener;
 pub mod arweave;
+pub mod arweave_tx;
+pub mod arweave_mock;
+#[cfg(test)]
+mod chain_mock;
 pub mod js_executor;
+pub mod npm;
+pub mod process;
+pub mod receipt;
+pub mod registry;
+#[cfg(feature = "quickjs")]
+pub mod quickjs_executor;
 pub mod contract;
-pub mod respond;    
+pub mod respond;
+pub mod registration;
+pub mod health;
+pub mod aggregator;
+pub mod aggregator_server;
+pub mod bls;
+pub mod gossip;
+pub mod task_store;
+pub mod retry;
+pub mod shutdown;
+pub mod metrics;
+pub mod admin;
+pub mod telemetry;
 
 // Re-export public types
 pub use config::Config;
 pub use executor::Executor;
 pub use listener::EventListener;
-pub use arweave::ArweaveClient; 
+pub use arweave::ArweaveClient;
 pub use contract::ContractClient;
\ No newline at end of file
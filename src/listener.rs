@@ -1,21 +1,75 @@
 use eyre::Result;
 use crate::config::Config;
 use crate::executor::Executor;
+use crate::metrics::METRICS;
+use crate::retry::unix_now;
+use crate::health::{HealthChecker, HealthReport};
+use crate::respond::Task;
+use crate::task_store::TaskStore;
 // use ethers::abi::AbiDecode;
 use ethers::providers::Provider;
-use ethers::types::{H256, U256, U64};
+use ethers::types::{U256, U64};
 use ethers::prelude::*;
 use ethers::abi::RawLog;
 use colored::*;
-use log::{info, error, warn};
+use tracing::{info, error, warn};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
 pub struct EventListener {
     provider: Provider<Http>,
     contract_address: String,
     executor: Executor,
     last_processed_block: U64,
+    poll_interval: tokio::time::Duration,
+    health: HealthChecker,
+    health_interval: tokio::time::Duration,
+    last_health_check: Option<tokio::time::Instant>,
+    /// Problems found by the last health check; tasks are skipped while set
+    degraded: Option<Vec<String>>,
+    /// Cancelled to stop taking tasks and drain the one in flight
+    shutdown: CancellationToken,
+    shutdown_timeout: tokio::time::Duration,
+    status: Arc<ListenerStatus>,
     // task_created_block: U256,
 }
 
+/// Listener state shared with the admin API
+#[derive(Default)]
+pub struct ListenerStatus {
+    paused: AtomicBool,
+    last_report: Mutex<Option<HealthReport>>,
+    last_processed_block: AtomicU64,
+    lag_blocks: AtomicU64,
+}
+
+impl ListenerStatus {
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Pauses or resumes taking tasks, returning whether it was paused before
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.paused.swap(paused, Ordering::SeqCst)
+    }
+
+    /// The latest periodic health check, `None` before the first one
+    pub fn last_report(&self) -> Option<HealthReport> {
+        self.last_report.lock().unwrap().clone()
+    }
+
+    pub fn last_processed_block(&self) -> u64 {
+        self.last_processed_block.load(Ordering::SeqCst)
+    }
+
+    /// Blocks between the chain head and the last processed block, as of the last poll
+    pub fn lag_blocks(&self) -> u64 {
+        self.lag_blocks.load(Ordering::SeqCst)
+    }
+}
+
 #[derive(Debug, Clone, EthEvent)]
 #[ethevent(abi = "ComputeRequestCreated(uint256,string,string,uint256,uint256)")]
 pub struct ComputeRequestCreated {
@@ -31,6 +85,32 @@ pub struct ComputeRequestCreated {
     pub task_created_block: U256,
 }
 
+#[derive(Debug, Clone, EthEvent)]
+#[ethevent(abi = "TaskResponded(uint256,(string,uint256,string,uint32),string,address)")]
+pub struct TaskResponded {
+    #[ethevent(indexed, name = "taskIndex")]
+    pub task_index: U256,
+    /// (codeArweaveUri, responseCount, language, taskCreatedBlock)
+    pub task: (String, U256, String, u32),
+    #[ethevent(name = "responseString")]
+    pub response_string: String,
+    pub operator: Address,
+}
+
+#[derive(Debug, Clone, EthEvent)]
+#[ethevent(abi = "TaskRespondedWithBLS(uint256,(string,uint256,string,uint32),string,uint256)")]
+pub struct TaskRespondedWithBLS {
+    #[ethevent(indexed, name = "taskIndex")]
+    pub task_index: U256,
+    /// (codeArweaveUri, responseCount, language, taskCreatedBlock)
+    pub task: (String, U256, String, u32),
+    #[ethevent(name = "responseString")]
+    pub response_string: String,
+    /// Bit i is set when `blsOperators[i]` signed
+    #[ethevent(name = "signerBitmap")]
+    pub signer_bitmap: U256,
+}
+
 #[derive(Debug)]
 pub struct ComputeRequest {
     pub request_id: U256,
@@ -40,86 +120,321 @@ pub struct ComputeRequest {
     pub task_created_block: U256,
 }
 
+impl ComputeRequest {
+    pub fn task(&self) -> Task {
+        Task {
+            code_arweave_uri: self.code_json.clone(),
+            language: self.code_lang.clone(),
+            response_count: self.node_count,
+            task_created_block: self.task_created_block.as_u32(),
+            request_id: self.request_id.as_u32(),
+        }
+    }
+}
+
 impl EventListener {
     pub async fn new(config1: Config) -> Result<Self> {
         let provider = Provider::<Http>::connect(&config1.rpc_url).await;
         let contract_address = config1.contract_address.clone();
+        let poll_interval = tokio::time::Duration::from_millis(config1.limits.poll_interval_ms);
+        let health = HealthChecker::new(&config1).await?;
+        let health_interval = tokio::time::Duration::from_secs(config1.health.check_interval_secs);
+        let shutdown_timeout = tokio::time::Duration::from_secs(config1.limits.shutdown_timeout_secs);
         
-        let executor = Executor::new(config1);
+        let store = Arc::new(TaskStore::open(&config1.store.path)?);
+        let executor = Executor::new(config1).with_store(store);
         Ok(Self { 
             provider,
             contract_address,
             executor,
             last_processed_block: U64::zero(),
+            poll_interval,
+            health,
+            health_interval,
+            last_health_check: None,
+            degraded: None,
+            shutdown: CancellationToken::new(),
+            shutdown_timeout,
+            status: Arc::new(ListenerStatus::default()),
             // task_created_block: U256::zero(),
         })
     }
 
+    /// Stops the listener once `token` is cancelled, see `shutdown::listen_for_signals`
+    pub fn with_shutdown(mut self, token: CancellationToken) -> Self {
+        self.shutdown = token;
+        self
+    }
+
+    pub fn status(&self) -> Arc<ListenerStatus> {
+        self.status.clone()
+    }
+
+    pub fn store(&self) -> Option<Arc<TaskStore>> {
+        self.executor.store().cloned()
+    }
+
+    /// Flushes the task store; call once the listener has stopped
+    pub fn close(&self) {
+        if let Some(store) = self.executor.store() {
+            if let Err(e) = store.checkpoint() {
+                warn!("{}", format!("Failed to flush task store: {}", e).yellow());
+            }
+        }
+    }
+
+    /// Runs the health check and updates whether tasks are taken
+    pub async fn check_health(&mut self) -> HealthReport {
+        let report = self.health.check().await;
+        self.last_health_check = Some(tokio::time::Instant::now());
+
+        if report.is_healthy() {
+            if self.degraded.take().is_some() {
+                info!("{}", "Health check passed, resuming task execution".green());
+            }
+        } else {
+            let problems = report.problems();
+            if self.degraded.as_ref() != Some(&problems) {
+                warn!("{}", "Health check failed, not taking tasks until resolved:".yellow());
+                for problem in &problems {
+                    warn!("  {}", problem.yellow());
+                }
+            }
+            self.degraded = Some(problems);
+        }
+        *self.status.last_report.lock().unwrap() = Some(report.clone());
+        report
+    }
+
     pub async fn start_listening(&mut self) -> Result<()> {
-        let address: Address = self.contract_address.parse()?;
-        let event_signature = "ComputeRequestCreated(uint256,string,string,uint256,uint256)";
-        let topic = H256::from(ethers::utils::keccak256(event_signature.as_bytes()));
+        // A restart or an earlier replay keeps its place instead of skipping to the head
+        if self.last_processed_block.is_zero() {
+            match self.provider.get_block_number().await {
+                Ok(block) => {
+                    self.last_processed_block = block;
+                    self.status.last_processed_block.store(block.as_u64(), Ordering::SeqCst);
+                },
+                Err(e) => {
+                    error!("{}", format!("Failed to get initial block number: {:?}", e).red());
+                    return Err(eyre::eyre!("Failed to get initial block number: {}", e));
+                }
+            }
+        }
+        info!("Starting to listen from block: {}", self.last_processed_block.to_string().cyan());
 
-        match self.provider.get_block_number().await {
-            Ok(block) => {
-                self.last_processed_block = block;
-                println!("Starting to listen from block: {}", self.last_processed_block.to_string().cyan());
-            },
+        let mut head_failures = 0u32;
+        while !self.shutdown.is_cancelled() {
+            let health_due = self
+                .last_health_check
+                .is_none_or(|checked| checked.elapsed() >= self.health_interval);
+            if health_due {
+                self.check_health().await;
+            }
+
+            // Keep the processed block and poll again, backing off while the RPC is down
+            let current_block = match self.provider.get_block_number().await {
+                Ok(block) => {
+                    head_failures = 0;
+                    block
+                }
+                Err(e) => {
+                    head_failures = head_failures.saturating_add(1);
+                    let delay = self.poll_interval.saturating_mul(1 << head_failures.min(6));
+                    warn!("{}", format!("Failed to get the current block, retrying in {:?}: {}", delay, e).yellow());
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = self.shutdown.cancelled() => {}
+                    }
+                    continue;
+                }
+            };
+            let lag = current_block.saturating_sub(self.last_processed_block).as_u64();
+            self.status.lag_blocks.store(lag, Ordering::SeqCst);
+            METRICS.listener_lag_blocks.set(lag as i64);
+
+            // While paused the processed block stays put, so the backlog is
+            // picked up on resume
+            let paused = self.status.is_paused();
+            if current_block > self.last_processed_block && !paused {
+                // On an RPC error the processed block stays put, so the same
+                // range is read again on the next poll
+                match self.process_range(self.last_processed_block + 1, current_block).await {
+                    Ok(()) => {
+                        self.last_processed_block = current_block;
+                        self.status.lag_blocks.store(0, Ordering::SeqCst);
+                        self.status.last_processed_block.store(current_block.as_u64(), Ordering::SeqCst);
+                        METRICS.last_processed_block.set(current_block.as_u64() as i64);
+                        METRICS.listener_lag_blocks.set(0);
+                    }
+                    Err(e) => error!("{}", format!("{:?}", e).red()),
+                }
+            }
+            if self.degraded.is_none() && !paused {
+                self.process_due_retries().await;
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(self.poll_interval) => {}
+                _ = self.shutdown.cancelled() => {}
+            }
+        }
+        info!("{}", "Stopped listening for compute requests".yellow());
+        Ok(())
+    }
+
+    /// Processes the compute requests emitted between `from_block` and `to_block`
+    /// (the current block if `None`) once, then returns
+    pub async fn replay(&mut self, from_block: U64, to_block: Option<U64>) -> Result<()> {
+        let to_block = match to_block {
+            Some(block) => block,
+            None => self.provider.get_block_number().await?,
+        };
+        info!("Replaying blocks {} to {}", from_block.to_string().cyan(), to_block.to_string().cyan());
+        self.check_health().await;
+        self.process_range(from_block, to_block).await?;
+        self.last_processed_block = to_block;
+        Ok(())
+    }
+
+    /// Runs the failed tasks whose retry backoff has elapsed
+    async fn process_due_retries(&self) {
+        let Some(store) = self.executor.store() else {
+            return;
+        };
+        let due = match store.due_retries(unix_now()) {
+            Ok(due) => due,
             Err(e) => {
-                error!("{}", format!("Failed to get initial block number: {:?}", e).red());
-                return Err(eyre::eyre!("Failed to get initial block number: {}", e));
+                warn!("{}", format!("Failed to read due retries: {}", e).yellow());
+                return;
+            }
+        };
+        for record in due {
+            if self.shutdown.is_cancelled() {
+                return;
+            }
+            info!(
+                "{}",
+                format!("Retrying task {} (attempt {})", record.task_index, record.attempts + 1).yellow()
+            );
+            match self.drain(record.task_index, self.executor.run_task(record.task())).await {
+                Some(Ok(_)) => info!("{}", format!("✓ Retry of task {} completed", record.task_index).green()),
+                Some(Err(e)) => error!("{}", format!("✗ Retry of task {} failed: {:?}", record.task_index, e).red()),
+                None => return,
             }
         }
+    }
 
-        loop {
-            let current_block = self.provider.get_block_number().await?;
-            
-            if current_block > self.last_processed_block {
-                let filter = Filter::new()
-                    .address(address)
-                    .topic0(topic)
-                    .from_block(self.last_processed_block + 1)
-                    .to_block(current_block);
-
-                if let Ok(logs) = self.provider.get_logs(&filter).await {
-                    for log in logs {
-                        info!("{}", "\nNew ComputeRequestCreated event detected!".green().bold());
-                        info!("Transaction hash: {}", format!("{:?}", log.transaction_hash).cyan());
-                        
-                        if let Ok(compute_request) = self.parse_compute_request_event(&log) {
-                            // info!("{}", "Parsed Compute Request:".yellow());
-                            info!("Code Ar Txn Id: {}", compute_request.code_json.cyan());
-                            info!("Code Language: {}", compute_request.code_lang.cyan());
-                            info!("Node Count: {}", compute_request.node_count.to_string().cyan());
-                            info!("Task Created Block: {}", compute_request.task_created_block.to_string().cyan());
-
-                            match self.executor.execute(
-                                compute_request.request_id,
-                                compute_request.code_lang.to_string(),
-                                compute_request.code_json,
-                                compute_request.node_count,
-                                compute_request.task_created_block,
-                            ).await {
-                                Ok(_) => {
-                                    info!("{}", format!("✓ Execution completed successfully for request ID: {}", 
-                                        compute_request.request_id).green());
-                                },
-                                Err(e) => {
-                                    error!("{}", format!("✗ Execution failed: {:?}", e).red());
-                                    warn!("{}", "continuing...".yellow());
-                                }
-                            }
-                        } else {
-                            error!("{}", "Failed to parse compute request event".red());
-                        }
+    /// Awaits a task's execution. Once shutdown is requested it gets at most
+    /// `limits.shutdown_timeout_secs` to finish; after that it is dropped, queued
+    /// to run again on the next start, and `None` is returned.
+    async fn drain(&self, task_index: u32, execution: impl Future<Output = Result<()>>) -> Option<Result<()>> {
+        tokio::pin!(execution);
+        tokio::select! {
+            outcome = &mut execution => return Some(outcome),
+            _ = self.shutdown.cancelled() => {}
+        }
+        info!(
+            "{}",
+            format!(
+                "Waiting up to {}s for task {} to finish",
+                self.shutdown_timeout.as_secs(),
+                task_index
+            )
+            .yellow()
+        );
+        match tokio::time::timeout(self.shutdown_timeout, execution).await {
+            Ok(outcome) => Some(outcome),
+            Err(_) => {
+                warn!("{}", format!("Task {} did not finish before the shutdown deadline", task_index).yellow());
+                if let Some(store) = self.executor.store() {
+                    if let Err(e) = store.defer(task_index, unix_now(), "interrupted by shutdown") {
+                        warn!("{}", format!("Failed to update task store: {}", e).yellow());
                     }
                 }
-                
-                self.last_processed_block = current_block;
+                None
             }
+        }
+    }
+
+    /// Records a task that was not run so it is picked up with the due retries,
+    /// or on the next start
+    fn defer(&self, task: &Task, reason: &str) {
+        let Some(store) = self.executor.store() else {
+            return;
+        };
+        let recorded = store.record_discovered(task).and_then(|started| {
+            if started {
+                store.defer(task.request_id, unix_now(), reason)?;
+            }
+            Ok(started)
+        });
+        match recorded {
+            Ok(true) => info!("Task {} queued to run later", task.request_id),
+            Ok(false) => info!("Task {}: already settled, leaving it as recorded", task.request_id),
+            Err(e) => warn!("{}", format!("Failed to update task store: {}", e).yellow()),
+        }
+    }
 
-            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+    async fn process_range(&mut self, from_block: U64, to_block: U64) -> Result<()> {
+        let address: Address = self.contract_address.parse()?;
+        let filter = Filter::new()
+            .address(address)
+            .topic0(ComputeRequestCreated::signature())
+            .from_block(from_block)
+            .to_block(to_block);
+
+        let logs = self.provider.get_logs(&filter).await.map_err(|e| {
+            eyre::eyre!("Failed to read compute requests in blocks {} to {}: {}", from_block, to_block, e)
+        })?;
+        for log in logs {
+            info!("{}", "\nNew ComputeRequestCreated event detected!".green().bold());
+            METRICS.events_seen.inc();
+            info!("Transaction hash: {}", format!("{:?}", log.transaction_hash).cyan());
+            
+            if let Ok(compute_request) = self.parse_compute_request_event(&log) {
+                if self.shutdown.is_cancelled() {
+                    self.defer(&compute_request.task(), "not started before shutdown");
+                    continue;
+                }
+                // info!("{}", "Parsed Compute Request:".yellow());
+                info!("Code Ar Txn Id: {}", compute_request.code_json.cyan());
+                info!("Code Language: {}", compute_request.code_lang.cyan());
+                info!("Node Count: {}", compute_request.node_count.to_string().cyan());
+                info!("Task Created Block: {}", compute_request.task_created_block.to_string().cyan());
+
+                // Queued rather than skipped: due retries only run once the
+                // operator is healthy again
+                if let Some(problems) = &self.degraded {
+                    let reason = format!("operator is degraded ({})", problems.join("; "));
+                    warn!("{}", format!("Deferring request ID {}: {}", compute_request.request_id, reason).yellow());
+                    self.defer(&compute_request.task(), &reason);
+                    continue;
+                }
+
+                let request_id = compute_request.request_id.as_u32();
+                let execution = self.executor.execute(
+                    compute_request.request_id,
+                    compute_request.code_lang.to_string(),
+                    compute_request.code_json,
+                    compute_request.node_count,
+                    compute_request.task_created_block,
+                );
+                match self.drain(request_id, execution).await {
+                    None => {}
+                    Some(Ok(_)) => {
+                        info!("{}", format!("✓ Execution completed successfully for request ID: {}", 
+                            compute_request.request_id).green());
+                    },
+                    Some(Err(e)) => {
+                        error!("{}", format!("✗ Execution failed: {:?}", e).red());
+                        warn!("{}", "continuing...".yellow());
+                    }
+                }
+            } else {
+                error!("{}", "Failed to parse compute request event".red());
+            }
         }
+        Ok(())
     }
 
     fn parse_compute_request_event(&self, log: &Log) -> Result<ComputeRequest> {
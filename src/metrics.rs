@@ -0,0 +1,130 @@
+//! Prometheus metrics for the operator, served as text on `/metrics`.
+
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::get;
+use axum::Router;
+use colored::*;
+use ethers::types::{TransactionReceipt, U256};
+use eyre::Result;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use std::sync::LazyLock;
+use tokio::net::TcpListener;
+use tracing::info;
+
+pub struct Metrics {
+    registry: Registry,
+    /// `ComputeRequestCreated` events picked up by the listener
+    pub events_seen: IntCounter,
+    /// Code runs by language and outcome (`success` or `failure`)
+    pub tasks_executed: IntCounterVec,
+    pub execution_duration: HistogramVec,
+    pub arweave_fetch_duration: Histogram,
+    pub arweave_fetch_errors: IntCounter,
+    /// Submissions by path and status: `sent`, then `confirmed` or `reverted` once
+    /// mined, or `accepted` once the aggregator took an off-chain response
+    pub submissions: IntCounterVec,
+    pub gas_used: IntCounter,
+    /// Gas spent on submissions in gwei, as gas used times the effective gas price
+    pub gas_spent_gwei: IntCounter,
+    /// Blocks between the chain head and the last processed block
+    pub listener_lag_blocks: IntGauge,
+    pub last_processed_block: IntGauge,
+}
+
+/// The process-wide metrics
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("function_layer".to_string()), None)
+            .expect("the namespace is valid");
+        let metrics = Self {
+            events_seen: IntCounter::new("events_seen_total", "ComputeRequestCreated events seen").unwrap(),
+            tasks_executed: IntCounterVec::new(
+                Opts::new("tasks_executed_total", "Code runs by language and outcome"),
+                &["language", "outcome"],
+            )
+            .unwrap(),
+            execution_duration: HistogramVec::new(
+                HistogramOpts::new("execution_duration_seconds", "Time spent running task code")
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+                &["language"],
+            )
+            .unwrap(),
+            arweave_fetch_duration: Histogram::with_opts(
+                HistogramOpts::new("arweave_fetch_duration_seconds", "Time spent fetching code from Arweave")
+                    .buckets(vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0]),
+            )
+            .unwrap(),
+            arweave_fetch_errors: IntCounter::new("arweave_fetch_errors_total", "Failed Arweave fetches").unwrap(),
+            submissions: IntCounterVec::new(
+                Opts::new("submissions_total", "Responses by path (chain, gossip or bls) and status"),
+                &["path", "status"],
+            )
+            .unwrap(),
+            gas_used: IntCounter::new("gas_used_total", "Gas used by submissions").unwrap(),
+            gas_spent_gwei: IntCounter::new("gas_spent_gwei_total", "Gas fees paid for submissions, in gwei")
+                .unwrap(),
+            listener_lag_blocks: IntGauge::new("listener_lag_blocks", "Blocks the listener is behind the chain head")
+                .unwrap(),
+            last_processed_block: IntGauge::new("last_processed_block", "Last block scanned for compute requests")
+                .unwrap(),
+            registry,
+        };
+
+        let collectors: Vec<Box<dyn prometheus::core::Collector>> = vec![
+            Box::new(metrics.events_seen.clone()),
+            Box::new(metrics.tasks_executed.clone()),
+            Box::new(metrics.execution_duration.clone()),
+            Box::new(metrics.arweave_fetch_duration.clone()),
+            Box::new(metrics.arweave_fetch_errors.clone()),
+            Box::new(metrics.submissions.clone()),
+            Box::new(metrics.gas_used.clone()),
+            Box::new(metrics.gas_spent_gwei.clone()),
+            Box::new(metrics.listener_lag_blocks.clone()),
+            Box::new(metrics.last_processed_block.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).expect("metric names are unique");
+        }
+        metrics
+    }
+
+    /// Counts a mined `respondToTask` as confirmed or reverted, along with the gas it cost
+    pub fn record_receipt(&self, receipt: &TransactionReceipt) {
+        let reverted = receipt.status != Some(1u64.into());
+        self.submissions
+            .with_label_values(&["chain", if reverted { "reverted" } else { "confirmed" }])
+            .inc();
+
+        let gas_used = receipt.gas_used.unwrap_or_default();
+        self.gas_used.inc_by(gas_used.low_u64());
+        if let Some(price) = receipt.effective_gas_price {
+            self.gas_spent_gwei.inc_by((gas_used * price / U256::exp10(9)).low_u64());
+        }
+    }
+
+    /// Renders every metric in the Prometheus text format
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("encoding to a Vec does not fail");
+        String::from_utf8(buffer).expect("the text format is UTF-8")
+    }
+}
+
+/// Serves `/metrics` on `listen_addr` until the process exits
+pub async fn serve(listen_addr: &str) -> Result<()> {
+    let listener = TcpListener::bind(listen_addr).await?;
+    info!("Serving metrics on {}", format!("http://{}/metrics", listener.local_addr()?).cyan());
+    let app = Router::new().route(
+        "/metrics",
+        get(|| async { ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], METRICS.encode()) }),
+    );
+    axum::serve(listener, app).await?;
+    Ok(())
+}
@@ -0,0 +1,537 @@
+//! npm dependencies of JS code modules.
+//!
+//! Every dependency is pinned to an exact version and the integrity hash of its
+//! tarball, and the module ships the `package-lock.json` that resolves the whole
+//! tree, transitive dependencies included. Packages are installed from npm's
+//! cache (or a configured mirror) with `npm ci` and install scripts disabled,
+//! and the installed tree is checked against the lockfile before use: a package
+//! that declares install scripts is refused outright. Installed `node_modules`
+//! trees are shared between runs in a cache keyed by the hash of the
+//! dependency list and the lockfile. Cache entries are made read-only once
+//! installed, and their contents are hashed again on every use so an entry
+//! that changed since it was checked is installed afresh.
+
+use crate::config::JsRuntimeConfig;
+use crate::js_executor::NpmInstallError;
+use crate::process;
+use ethers::utils::hex;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tempfile::TempDir;
+use thiserror::Error;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// How much of npm's output is kept for error messages
+const MAX_NPM_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// Holds the content hash of a cache entry, next to its `node_modules`
+const CONTENT_HASH_FILE: &str = "content.sha256";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Dependency {
+    /// Exact version, e.g. `4.17.21`
+    pub version: String,
+    /// Integrity of the package tarball as npm reports it, e.g. `sha512-...`
+    pub integrity: String,
+}
+
+/// Dependencies of a code module by package name
+pub type Dependencies = BTreeMap<String, Dependency>;
+
+/// The dependencies are not pinned, or what got installed is not what was pinned
+#[derive(Error, Debug)]
+pub enum DependencyError {
+    #[error("invalid package name {0:?}")]
+    InvalidName(String),
+    #[error("{name}@{version} is not pinned to an exact version")]
+    Unpinned { name: String, version: String },
+    #[error("{0} has no integrity hash, expected sha512-<base64>")]
+    MissingIntegrity(String),
+    #[error("{name} resolved to {actual}, expected {expected}")]
+    VersionMismatch { name: String, expected: String, actual: String },
+    #[error("{name}@{version} has integrity {actual}, expected {expected}")]
+    IntegrityMismatch { name: String, version: String, expected: String, actual: String },
+    #[error("{0} has install scripts, which are not allowed")]
+    InstallScript(String),
+    #[error("code with dependencies must include the package-lock.json that resolves them")]
+    MissingLockfile,
+    #[error("invalid lockfile: {0}")]
+    InvalidLockfile(String),
+    #[error("{0} is not in the lockfile")]
+    NotLocked(String),
+}
+
+/// Checks that every dependency names a package, an exact version and an
+/// integrity hash, and that `lockfile` pins the same packages and every
+/// package they depend on
+pub fn check_pinned(dependencies: &Dependencies, lockfile: Option<&serde_json::Value>) -> Result<(), DependencyError> {
+    for (name, dependency) in dependencies {
+        if !is_package_name(name) {
+            return Err(DependencyError::InvalidName(name.clone()));
+        }
+        if !is_exact_version(&dependency.version) {
+            return Err(DependencyError::Unpinned { name: name.clone(), version: dependency.version.clone() });
+        }
+        if !is_integrity(&dependency.integrity) {
+            return Err(DependencyError::MissingIntegrity(name.clone()));
+        }
+    }
+    if !dependencies.is_empty() {
+        check_lockfile(&parse_lockfile(lockfile)?, dependencies)?;
+    }
+    Ok(())
+}
+
+fn parse_lockfile(lockfile: Option<&serde_json::Value>) -> Result<Lockfile, DependencyError> {
+    let lockfile = lockfile.ok_or(DependencyError::MissingLockfile)?;
+    let lockfile = Lockfile::deserialize(lockfile).map_err(|e| DependencyError::InvalidLockfile(e.to_string()))?;
+    // Version 1 has no `packages`, only the nested `dependencies` npm 6 wrote
+    if lockfile.lockfile_version < 2 {
+        return Err(DependencyError::InvalidLockfile(format!("lockfileVersion {} is not supported, expected 2 or 3", lockfile.lockfile_version)));
+    }
+    Ok(lockfile)
+}
+
+/// The root of the lockfile must ask for exactly `dependencies`, and every
+/// package in the tree must be pinned the way `dependencies` are
+fn check_lockfile(lockfile: &Lockfile, dependencies: &Dependencies) -> Result<(), DependencyError> {
+    let root = lockfile.packages.get("").map(|root| &root.dependencies);
+    for (name, dependency) in dependencies {
+        if root.and_then(|root| root.get(name)) != Some(&dependency.version) {
+            return Err(DependencyError::NotLocked(name.clone()));
+        }
+        let locked = lockfile.packages.get(&format!("node_modules/{}", name)).ok_or_else(|| DependencyError::NotLocked(name.clone()))?;
+        check_locked(name, locked, &dependency.version, &dependency.integrity)?;
+    }
+    if let Some(name) = root.and_then(|root| root.keys().find(|name| !dependencies.contains_key(*name))) {
+        return Err(DependencyError::InvalidLockfile(format!("{} is not one of the dependencies", name)));
+    }
+    for (path, package) in lockfile.packages.iter().filter(|(path, _)| !path.is_empty()) {
+        let name = package_name(path);
+        if package.link {
+            return Err(DependencyError::InvalidLockfile(format!("{} is a link", name)));
+        }
+        let version = package.version.as_deref().unwrap_or_default();
+        if !is_exact_version(version) {
+            return Err(DependencyError::Unpinned { name: name.to_string(), version: version.to_string() });
+        }
+        if !package.integrity.as_deref().is_some_and(is_integrity) {
+            return Err(DependencyError::MissingIntegrity(name.to_string()));
+        }
+        if package.has_install_script {
+            return Err(DependencyError::InstallScript(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn check_locked(name: &str, locked: &LockedPackage, version: &str, integrity: &str) -> Result<(), DependencyError> {
+    let locked_version = locked.version.clone().unwrap_or_default();
+    if locked_version != version {
+        return Err(DependencyError::VersionMismatch {
+            name: name.to_string(),
+            expected: version.to_string(),
+            actual: locked_version,
+        });
+    }
+    let locked_integrity = locked.integrity.clone().unwrap_or_default();
+    if locked_integrity != integrity {
+        return Err(DependencyError::IntegrityMismatch {
+            name: name.to_string(),
+            version: locked_version,
+            expected: integrity.to_string(),
+            actual: locked_integrity,
+        });
+    }
+    Ok(())
+}
+
+/// The package name in a lockfile path like `node_modules/a/node_modules/@scope/b`
+fn package_name(path: &str) -> &str {
+    path.rsplit_once("node_modules/").map_or(path, |(_, name)| name)
+}
+
+/// `name` or `@scope/name`, lowercase and URL safe, as npm requires
+fn is_package_name(name: &str) -> bool {
+    let valid_part = |part: &str| {
+        !part.is_empty()
+            && !part.starts_with(['.', '_'])
+            && part.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-._~".contains(&b))
+    };
+    match name.strip_prefix('@') {
+        Some(scoped) => matches!(scoped.split_once('/'), Some((scope, name)) if valid_part(scope) && valid_part(name)),
+        None => name.len() <= 214 && valid_part(name),
+    }
+}
+
+/// `MAJOR.MINOR.PATCH` with an optional prerelease and build, and nothing a range could match
+fn is_exact_version(version: &str) -> bool {
+    let (version, build) = version.split_once('+').unwrap_or((version, ""));
+    let (core, prerelease) = version.split_once('-').unwrap_or((version, ""));
+    let identifiers_ok = |s: &str| s.split('.').all(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-'));
+    let numbers: Vec<&str> = core.split('.').collect();
+    numbers.len() == 3
+        && numbers.iter().all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        && (prerelease.is_empty() || identifiers_ok(prerelease))
+        && (build.is_empty() || identifiers_ok(build))
+}
+
+fn is_integrity(integrity: &str) -> bool {
+    match integrity.split_once('-') {
+        Some(("sha512", hash)) => {
+            !hash.is_empty() && hash.bytes().all(|b| b.is_ascii_alphanumeric() || b"+/=".contains(&b))
+        }
+        _ => false,
+    }
+}
+
+/// The package-lock.json written for `npm ci`
+fn lockfile_json(lockfile: &serde_json::Value) -> Result<String> {
+    Ok(serde_json::to_string_pretty(lockfile)?)
+}
+
+/// Identifies a set of dependencies and the tree they resolve to in the
+/// `node_modules` cache
+pub fn cache_key(dependencies: &Dependencies, lockfile_json: &str) -> String {
+    let mut hasher = Sha256::new();
+    for (name, dependency) in dependencies {
+        hasher.update(format!("{}@{} {}\n", name, dependency.version, dependency.integrity));
+    }
+    hasher.update(Sha256::digest(lockfile_json));
+    hex::encode(hasher.finalize())
+}
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default, rename = "lockfileVersion")]
+    lockfile_version: u32,
+    #[serde(default)]
+    packages: BTreeMap<String, LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    version: Option<String>,
+    integrity: Option<String>,
+    /// Only read for the root package, `""`
+    #[serde(default)]
+    dependencies: BTreeMap<String, String>,
+    /// Skipped by npm on platforms the package doesn't support
+    #[serde(default)]
+    optional: bool,
+    /// A symlink to a local directory rather than a package
+    #[serde(default)]
+    link: bool,
+    #[serde(default, rename = "hasInstallScript")]
+    has_install_script: bool,
+}
+
+pub struct NpmInstaller {
+    config: JsRuntimeConfig,
+}
+
+impl NpmInstaller {
+    pub fn new(config: JsRuntimeConfig) -> Self {
+        Self { config }
+    }
+
+    /// Returns the `node_modules` directory holding `dependencies`, installing
+    /// the tree `lockfile` resolves them to on first use. `offline` overrides
+    /// `npm_offline`, e.g. to fill the npm cache from the registry.
+    pub async fn node_modules(
+        &self,
+        dependencies: &Dependencies,
+        lockfile: Option<&serde_json::Value>,
+        offline: bool,
+    ) -> Result<PathBuf> {
+        check_pinned(dependencies, lockfile)?;
+        let lockfile = lockfile.ok_or(DependencyError::MissingLockfile)?;
+        let lockfile_json = lockfile_json(lockfile)?;
+        let dir = self.config.modules_cache_dir.join(cache_key(dependencies, &lockfile_json));
+        let node_modules = dir.join("node_modules");
+        if node_modules.is_dir() {
+            match check_content(&dir) {
+                Ok(()) => return Ok(node_modules),
+                Err(e) => {
+                    warn!("Reinstalling {}: {:#}", dir.display(), e);
+                    set_tree_writable(&dir, true)?;
+                    std::fs::remove_dir_all(&dir)?;
+                }
+            }
+        }
+
+        // Install next to the cache entry and move it in once it checks out, so
+        // an entry that exists is always complete
+        std::fs::create_dir_all(&self.config.modules_cache_dir)?;
+        let staging = TempDir::new_in(&self.config.modules_cache_dir)?;
+        self.install(staging.path(), dependencies, &lockfile_json, offline).await?;
+        verify_installed(&staging.path().join("node_modules/.package-lock.json"), &parse_lockfile(Some(lockfile))?)?;
+        std::fs::write(staging.path().join(CONTENT_HASH_FILE), content_hash(staging.path())?)?;
+        match std::fs::rename(staging.path(), &dir) {
+            Ok(()) => set_tree_writable(&dir, false)?,
+            // Another run installed the same dependencies first
+            Err(_) if node_modules.is_dir() => {}
+            Err(e) => return Err(e.into()),
+        }
+        info!("Installed {} dependencies into {}", dependencies.len(), dir.display());
+        Ok(node_modules)
+    }
+
+    async fn install(&self, dir: &Path, dependencies: &Dependencies, lockfile_json: &str, offline: bool) -> Result<()> {
+        let versions: BTreeMap<&str, &str> = dependencies
+            .iter()
+            .map(|(name, dependency)| (name.as_str(), dependency.version.as_str()))
+            .collect();
+        let package_json = serde_json::json!({ "private": true, "dependencies": versions });
+        std::fs::write(dir.join("package.json"), serde_json::to_string_pretty(&package_json)?)?;
+        std::fs::write(dir.join("package-lock.json"), lockfile_json)?;
+
+        // `npm ci` installs exactly what the lockfile says and fails if it
+        // doesn't match package.json, where `npm install` would resolve ranges
+        let mut command = Command::new(&self.config.npm_path);
+        command
+            .args(["ci", "--ignore-scripts", "--no-audit", "--no-fund", "--cache"])
+            .arg(std::path::absolute(&self.config.npm_cache_dir)?)
+            .current_dir(dir);
+        if offline {
+            command.arg("--offline");
+        }
+        if let Some(registry) = &self.config.npm_registry {
+            command.args(["--registry", registry]);
+        }
+        let timeout = Duration::from_secs(self.config.npm_install_timeout_secs);
+        let output = process::run(&mut command, MAX_NPM_OUTPUT_BYTES, timeout)
+            .await
+            .map_err(|e| NpmInstallError(e.to_string()))?;
+        if !output.status.success() {
+            return Err(NpmInstallError(String::from_utf8_lossy(&output.stderr).trim().to_string()).into());
+        }
+        Ok(())
+    }
+}
+
+/// Every file, directory and symlink under `dir`, without following symlinks,
+/// in a stable order
+fn tree_entries(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        for entry in std::fs::read_dir(&current)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            }
+            entries.push(entry.path());
+        }
+    }
+    entries.sort();
+    Ok(entries)
+}
+
+/// Hash of the paths and contents of a cache entry, apart from the hash itself
+fn content_hash(dir: &Path) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    for path in tree_entries(dir)? {
+        let relative = path.strip_prefix(dir).expect("entries are under dir");
+        if relative == Path::new(CONTENT_HASH_FILE) {
+            continue;
+        }
+        let file_type = std::fs::symlink_metadata(&path)?.file_type();
+        let (kind, contents) = if file_type.is_symlink() {
+            ("link", std::fs::read_link(&path)?.to_string_lossy().into_owned().into_bytes())
+        } else if file_type.is_dir() {
+            ("dir", Vec::new())
+        } else {
+            ("file", std::fs::read(&path)?)
+        };
+        hasher.update(format!("{} {} {}\n", kind, relative.display(), contents.len()));
+        hasher.update(contents);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Checks that a cache entry still has the contents it was installed with
+fn check_content(dir: &Path) -> Result<()> {
+    let expected = std::fs::read_to_string(dir.join(CONTENT_HASH_FILE))
+        .map_err(|e| eyre::eyre!("no content hash: {}", e))?;
+    if content_hash(dir)? != expected.trim() {
+        return Err(eyre::eyre!("contents changed since it was installed"));
+    }
+    Ok(())
+}
+
+/// Makes a cache entry read-only, or writable again so it can be removed
+fn set_tree_writable(dir: &Path, writable: bool) -> std::io::Result<()> {
+    let mut paths = tree_entries(dir)?;
+    paths.push(dir.to_path_buf());
+    for path in paths {
+        if !std::fs::symlink_metadata(&path)?.file_type().is_symlink() {
+            set_writable(&path, writable)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_writable(path: &Path, writable: bool) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    let mode = permissions.mode();
+    permissions.set_mode(if writable { mode | 0o200 } else { mode & !0o222 });
+    std::fs::set_permissions(path, permissions)
+}
+
+#[cfg(windows)]
+#[allow(clippy::permissions_set_readonly_false)]
+fn set_writable(path: &Path, writable: bool) -> std::io::Result<()> {
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_readonly(!writable);
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Checks the tree npm installed, as recorded in `node_modules/.package-lock.json`,
+/// against every package in the lockfile it was installed from
+fn verify_installed(path: &Path, lockfile: &Lockfile) -> Result<(), eyre::Report> {
+    let installed: Lockfile = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    for (path, package) in lockfile.packages.iter().filter(|(path, _)| !path.is_empty()) {
+        let name = package_name(path);
+        match installed.packages.get(path) {
+            Some(installed) => check_locked(
+                name,
+                installed,
+                package.version.as_deref().unwrap_or_default(),
+                package.integrity.as_deref().unwrap_or_default(),
+            )?,
+            None if package.optional => {}
+            None => return Err(NpmInstallError(format!("{} is in the lockfile but was not installed", name)).into()),
+        }
+    }
+    for (path, package) in &installed.packages {
+        if !lockfile.packages.contains_key(path) {
+            return Err(DependencyError::NotLocked(package_name(path).to_string()).into());
+        }
+        if package.has_install_script {
+            return Err(DependencyError::InstallScript(package_name(path).to_string()).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::CodeModule;
+
+    fn code_module() -> CodeModule {
+        serde_json::from_str(include_str!("../testdata/npm/code.json")).unwrap()
+    }
+
+    fn check(code_module: &CodeModule) -> Result<(), DependencyError> {
+        check_pinned(&code_module.dependencies, code_module.lockfile.as_ref())
+    }
+
+    /// The one transitive dependency
+    fn lockfile_entry(code_module: &mut CodeModule) -> &mut serde_json::Value {
+        &mut code_module.lockfile.as_mut().unwrap()["packages"]["node_modules/leftpad-lite"]
+    }
+
+    #[test]
+    fn accepts_a_fully_locked_tree() {
+        check(&code_module()).unwrap();
+    }
+
+    #[test]
+    fn requires_a_lockfile() {
+        let mut code_module = code_module();
+        code_module.lockfile = None;
+        assert!(matches!(check(&code_module), Err(DependencyError::MissingLockfile)));
+    }
+
+    #[test]
+    fn rejects_unpinned_transitive_dependencies() {
+        let mut code_module = code_module();
+        lockfile_entry(&mut code_module)["integrity"].take();
+        assert!(matches!(check(&code_module), Err(DependencyError::MissingIntegrity(name)) if name == "leftpad-lite"));
+        lockfile_entry(&mut code_module)["version"] = "^2.0.0".into();
+        assert!(matches!(check(&code_module), Err(DependencyError::Unpinned { .. })));
+    }
+
+    #[test]
+    fn rejects_install_scripts_anywhere_in_the_tree() {
+        let mut code_module = code_module();
+        lockfile_entry(&mut code_module)["hasInstallScript"] = true.into();
+        assert!(matches!(check(&code_module), Err(DependencyError::InstallScript(name)) if name == "leftpad-lite"));
+    }
+
+    #[test]
+    fn lockfile_must_match_the_dependencies() {
+        let mut module = code_module();
+        module.lockfile.as_mut().unwrap()["packages"][""]["dependencies"]["padder"] = "^1.0.0".into();
+        assert!(matches!(check(&module), Err(DependencyError::NotLocked(name)) if name == "padder"));
+
+        let mut module = code_module();
+        module.dependencies.get_mut("padder").unwrap().integrity = format!("sha512-{}", "A".repeat(86));
+        assert!(matches!(check(&module), Err(DependencyError::IntegrityMismatch { .. })));
+
+        let mut module = code_module();
+        module.lockfile.as_mut().unwrap()["lockfileVersion"] = 1.into();
+        assert!(matches!(check(&module), Err(DependencyError::InvalidLockfile(_))));
+    }
+
+    #[test]
+    fn cache_key_covers_the_lockfile() {
+        let mut code_module = code_module();
+        let key = cache_key(&code_module.dependencies, &lockfile_json(code_module.lockfile.as_ref().unwrap()).unwrap());
+        lockfile_entry(&mut code_module)["version"] = "2.0.2".into();
+        let changed = cache_key(&code_module.dependencies, &lockfile_json(code_module.lockfile.as_ref().unwrap()).unwrap());
+        assert_ne!(key, changed);
+    }
+
+    #[tokio::test]
+    async fn installs_the_locked_tree_offline() {
+        let dir = TempDir::new().unwrap();
+        let config = JsRuntimeConfig {
+            npm_cache_dir: dir.path().join("npm-cache"),
+            modules_cache_dir: dir.path().join("node_modules"),
+            ..JsRuntimeConfig::default()
+        };
+        let status = std::process::Command::new(&config.npm_path)
+            .args(["cache", "add", "--cache"])
+            .arg(&config.npm_cache_dir)
+            .args(["testdata/npm/leftpad-lite-2.0.1.tgz", "testdata/npm/padder-1.0.0.tgz"])
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success());
+
+        let code_module = code_module();
+        let installer = NpmInstaller::new(config);
+        let node_modules = installer
+            .node_modules(&code_module.dependencies, code_module.lockfile.as_ref(), true)
+            .await
+            .unwrap();
+        let index = node_modules.join("padder/index.js");
+        let original = std::fs::read_to_string(&index).unwrap();
+        assert!(node_modules.join("leftpad-lite/index.js").is_file());
+        assert!(std::fs::metadata(&index).unwrap().permissions().readonly());
+        assert!(std::fs::metadata(node_modules.join("padder")).unwrap().permissions().readonly());
+
+        // A cache entry changed behind the installer's back is installed again
+        set_writable(&index, true).unwrap();
+        std::fs::write(&index, "module.exports = () => 'tampered';").unwrap();
+        let reinstalled = installer
+            .node_modules(&code_module.dependencies, code_module.lockfile.as_ref(), true)
+            .await
+            .unwrap();
+        assert_eq!(reinstalled, node_modules);
+        assert_eq!(std::fs::read_to_string(&index).unwrap(), original);
+        assert!(std::fs::metadata(&index).unwrap().permissions().readonly());
+        // So the temporary directory can be removed without root
+        set_tree_writable(&dir.path().join("node_modules"), true).unwrap();
+    }
+}
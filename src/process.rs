@@ -0,0 +1,180 @@
+//! Child processes for the runtimes. They run on tokio, so a long run never
+//! blocks the listener; their output is read as it arrives into bounded
+//! buffers, and a child is killed when it times out or when the future waiting
+//! on it is dropped. On unix the kill covers the child's whole process group,
+//! so whatever it spawned goes with it.
+
+use eyre::Result;
+use std::process::{ExitStatus, Stdio};
+#[cfg(unix)]
+use std::collections::BTreeSet;
+#[cfg(unix)]
+use std::sync::Mutex;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::process::Command;
+
+/// The process did not finish within its time limit and was killed
+#[derive(Error, Debug)]
+#[error("process ran longer than {0:?} and was killed")]
+pub struct Timeout(pub Duration);
+
+#[derive(Debug)]
+pub struct ProcessOutput {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// Whether stdout or stderr went over the limit and was cut short
+    pub truncated: bool,
+}
+
+/// Process groups of the children currently running, for `kill_all`
+#[cfg(unix)]
+static LIVE_GROUPS: Mutex<BTreeSet<libc::pid_t>> = Mutex::new(BTreeSet::new());
+
+/// A child's process group, killed when this is dropped
+#[cfg(unix)]
+struct ProcessGroup(libc::pid_t);
+
+#[cfg(unix)]
+impl ProcessGroup {
+    /// `pid` leads its own group, so the group id is the child's pid
+    fn new(pid: u32) -> Self {
+        let pgid = pid as libc::pid_t;
+        LIVE_GROUPS.lock().unwrap_or_else(|e| e.into_inner()).insert(pgid);
+        Self(pgid)
+    }
+
+    fn kill(&self) {
+        // Fails with ESRCH once every process in the group has exited
+        unsafe { libc::killpg(self.0, libc::SIGKILL) };
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.kill();
+        LIVE_GROUPS.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.0);
+    }
+}
+
+/// Kills every child still running and whatever it spawned. For exiting
+/// without unwinding, which skips the kill on drop.
+pub fn kill_all() {
+    #[cfg(unix)]
+    for &pgid in LIVE_GROUPS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        unsafe { libc::killpg(pgid, libc::SIGKILL) };
+    }
+}
+
+/// Runs `command` to completion, keeping at most `max_output_bytes` of each of
+/// stdout and stderr. The rest is read and dropped so the child never blocks on
+/// a full pipe.
+pub async fn run(command: &mut Command, max_output_bytes: usize, timeout: Duration) -> Result<ProcessOutput> {
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // In its own process group, a Ctrl-C in the terminal reaches only the
+    // operator, which then lets the run finish during shutdown
+    #[cfg(unix)]
+    command.process_group(0);
+
+    let mut child = command.spawn()?;
+    #[cfg(unix)]
+    let group = child.id().map(ProcessGroup::new);
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let finished = async {
+        tokio::try_join!(
+            read_bounded(stdout, max_output_bytes),
+            read_bounded(stderr, max_output_bytes),
+            child.wait(),
+        )
+    };
+    match tokio::time::timeout(timeout, finished).await {
+        Ok(result) => {
+            let ((stdout, stdout_truncated), (stderr, stderr_truncated), status) = result?;
+            Ok(ProcessOutput { status, stdout, stderr, truncated: stdout_truncated || stderr_truncated })
+        }
+        Err(_) => {
+            #[cfg(unix)]
+            if let Some(group) = &group {
+                group.kill();
+            }
+            child.kill().await?;
+            Err(Timeout(timeout).into())
+        }
+    }
+}
+
+async fn read_bounded(mut reader: impl AsyncRead + Unpin, limit: usize) -> std::io::Result<(Vec<u8>, bool)> {
+    let mut kept = Vec::new();
+    let mut truncated = false;
+    let mut buffer = [0u8; 8192];
+    loop {
+        let read = reader.read(&mut buffer).await?;
+        if read == 0 {
+            return Ok((kept, truncated));
+        }
+        let room = limit.saturating_sub(kept.len());
+        kept.extend_from_slice(&buffer[..read.min(room)]);
+        truncated |= read > room;
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    /// Spawns `sh`, which starts a `sleep` in the background and waits; returns
+    /// where the `sleep`'s pid gets written
+    fn spawn_grandchild(dir: &tempfile::TempDir) -> (Command, std::path::PathBuf) {
+        let pid_file = dir.path().join("pid");
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(format!("sleep 30 & echo $! > {}; wait", pid_file.display()));
+        (command, pid_file)
+    }
+
+    /// Alive and not a zombie waiting to be reaped
+    fn is_running(pid_file: &std::path::Path) -> bool {
+        let pid = std::fs::read_to_string(pid_file).unwrap();
+        match std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())) {
+            Ok(stat) => stat.rsplit_once(") ").is_some_and(|(_, rest)| !rest.starts_with('Z')),
+            Err(_) => false,
+        }
+    }
+
+    async fn wait_for_file(path: &std::path::Path) {
+        while !path.exists() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    #[tokio::test]
+    async fn timeout_kills_grandchildren() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (mut command, pid_file) = spawn_grandchild(&dir);
+        let error = run(&mut command, 1024, Duration::from_millis(500)).await.unwrap_err();
+        assert!(error.is::<Timeout>());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!is_running(&pid_file));
+    }
+
+    #[tokio::test]
+    async fn dropping_the_run_kills_grandchildren() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let (mut command, pid_file) = spawn_grandchild(&dir);
+        let running = tokio::spawn(async move { run(&mut command, 1024, Duration::from_secs(30)).await.map(drop) });
+        wait_for_file(&pid_file).await;
+        assert!(is_running(&pid_file));
+        running.abort();
+        let _ = running.await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!is_running(&pid_file));
+    }
+}
@@ -0,0 +1,277 @@
+//! JS runtime embedded in the operator through QuickJS, for operators without
+//! `node` and `npm`. Code runs in-process on a blocking thread under a heap
+//! limit, an instruction budget and the code run time limit, and is interrupted
+//! when the future running it is dropped. There is no module loader, so code
+//! modules with imports are rejected.
+
+use crate::config::{JsRuntimeConfig, LimitsConfig};
+use crate::executor::{CodeModule, Execution, ExecutionFailed, ExecutionLogs, ExecutionResult, InvalidCode};
+use crate::js_executor::Determinism;
+use crate::receipt::{ResourceUsage, RuntimeInfo};
+use eyre::Result;
+use rquickjs::{CatchResultExt, Context, Promise, Runtime};
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tracing::{debug, info};
+
+const DETERMINISM_PRELUDE: &str = include_str!("js/determinism_quickjs.js");
+const CONSOLE: &str = include_str!("js/console_quickjs.js");
+
+/// QuickJS calls the interrupt handler about once every this many instructions
+const INSTRUCTIONS_PER_INTERRUPT: u64 = 10_000;
+
+/// The code ran out of its instruction budget or time, or the run was cancelled
+#[derive(Error, Debug, Clone, Copy)]
+pub enum LimitExceeded {
+    #[error("code ran more than {0} instructions")]
+    Instructions(u64),
+    #[error("code ran longer than {0:?}")]
+    Duration(Duration),
+    #[error("run was cancelled")]
+    Cancelled,
+}
+
+pub struct QuickJsExecutor {
+    config: JsRuntimeConfig,
+    limits: LimitsConfig,
+    determinism: Determinism,
+}
+
+impl QuickJsExecutor {
+    pub fn new(config: JsRuntimeConfig) -> Self {
+        Self { config, limits: LimitsConfig::default(), determinism: Determinism::default() }
+    }
+
+    /// Run time limit, the default unless set
+    pub fn with_limits(mut self, limits: LimitsConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Clock and seed used when `deterministic` is set in the runtime config
+    pub fn with_determinism(mut self, determinism: Determinism) -> Self {
+        self.determinism = determinism;
+        self
+    }
+
+    pub async fn execute(&self, code_module: &CodeModule) -> Result<Execution> {
+        if code_module.imports.contains(".env") || code_module.function.contains(".env") {
+            return Err(InvalidCode("usage of .env is not allowed for security reasons".to_string()).into());
+        }
+        if !code_module.imports.trim().is_empty() || !code_module.dependencies.is_empty() {
+            return Err(InvalidCode("the quickjs engine does not support imports, use the node engine".to_string()).into());
+        }
+
+        let prelude = self.config.deterministic.then(|| self.determinism.fill(DETERMINISM_PRELUDE));
+        // Resolves to the JSON of whatever the function returns, awaiting it if it is async
+        let script = format!("Promise.resolve(({})()).then((result) => JSON.stringify(result))", code_module.function);
+        let limits = Limits {
+            memory_bytes: self.config.memory_limit_bytes,
+            instructions: self.config.max_instructions,
+            duration: Duration::from_secs(self.limits.max_code_run_duration_secs),
+            output_bytes: self.limits.max_output_bytes,
+        };
+
+        // The engine blocks until the code finishes, keep it off the async workers
+        let cancel = CancelOnDrop(Arc::new(AtomicBool::new(false)));
+        let cancelled = cancel.0.clone();
+        let started = Instant::now();
+        let (output, logs, mut usage) =
+            tokio::task::spawn_blocking(move || evaluate(prelude.as_deref(), &script, limits, &cancelled)).await??;
+        usage.wall_time_ms = started.elapsed().as_millis() as u64;
+        if !logs.is_empty() {
+            debug!("Code logs:\n{}{}", logs.stdout, logs.stderr);
+        }
+        info!("Output: {}", output);
+
+        let result: serde_json::Value = serde_json::from_str(&output)?;
+        Ok(Execution {
+            result: ExecutionResult::from_json(result)?,
+            logs,
+            runtime: RuntimeInfo { name: "quickjs".to_string(), version: engine_version() },
+            usage,
+            lockfile_hash: None,
+            determinism: self.config.deterministic.then_some(self.determinism),
+        })
+    }
+}
+
+/// Version of the QuickJS library linked in
+fn engine_version() -> String {
+    // SAFETY: JS_GetVersion returns a pointer to a static NUL-terminated string
+    unsafe { CStr::from_ptr(rquickjs::qjs::JS_GetVersion()) }.to_string_lossy().into_owned()
+}
+
+/// Tells the engine thread to stop once `execute` is no longer awaited
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    memory_bytes: usize,
+    instructions: u64,
+    duration: Duration,
+    output_bytes: usize,
+}
+
+/// Runs `script` in a fresh runtime and returns the string it resolves to,
+/// along with what it printed and the heap and instructions it used
+fn evaluate(
+    prelude: Option<&str>,
+    script: &str,
+    limits: Limits,
+    cancelled: &Arc<AtomicBool>,
+) -> Result<(String, ExecutionLogs, ResourceUsage)> {
+    let runtime = Runtime::new()?;
+    runtime.set_memory_limit(limits.memory_bytes);
+
+    let exceeded = Rc::new(Cell::new(None));
+    let deadline = Instant::now() + limits.duration;
+    let interrupts = Rc::new(Cell::new(0u64));
+    let reason = exceeded.clone();
+    let counter = interrupts.clone();
+    let cancelled = cancelled.clone();
+    runtime.set_interrupt_handler(Some(Box::new(move || {
+        counter.set(counter.get() + 1);
+        if cancelled.load(Ordering::Relaxed) {
+            reason.set(Some(LimitExceeded::Cancelled));
+        } else if counter.get().saturating_mul(INSTRUCTIONS_PER_INTERRUPT) > limits.instructions {
+            reason.set(Some(LimitExceeded::Instructions(limits.instructions)));
+        } else if Instant::now() > deadline {
+            reason.set(Some(LimitExceeded::Duration(limits.duration)));
+        }
+        reason.get().is_some()
+    })));
+
+    let context = Context::full(&runtime)?;
+    let (output, logs) = context.with(|ctx| {
+        let run = || -> Result<Option<String>, String> {
+            ctx.eval::<(), _>(CONSOLE).catch(&ctx).map_err(|e| e.to_string())?;
+            if let Some(prelude) = prelude {
+                ctx.eval::<(), _>(prelude).catch(&ctx).map_err(|e| e.to_string())?;
+            }
+            let promise: Promise = ctx.eval(script).catch(&ctx).map_err(|e| e.to_string())?;
+            promise.finish::<Option<String>>().catch(&ctx).map_err(|e| e.to_string())
+        };
+        let output = run();
+        // Lost when the run was interrupted, as the engine won't run anything after that
+        let logs = ctx.eval::<String, _>("__logs()").ok();
+        (output, logs)
+    });
+    let mut logs: ExecutionLogs = logs.and_then(|logs| serde_json::from_str(&logs).ok()).unwrap_or_default();
+    logs.truncated = truncate(&mut logs.stdout, limits.output_bytes) | truncate(&mut logs.stderr, limits.output_bytes);
+    let usage = ResourceUsage {
+        heap_bytes: Some(runtime.memory_usage().malloc_size.max(0) as u64),
+        instructions: Some(interrupts.get().saturating_mul(INSTRUCTIONS_PER_INTERRUPT)),
+        stdout_bytes: logs.stdout.len() as u64,
+        stderr_bytes: logs.stderr.len() as u64,
+        ..ResourceUsage::default()
+    };
+
+    match output {
+        // `undefined`, which the result conversion rejects like `null`
+        Ok(None) => Ok(("null".to_string(), logs, usage)),
+        Ok(Some(output)) => Ok((output, logs, usage)),
+        Err(message) => match exceeded.get() {
+            Some(limit) => Err(limit.into()),
+            None => Err(ExecutionFailed { message, logs }.into()),
+        },
+    }
+}
+
+/// Cuts `text` down to at most `limit` bytes, returning whether it was longer
+fn truncate(text: &mut String, limit: usize) -> bool {
+    if text.len() <= limit {
+        return false;
+    }
+    let mut end = limit;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text.truncate(end);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_response::{ErrorClass, ErrorResponse};
+    use crate::executor::error_response;
+    use crate::npm::Dependency;
+    use crate::respond::Task;
+    use ethers::types::{H256, U256};
+
+    fn code_module(function: &str) -> CodeModule {
+        CodeModule {
+            function: function.to_string(),
+            imports: String::new(),
+            dependencies: Default::default(),
+            lockfile: None,
+        }
+    }
+
+    async fn run(config: JsRuntimeConfig, function: &str) -> Result<Execution> {
+        let determinism = Determinism { clock_ms: 1_700_000_000_000, seed: H256::repeat_byte(0x11) };
+        QuickJsExecutor::new(config).with_determinism(determinism).execute(&code_module(function)).await
+    }
+
+    #[tokio::test]
+    async fn imports_are_invalid_code() {
+        let mut with_imports = code_module("() => 1");
+        with_imports.imports = "import _ from 'lodash';".to_string();
+        let mut with_dependencies = code_module("() => 1");
+        with_dependencies.dependencies.insert(
+            "lodash".to_string(),
+            Dependency { version: "4.17.21".to_string(), integrity: "sha512-".to_string() },
+        );
+        for code_module in [with_imports, with_dependencies] {
+            let error = QuickJsExecutor::new(JsRuntimeConfig::default()).execute(&code_module).await.unwrap_err();
+            assert!(error.is::<InvalidCode>(), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn heap_limit_stops_the_code() {
+        let config = JsRuntimeConfig { memory_limit_bytes: 4 * 1024 * 1024, ..JsRuntimeConfig::default() };
+        let function = "() => { const chunks = []; while (true) chunks.push(new Array(100000).fill(1)); }";
+        let error = run(config, function).await.unwrap_err();
+        let failed = error.downcast_ref::<ExecutionFailed>().unwrap_or_else(|| panic!("{}", error));
+        assert!(failed.message.contains("out of memory"), "{}", failed.message);
+    }
+
+    #[tokio::test]
+    async fn instruction_budget_is_a_timeout() {
+        let config = JsRuntimeConfig { max_instructions: 100_000, ..JsRuntimeConfig::default() };
+        let error = run(config, "() => { while (true) {} }").await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<LimitExceeded>(), Some(LimitExceeded::Instructions(100_000))), "{}", error);
+
+        let task = Task {
+            code_arweave_uri: "code".to_string(),
+            language: "js".to_string(),
+            response_count: U256::one(),
+            task_created_block: 1,
+            request_id: 7,
+        };
+        assert_eq!(error_response(&task, &error), Some(ErrorResponse::new(ErrorClass::Timeout, "")));
+    }
+
+    #[tokio::test]
+    async fn clock_is_fixed_and_random_seeded() {
+        let config = JsRuntimeConfig { deterministic: true, ..JsRuntimeConfig::default() };
+        let function = "() => [Date.now(), new Date().getTime(), Math.floor(Math.random() * 1e9)]";
+        let first = run(config.clone(), function).await.unwrap().result.to_response_string();
+        let second = run(config, function).await.unwrap().result.to_response_string();
+        assert!(first.starts_with("1700000000000,1700000000000,"), "{}", first);
+        assert_eq!(first, second);
+    }
+}
@@ -0,0 +1,175 @@
+//! Execution receipts: what an operator ran, on what, and what came out, so a
+//! result can be reproduced or disputed later.
+//!
+//! The receipt hash is signed along with the response (see
+//! `respond::create_signature_for_task`), which ties the on-chain signature to
+//! the receipt. Receipts are kept in the task store and can also be uploaded to
+//! Arweave.
+
+use crate::arweave_tx::Tag;
+use crate::executor::{Execution, FetchedCode};
+use crate::respond::Task;
+use crate::retry::unix_now;
+use ethers::abi::{encode, Token};
+use ethers::types::{Address, H256, U256};
+use ethers::utils::keccak256;
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever the receipt format changes
+pub const RECEIPT_VERSION: u32 = 1;
+
+/// The runtime that ran the code
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuntimeInfo {
+    /// `node`, `quickjs` or `go`
+    pub name: String,
+    pub version: String,
+}
+
+/// Resources a run used, as far as its runtime reports them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: Option<u64>,
+    pub max_rss_bytes: Option<u64>,
+    /// Heap allocated by the embedded engine
+    pub heap_bytes: Option<u64>,
+    /// Approximate bytecode instructions run by the embedded engine
+    pub instructions: Option<u64>,
+    pub stdout_bytes: u64,
+    pub stderr_bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionReceipt {
+    pub version: u32,
+    pub task_index: u32,
+    /// keccak256(abi.encode(task)), as stored on-chain
+    pub task_hash: H256,
+    pub operator: Address,
+    /// Arweave ID of the code that ran, also for tasks that named it through the registry
+    pub code_uri: String,
+    /// keccak256 of the code module JSON as fetched
+    pub code_hash: H256,
+    /// keccak256(abi.encode(taskHash, clockMs, seed)): everything the code
+    /// could observe besides itself
+    pub input_hash: H256,
+    pub deterministic: bool,
+    pub runtime: RuntimeInfo,
+    /// keccak256 of the package-lock.json the dependencies were installed from
+    pub lockfile_hash: Option<H256>,
+    pub usage: ResourceUsage,
+    /// keccak256 of the response string
+    pub output_hash: H256,
+    /// Unix time the receipt was made
+    pub created_at: i64,
+}
+
+impl ExecutionReceipt {
+    pub fn new(
+        task: &Task,
+        operator: Address,
+        code: &FetchedCode,
+        execution: &Execution,
+        response_string: &str,
+    ) -> Self {
+        let determinism = execution.determinism.unwrap_or_default();
+        let input = encode(&[
+            Token::FixedBytes(task.hash().as_bytes().to_vec()),
+            Token::Uint(U256::from(determinism.clock_ms)),
+            Token::FixedBytes(determinism.seed.as_bytes().to_vec()),
+        ]);
+        Self {
+            version: RECEIPT_VERSION,
+            task_index: task.request_id,
+            task_hash: task.hash(),
+            operator,
+            code_uri: code.code_uri.clone(),
+            code_hash: H256::from(keccak256(&code.bytes)),
+            input_hash: H256::from(keccak256(input)),
+            deterministic: execution.determinism.is_some(),
+            runtime: execution.runtime.clone(),
+            lockfile_hash: execution.lockfile_hash,
+            usage: execution.usage.clone(),
+            output_hash: H256::from(keccak256(response_string.as_bytes())),
+            created_at: unix_now(),
+        }
+    }
+
+    /// The canonical JSON encoding, which is what gets hashed and uploaded
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("a receipt always serializes")
+    }
+
+    /// keccak256 of `to_bytes`, the value operators sign
+    pub fn hash(&self) -> H256 {
+        H256::from(keccak256(self.to_bytes()))
+    }
+
+    /// Arweave tags, so receipts can be found by task and operator
+    pub fn tags(&self) -> Vec<Tag> {
+        vec![
+            Tag::new("Content-Type", "application/json"),
+            Tag::new("App-Name", "FunctionLayer"),
+            Tag::new("App-Version", env!("CARGO_PKG_VERSION")),
+            Tag::new("Type", "Execution-Receipt"),
+            Tag::new("Task-Index", &self.task_index.to_string()),
+            Tag::new("Operator", &format!("{:?}", self.operator)),
+            Tag::new("Receipt-Hash", &format!("{:?}", self.hash())),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arweave_mock::MockGateway;
+    use crate::config::Config;
+    use crate::executor::{ExecutionResult, Executor};
+    use crate::respond::receipt_message_hash;
+
+    fn task() -> Task {
+        Task {
+            code_arweave_uri: "code".to_string(),
+            language: "js".to_string(),
+            response_count: U256::one(),
+            task_created_block: 1,
+            request_id: 7,
+        }
+    }
+
+    /// keccak256(abi.encodePacked("42", uint256(7), bytes32(0xabab...)))
+    #[test]
+    fn receipt_digest_packs_the_index_as_uint256() {
+        assert_eq!(
+            H256::from(receipt_message_hash("42", 7u32.into(), H256::repeat_byte(0xab))),
+            "0x38f4535eecbe75bc96cac65d4edd4857b07756e92d61b555667952e91a6e3d02".parse::<H256>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn code_hash_covers_the_fetched_bytes() {
+        // Whitespace, key order and fields the operator ignores all count
+        let data = b"{\n  \"function\": \"() => 1\",\n  \"imports\": \"\",\n  \"author\": \"someone\"\n}\n".to_vec();
+        let gateway = MockGateway::start().await.unwrap();
+        gateway.insert("code", data.clone(), Vec::new());
+        let mut config = Config::default();
+        config.arweave.gateway_url = gateway.url().to_string();
+
+        let code = Executor::new(config).fetch_code("code", "js").await.unwrap();
+        assert_eq!(code.bytes, data);
+        assert_eq!(code.module.function, "() => 1");
+
+        let execution = Execution {
+            result: ExecutionResult::Uint(U256::one()),
+            logs: Default::default(),
+            runtime: Default::default(),
+            usage: Default::default(),
+            lockfile_hash: None,
+            determinism: None,
+        };
+        let receipt = ExecutionReceipt::new(&task(), Address::zero(), &code, &execution, "1");
+        assert_eq!(receipt.code_hash, H256::from(keccak256(&data)));
+        assert_eq!(receipt.code_uri, "code");
+    }
+}
@@ -0,0 +1,262 @@
+use crate::config::Config;
+use crate::contract::ContractClient;
+use ethers::abi::{encode, parse_abi, Token};
+use ethers::prelude::*;
+use ethers::types::{Address, Bytes, H256, U256};
+use ethers::utils::keccak256;
+use eyre::Result;
+use std::time::Duration;
+
+/// EIP-712 type of the AVSDirectory registration; its keccak256 is the
+/// directory's `OPERATOR_AVS_REGISTRATION_TYPEHASH`
+pub const OPERATOR_AVS_REGISTRATION_TYPE: &str =
+    "OperatorAVSRegistration(address operator,address avs,bytes32 salt,uint256 expiry)";
+
+/// How long a registration signature stays valid unless told otherwise
+pub const DEFAULT_SIGNATURE_TTL: Duration = Duration::from_secs(60 * 60);
+
+type SignerClient = SignerMiddleware<Provider<Http>, Wallet<k256::ecdsa::SigningKey>>;
+
+/// The `ISignatureUtils.SignatureWithSaltAndExpiry` passed to `registerOperatorToAVS`
+#[derive(Debug, Clone)]
+pub struct SignatureWithSaltAndExpiry {
+    pub signature: Bytes,
+    pub salt: [u8; 32],
+    pub expiry: U256,
+}
+
+#[derive(Debug)]
+pub struct RegistrationStatus {
+    pub operator: Address,
+    pub avs: Address,
+    pub avs_directory: Address,
+    /// `operatorRegistered(operator)` on the ServiceManager
+    pub registered_with_service_manager: bool,
+    /// `avsOperatorStatus(avs, operator)` on the AVSDirectory
+    pub registered_with_avs_directory: bool,
+    /// `isOperator(operator)` on the DelegationManager, a prerequisite for registering
+    pub eigenlayer_operator: bool,
+}
+
+impl RegistrationStatus {
+    pub fn is_registered(&self) -> bool {
+        self.registered_with_service_manager && self.registered_with_avs_directory
+    }
+}
+
+/// EIP-712 digest the AVSDirectory expects the operator to sign
+pub fn registration_digest(
+    domain_separator: H256,
+    operator: Address,
+    avs: Address,
+    salt: [u8; 32],
+    expiry: U256,
+) -> H256 {
+    let struct_hash = keccak256(encode(&[
+        Token::FixedBytes(keccak256(OPERATOR_AVS_REGISTRATION_TYPE).to_vec()),
+        Token::Address(operator),
+        Token::Address(avs),
+        Token::FixedBytes(salt.to_vec()),
+        Token::Uint(expiry),
+    ]));
+
+    let mut message = Vec::with_capacity(66);
+    message.extend_from_slice(b"\x19\x01");
+    message.extend_from_slice(domain_separator.as_bytes());
+    message.extend_from_slice(&struct_hash);
+    H256::from(keccak256(message))
+}
+
+/// Registers and deregisters the configured operator with the AVS
+pub struct OperatorRegistration {
+    client: ContractClient,
+    avs_directory: Contract<SignerClient>,
+    wallet: LocalWallet,
+}
+
+impl OperatorRegistration {
+    pub async fn new(config: &Config) -> Result<Self> {
+        let client = ContractClient::from_config(config).await?;
+        let avs_directory_address: Address = client
+            .contract()
+            .method::<_, Address>("avsDirectory", ())?
+            .call()
+            .await?;
+        let avs_directory_abi = parse_abi(&[
+            "function domainSeparator() view returns (bytes32)",
+            "function calculateOperatorAVSRegistrationDigestHash(address operator, address avs, bytes32 salt, uint256 expiry) view returns (bytes32)",
+            "function avsOperatorStatus(address avs, address operator) view returns (uint8)",
+            "function operatorSaltIsSpent(address operator, bytes32 salt) view returns (bool)",
+            "function delegation() view returns (address)",
+        ])?;
+        let avs_directory = Contract::new(
+            avs_directory_address,
+            avs_directory_abi,
+            client.contract().client(),
+        );
+        let wallet = client.contract().client().signer().clone();
+
+        Ok(Self {
+            client,
+            avs_directory,
+            wallet,
+        })
+    }
+
+    pub fn operator(&self) -> Address {
+        self.wallet.address()
+    }
+
+    /// Pre-flight check of where the operator stands with EigenLayer and the AVS
+    pub async fn status(&self) -> Result<RegistrationStatus> {
+        let operator = self.operator();
+        let avs = self.client.contract().address();
+
+        let registered_with_service_manager = self.client.is_operator_registered(operator).await?;
+        let avs_directory_status: u8 = self
+            .avs_directory
+            .method::<_, u8>("avsOperatorStatus", (avs, operator))?
+            .call()
+            .await?;
+        let delegation: Address = self
+            .avs_directory
+            .method::<_, Address>("delegation", ())?
+            .call()
+            .await?;
+        let delegation_manager = Contract::new(
+            delegation,
+            parse_abi(&["function isOperator(address operator) view returns (bool)"])?,
+            self.client.contract().client(),
+        );
+        let eigenlayer_operator: bool = delegation_manager
+            .method::<_, bool>("isOperator", operator)?
+            .call()
+            .await?;
+
+        Ok(RegistrationStatus {
+            operator,
+            avs,
+            avs_directory: self.avs_directory.address(),
+            registered_with_service_manager,
+            registered_with_avs_directory: avs_directory_status == 1,
+            eigenlayer_operator,
+        })
+    }
+
+    /// Signs the registration digest with a fresh salt, valid for `ttl` from the latest block
+    pub async fn sign_registration(&self, ttl: Duration) -> Result<SignatureWithSaltAndExpiry> {
+        let operator = self.operator();
+        let avs = self.client.contract().address();
+
+        let salt = loop {
+            let salt: [u8; 32] = rand::random();
+            let spent: bool = self
+                .avs_directory
+                .method::<_, bool>("operatorSaltIsSpent", (operator, salt))?
+                .call()
+                .await?;
+            if !spent {
+                break salt;
+            }
+        };
+        let latest = self
+            .client
+            .provider()
+            .get_block(BlockNumber::Latest)
+            .await?
+            .ok_or_else(|| eyre::eyre!("Failed to fetch latest block"))?;
+        let expiry = latest.timestamp + U256::from(ttl.as_secs());
+
+        let domain_separator: H256 = self
+            .avs_directory
+            .method::<_, H256>("domainSeparator", ())?
+            .call()
+            .await?;
+        let digest = registration_digest(domain_separator, operator, avs, salt, expiry);
+
+        // Guard against signing something the AVSDirectory won't accept
+        let expected: H256 = self
+            .avs_directory
+            .method::<_, H256>(
+                "calculateOperatorAVSRegistrationDigestHash",
+                (operator, avs, salt, expiry),
+            )?
+            .call()
+            .await?;
+        if digest != expected {
+            return Err(eyre::eyre!(
+                "Registration digest mismatch: computed {:?}, AVSDirectory expects {:?}",
+                digest,
+                expected
+            ));
+        }
+
+        let signature = self.wallet.sign_hash(digest)?;
+        Ok(SignatureWithSaltAndExpiry {
+            signature: Bytes::from(signature.to_vec()),
+            salt,
+            expiry,
+        })
+    }
+
+    /// Checks the operator can register, signs and submits the registration
+    pub async fn register(&self, ttl: Duration) -> Result<TransactionReceipt> {
+        let status = self.status().await?;
+        if status.is_registered() {
+            return Err(eyre::eyre!("Operator {:?} is already registered", status.operator));
+        }
+        if !status.eigenlayer_operator {
+            return Err(eyre::eyre!(
+                "Operator {:?} is not registered with the EigenLayer DelegationManager; register as an operator first",
+                status.operator
+            ));
+        }
+
+        let signature = self.sign_registration(ttl).await?;
+        self.submit(signature).await
+    }
+
+    /// Submits an already produced registration signature
+    pub async fn submit(&self, signature: SignatureWithSaltAndExpiry) -> Result<TransactionReceipt> {
+        self.client
+            .register_operator(self.operator(), signature.signature, signature.salt, signature.expiry)
+            .await
+    }
+
+    pub async fn deregister(&self) -> Result<TransactionReceipt> {
+        let status = self.status().await?;
+        if !status.registered_with_service_manager {
+            return Err(eyre::eyre!("Operator {:?} is not registered", status.operator));
+        }
+        self.client.deregister_operator(self.operator()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn h256(hex: &str) -> H256 {
+        hex.parse().unwrap()
+    }
+
+    #[test]
+    fn type_hashes_to_avs_directory_typehash() {
+        assert_eq!(
+            H256::from(keccak256(OPERATOR_AVS_REGISTRATION_TYPE)),
+            h256("0xda2c89bafdd34776a2b8bb9c83c82f419e20cc8c67207f70edd58249b92661bd")
+        );
+    }
+
+    /// Expected digest worked out by following
+    /// `AVSDirectory.calculateOperatorAVSRegistrationDigestHash` step by step, with
+    /// the domain separator of a directory at 0x5FbDB231... on chain 31337
+    #[test]
+    fn digest_matches_avs_directory() {
+        let domain_separator = h256("0xa0335abdbc7db9c9a09d6594e53b1358b5f7eeaeaac7dcddc60116bcc49f5785");
+        let operator: Address = "0x70997970C51812dc3A010C7d01b50e0d17dc79C8".parse().unwrap();
+        let avs: Address = "0xe7f1725E7734CE288F8367e1Bb143E90bb3F0512".parse().unwrap();
+        let digest = registration_digest(domain_separator, operator, avs, [0x11; 32], U256::from(1_700_000_000u64));
+        assert_eq!(digest, h256("0x2070ed96f37e1c0e8c7efe1d229eb8f1b43b15871011ac1ab7d3be769be19350"));
+    }
+}
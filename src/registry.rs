@@ -0,0 +1,351 @@
+//! Function registry: lets a task name its code as `registry://<name>@<version>`
+//! instead of an Arweave ID.
+//!
+//! The registry is a manifest on Arweave mapping each function name and
+//! version to its code URI, language and output type, signed by the registry
+//! publisher. Arweave data never changes, so every operator configured with the
+//! same manifest resolves a name to the same code. Fetched manifests are cached
+//! on disk by ID and their signature is checked on every load.
+
+use crate::arweave::ArweaveClient;
+use crate::arweave_tx::Tag;
+use crate::config::Config;
+use crate::executor::ExecutionResult;
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::{Address, Bytes, Signature, H256};
+use ethers::utils::keccak256;
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use thiserror::Error;
+use tracing::info;
+
+/// Prefix of code URIs that name a registry function
+pub const REGISTRY_SCHEME: &str = "registry://";
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("invalid registry reference {0:?}, expected registry://<name>@<version>")]
+    InvalidReference(String),
+    #[error("{name}@{version} is not in the function registry")]
+    UnknownFunction { name: String, version: String },
+    #[error("registry.manifest_uri and registry.signer must be set to resolve registry references")]
+    NotConfigured,
+    #[error("registry manifest is signed by {actual:?}, expected {expected:?}")]
+    WrongSigner { expected: Address, actual: Address },
+}
+
+/// What a registry function returns, one per `ExecutionResult` variant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputType {
+    Uint,
+    UintArray,
+    Bool,
+    BoolArray,
+    Bytes,
+}
+
+impl OutputType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputType::Uint => "uint",
+            OutputType::UintArray => "uint_array",
+            OutputType::Bool => "bool",
+            OutputType::BoolArray => "bool_array",
+            OutputType::Bytes => "bytes",
+        }
+    }
+
+    pub fn matches(&self, result: &ExecutionResult) -> bool {
+        matches!(
+            (self, result),
+            (OutputType::Uint, ExecutionResult::Uint(_))
+                | (OutputType::UintArray, ExecutionResult::UintArray(_))
+                | (OutputType::Bool, ExecutionResult::Bool(_))
+                | (OutputType::BoolArray, ExecutionResult::BoolArray(_))
+                | (OutputType::Bytes, ExecutionResult::Bytes(_))
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    /// Arweave ID of the code module
+    pub code_uri: String,
+    pub language: String,
+    pub output: OutputType,
+}
+
+impl fmt::Display for OutputType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A function name and version, as in `registry://sum@1.0.0`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionRef {
+    pub name: String,
+    pub version: String,
+}
+
+impl FunctionRef {
+    /// `None` when `code_uri` is a plain Arweave ID
+    pub fn parse(code_uri: &str) -> Option<Result<Self, RegistryError>> {
+        let reference = code_uri.strip_prefix(REGISTRY_SCHEME)?;
+        let valid = |part: &str| {
+            !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric() || b"-._".contains(&b))
+        };
+        Some(match reference.split_once('@') {
+            Some((name, version)) if valid(name) && valid(version) => {
+                Ok(Self { name: name.to_string(), version: version.to_string() })
+            }
+            _ => Err(RegistryError::InvalidReference(code_uri.to_string())),
+        })
+    }
+}
+
+impl fmt::Display for FunctionRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}@{}", REGISTRY_SCHEME, self.name, self.version)
+    }
+}
+
+/// Functions by name, then by version
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub functions: BTreeMap<String, BTreeMap<String, RegistryEntry>>,
+}
+
+impl Manifest {
+    /// keccak256 of the manifest JSON, the message the publisher signs
+    pub fn hash(&self) -> H256 {
+        H256::from(keccak256(serde_json::to_vec(self).expect("a manifest always serializes")))
+    }
+
+    pub fn get(&self, reference: &FunctionRef) -> Option<&RegistryEntry> {
+        self.functions.get(&reference.name)?.get(&reference.version)
+    }
+
+    pub async fn sign(self, wallet: &LocalWallet) -> Result<SignedManifest> {
+        let signature = wallet.sign_message(self.hash()).await?;
+        Ok(SignedManifest { manifest: self, signature: Bytes::from(signature.to_vec()) })
+    }
+}
+
+/// A manifest as published on Arweave
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedManifest {
+    #[serde(flatten)]
+    pub manifest: Manifest,
+    pub signature: Bytes,
+}
+
+impl SignedManifest {
+    pub fn signer(&self) -> Result<Address> {
+        let signature = Signature::try_from(self.signature.as_ref())?;
+        Ok(signature.recover(self.manifest.hash().as_bytes())?)
+    }
+
+    /// Arweave tags, so manifests can be found by publisher
+    pub fn tags(&self, signer: Address) -> Vec<Tag> {
+        vec![
+            Tag::new("Content-Type", "application/json"),
+            Tag::new("App-Name", "FunctionLayer"),
+            Tag::new("App-Version", env!("CARGO_PKG_VERSION")),
+            Tag::new("Type", "Function-Registry"),
+            Tag::new("Registry-Signer", &format!("{:?}", signer)),
+        ]
+    }
+}
+
+pub struct Registry {
+    gateway_url: String,
+    manifest_uri: Option<String>,
+    signer: Option<Address>,
+    cache_dir: PathBuf,
+    manifest: Mutex<Option<Manifest>>,
+}
+
+impl Registry {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            gateway_url: config.arweave.gateway_url.clone(),
+            manifest_uri: config.registry.manifest_uri.clone(),
+            signer: config.registry.signer.as_deref().and_then(|signer| signer.parse().ok()),
+            cache_dir: config.registry.cache_dir.clone(),
+            manifest: Mutex::new(None),
+        }
+    }
+
+    pub async fn resolve(&self, reference: &FunctionRef) -> Result<RegistryEntry> {
+        if self.manifest.lock().unwrap().is_none() {
+            let manifest = self.load().await?;
+            *self.manifest.lock().unwrap() = Some(manifest);
+        }
+        let manifest = self.manifest.lock().unwrap();
+        let entry = manifest.as_ref().and_then(|manifest| manifest.get(reference)).cloned();
+        entry.ok_or_else(|| {
+            RegistryError::UnknownFunction { name: reference.name.clone(), version: reference.version.clone() }.into()
+        })
+    }
+
+    /// Reads the manifest from the cache, fetching it on first use
+    async fn load(&self) -> Result<Manifest> {
+        let (Some(manifest_uri), Some(expected)) = (&self.manifest_uri, self.signer) else {
+            return Err(RegistryError::NotConfigured.into());
+        };
+        let cache_path = self.cache_dir.join(format!("{}.json", manifest_uri));
+        let (signed, fetched) = match std::fs::read(&cache_path) {
+            Ok(cached) => (serde_json::from_slice::<SignedManifest>(&cached)?, false),
+            Err(_) => {
+                let json = ArweaveClient::new(Some(self.gateway_url.clone()))
+                    .get_transaction_data_json(manifest_uri)
+                    .await?;
+                (serde_json::from_value::<SignedManifest>(json)?, true)
+            }
+        };
+        let actual = signed.signer()?;
+        if actual != expected {
+            return Err(RegistryError::WrongSigner { expected, actual }.into());
+        }
+        if fetched {
+            std::fs::create_dir_all(&self.cache_dir)?;
+            std::fs::write(&cache_path, serde_json::to_vec(&signed)?)?;
+            info!("Cached registry manifest {} in {}", manifest_uri, cache_path.display());
+        }
+        Ok(signed.manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arweave_mock::MockGateway;
+    use crate::executor::{check_output, Executor};
+    use ethers::types::U256;
+    use tempfile::TempDir;
+
+    fn manifest() -> Manifest {
+        let entry = RegistryEntry { code_uri: "sum-code".to_string(), language: "js".to_string(), output: OutputType::Uint };
+        let mut manifest = Manifest::default();
+        manifest.functions.entry("sum".to_string()).or_default().insert("1.0.0".to_string(), entry);
+        manifest
+    }
+
+    fn config(gateway_url: &str, cache_dir: &TempDir, signer: Address) -> Config {
+        let mut config = Config::default();
+        config.arweave.gateway_url = gateway_url.to_string();
+        config.registry.manifest_uri = Some("manifest".to_string());
+        config.registry.signer = Some(format!("{:?}", signer));
+        config.registry.cache_dir = cache_dir.path().to_path_buf();
+        config
+    }
+
+    fn sum() -> FunctionRef {
+        FunctionRef { name: "sum".to_string(), version: "1.0.0".to_string() }
+    }
+
+    #[test]
+    fn parses_function_references() {
+        assert!(FunctionRef::parse("hK8Z3Ewz0PMBJLUX6nVjOEdOgIHVP6sy7jr9Xqu4cKE").is_none());
+        let reference = FunctionRef::parse("registry://sum@1.0.0").unwrap().unwrap();
+        assert_eq!(reference, sum());
+        assert_eq!(reference.to_string(), "registry://sum@1.0.0");
+
+        for invalid in ["registry://sum", "registry://@1.0.0", "registry://sum@", "registry://a/b@1", "registry://sum@1@2"] {
+            assert!(
+                matches!(FunctionRef::parse(invalid), Some(Err(RegistryError::InvalidReference(uri))) if uri == invalid),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn signed_manifests_recover_their_signer() {
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let signed = manifest().sign(&wallet).await.unwrap();
+        assert_eq!(signed.signer().unwrap(), wallet.address());
+
+        // As published: the manifest's fields with the signature next to them
+        let published: SignedManifest = serde_json::from_slice(&serde_json::to_vec(&signed).unwrap()).unwrap();
+        assert_eq!(published.manifest, manifest());
+        assert_eq!(published.signer().unwrap(), wallet.address());
+
+        let mut tampered = published;
+        tampered.manifest.functions.get_mut("sum").unwrap().get_mut("1.0.0").unwrap().code_uri = "other".to_string();
+        assert_ne!(tampered.signer().unwrap(), wallet.address());
+    }
+
+    #[tokio::test]
+    async fn fetches_once_then_resolves_from_the_cache() {
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let signed = manifest().sign(&wallet).await.unwrap();
+        let gateway = MockGateway::start().await.unwrap();
+        gateway.insert("manifest", serde_json::to_vec(&signed).unwrap(), Vec::new());
+        let cache_dir = TempDir::new().unwrap();
+
+        let registry = Registry::new(&config(gateway.url(), &cache_dir, wallet.address()));
+        assert_eq!(registry.resolve(&sum()).await.unwrap(), manifest().get(&sum()).unwrap().clone());
+        assert!(cache_dir.path().join("manifest.json").is_file());
+        let unknown = FunctionRef { name: "sum".to_string(), version: "2.0.0".to_string() };
+        let error = registry.resolve(&unknown).await.unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(RegistryError::UnknownFunction { .. })), "{}", error);
+
+        // Served from the cache once the gateway is gone
+        let registry = Registry::new(&config("http://127.0.0.1:9", &cache_dir, wallet.address()));
+        assert_eq!(registry.resolve(&sum()).await.unwrap().code_uri, "sum-code");
+    }
+
+    #[tokio::test]
+    async fn rejects_a_manifest_from_another_signer() {
+        let publisher = LocalWallet::new(&mut rand::thread_rng());
+        let expected = Address::from_low_u64_be(0xaa);
+        let cache_dir = TempDir::new().unwrap();
+        let signed = manifest().sign(&publisher).await.unwrap();
+        std::fs::write(cache_dir.path().join("manifest.json"), serde_json::to_vec(&signed).unwrap()).unwrap();
+
+        let registry = Registry::new(&config("http://127.0.0.1:9", &cache_dir, expected));
+        let error = registry.resolve(&sum()).await.unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref(),
+                Some(RegistryError::WrongSigner { expected: e, actual }) if *e == expected && *actual == publisher.address()
+            ),
+            "{}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn registry_code_keeps_its_arweave_id_and_output_type() {
+        let wallet = LocalWallet::new(&mut rand::thread_rng());
+        let gateway = MockGateway::start().await.unwrap();
+        gateway.insert("manifest", serde_json::to_vec(&manifest().sign(&wallet).await.unwrap()).unwrap(), Vec::new());
+        gateway.insert("sum-code", br#"{"function": "() => 1", "imports": ""}"#.to_vec(), Vec::new());
+        let cache_dir = TempDir::new().unwrap();
+        let executor = Executor::new(config(gateway.url(), &cache_dir, wallet.address()));
+
+        let code = executor.fetch_code("registry://sum@1.0.0", "js").await.unwrap();
+        assert_eq!(code.code_uri, "sum-code");
+        assert_eq!(code.output, Some(OutputType::Uint));
+        let error = executor.fetch_code("registry://sum@1.0.0", "python").await.unwrap_err();
+        assert!(error.to_string().contains("registered as js"), "{}", error);
+    }
+
+    #[test]
+    fn check_output_compares_the_result_type() {
+        let uint = ExecutionResult::Uint(U256::one());
+        check_output(None, &uint).unwrap();
+        check_output(Some(OutputType::Uint), &uint).unwrap();
+        let error = check_output(Some(OutputType::BoolArray), &uint).unwrap_err();
+        assert_eq!(error.to_string(), "invalid result: expected bool_array output");
+        assert!(OutputType::Bytes.matches(&ExecutionResult::Bytes(vec![1])));
+        assert!(!OutputType::UintArray.matches(&ExecutionResult::BoolArray(vec![true])));
+    }
+}
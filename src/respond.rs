@@ -1,5 +1,5 @@
 use crate::contract::ContractClient; // Assuming these exist in contract.rs
-use ethers::abi::Token;
+use ethers::abi::{encode, Token};
 use ethers::{
     abi::encode_packed,
     prelude::*,
@@ -7,8 +7,8 @@ use ethers::{
     utils::keccak256,
 };
 use serde::{Deserialize, Serialize};
-use std::env;
 use std::fs;
+use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Task {
@@ -19,20 +19,82 @@ pub struct Task {
     pub request_id: u32,
 }
 
+impl Task {
+    /// keccak256(abi.encode(task)), what the contract stores in `allTaskHashes`
+    pub fn hash(&self) -> H256 {
+        H256::from(keccak256(encode(&[Token::Tuple(vec![
+            Token::String(self.code_arweave_uri.clone()),
+            Token::Uint(self.response_count),
+            Token::String(self.language.clone()),
+            Token::Uint(U256::from(self.task_created_block)),
+        ])])))
+    }
+}
+
+/// `encode_packed` packs a `Token::Uint` in as few bytes as the value needs,
+/// while Solidity packs a uint256 in all 32, so it goes in as a 32 byte word
+fn uint256_token(value: U256) -> Token {
+    let mut word = [0u8; 32];
+    value.to_big_endian(&mut word);
+    Token::FixedBytes(word.to_vec())
+}
+
+/// keccak256(abi.encodePacked(responseString, taskIndex)), the message operators sign
+pub fn response_message_hash(response_string: &str, task_index: U256) -> [u8; 32] {
+    let packed = encode_packed(&[
+        Token::String(response_string.to_string()),
+        uint256_token(task_index),
+    ])
+    .expect("string and uint256 always pack");
+    keccak256(packed)
+}
+
+/// keccak256(abi.encodePacked(responseString, taskIndex, receiptHash)), the
+/// message signed when the response comes with an execution receipt
+pub fn receipt_message_hash(response_string: &str, task_index: U256, receipt_hash: H256) -> [u8; 32] {
+    let packed = encode_packed(&[
+        Token::String(response_string.to_string()),
+        uint256_token(task_index),
+        Token::FixedBytes(receipt_hash.as_bytes().to_vec()),
+    ])
+    .expect("string, uint256 and bytes32 always pack");
+    keccak256(packed)
+}
+
+/// The receipt hash a signature commits to. Signatures over a receipt are the
+/// 65 byte signature followed by the 32 byte receipt hash.
+pub fn signed_receipt_hash(signature: &[u8]) -> Option<H256> {
+    (signature.len() == 65 + 32).then(|| H256::from_slice(&signature[65..]))
+}
+
+/// Recovers the operator that produced `signature` with `create_signature_for_task`
+pub fn recover_response_signer(
+    signature: &[u8],
+    response_string: &str,
+    task_index: U256,
+) -> Result<Address, Box<dyn std::error::Error + Send + Sync>> {
+    let (signature, message_hash) = match signed_receipt_hash(signature) {
+        Some(receipt_hash) => (&signature[..65], receipt_message_hash(response_string, task_index, receipt_hash)),
+        None => (signature, response_message_hash(response_string, task_index)),
+    };
+    let signature = Signature::try_from(signature)?;
+    Ok(signature.recover(&message_hash[..])?)
+}
+
+/// Signs the response, and the hash of its execution receipt when there is one
 pub async fn create_signature_for_task(
     wallet: &LocalWallet,
     response_string: &str,
     task: &Task,
+    receipt_hash: Option<H256>,
 ) -> Result<Bytes, Box<dyn std::error::Error + Send + Sync>> {
-    let packed = encode_packed(&[
-        Token::String(response_string.to_string()),
-        Token::Uint(task.request_id.into()),
-    ])?;
-
-    let message_hash = keccak256(packed);
+    let message_hash = match receipt_hash {
+        Some(receipt_hash) => receipt_message_hash(response_string, task.request_id.into(), receipt_hash),
+        None => response_message_hash(response_string, task.request_id.into()),
+    };
 
     // Remove hardcoded chain ID - use wallet's existing chain ID
-    let signature = wallet.sign_message(&message_hash).await?;
+    let signature = wallet.sign_message(message_hash).await?;
 
     // When recovering, use the same hash
     let recovered = signature
@@ -47,53 +109,63 @@ pub async fn create_signature_for_task(
         "Recovered signer doesn't match wallet address"
     );
 
-    Ok(Bytes::from(signature.to_vec()))
+    let mut signature = signature.to_vec();
+    if let Some(receipt_hash) = receipt_hash {
+        signature.extend_from_slice(receipt_hash.as_bytes());
+    }
+    Ok(Bytes::from(signature))
+}
+
+/// Signs the response and sends `respondToTask`, returning the transaction hash
+/// without waiting for it to be mined
+pub async fn send_response(
+    contract_client: &ContractClient,
+    wallet: &LocalWallet,
+    task: Task,
+    response_string: String,
+    receipt_hash: Option<H256>,
+) -> Result<H256, Box<dyn std::error::Error + Send + Sync>> {
+    let signature = create_signature_for_task(wallet, &response_string, &task, receipt_hash).await?;
+
+    // Submit response with task struct
+    let call = contract_client.contract().method::<_, ()>(
+        "respondToTask",
+        (
+            (
+                task.code_arweave_uri,
+                task.response_count,
+                task.language,
+                task.task_created_block,
+            ),
+            task.request_id,
+            response_string,
+            signature,
+        ),
+    )?;
+    let pending = call.send().await?;
+    Ok(pending.tx_hash())
 }
 
 pub async fn respond_to_task(
     contract_address: &str,
     rpc_url: &str,
+    abi_path: &Path,
+    private_key: &str,
     task: Task,
     response_string: String,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<TransactionReceipt, Box<dyn std::error::Error + Send + Sync>> {
     // Read ABI from file
-    let abi = fs::read_to_string("abi/abi.json")?;
-
-    // Read private key from environment
-    let private_key =
-        env::var("OPERATOR_PRIVATE_KEY").expect("OPERATOR_PRIVATE_KEY must be set in environment");
+    let abi = fs::read_to_string(abi_path)?;
 
     // Create new contract client for this response
     let contract_client =
-        ContractClient::new(contract_address, rpc_url, &abi, &private_key).await?;
+        ContractClient::new(contract_address, rpc_url, &abi, private_key).await?;
 
     // Create wallet for signing with the correct chain ID
     let wallet = private_key
         .parse::<LocalWallet>()?
         .with_chain_id(contract_client.provider().get_chainid().await?.as_u64());
 
-    let signature = create_signature_for_task(&wallet, &response_string, &task).await?;
-
-    // Submit response with task struct
-    let result = contract_client
-        .contract()
-        .method::<_, ()>(
-            "respondToTask",
-            (
-                (
-                    task.code_arweave_uri,
-                    task.response_count,
-                    task.language,
-                    task.task_created_block,
-                ),
-                task.request_id,
-                response_string,
-                signature,
-            ),
-        )?
-        .send()
-        .await?
-        .clone();
-
-    Ok(())
+    let tx_hash = send_response(&contract_client, &wallet, task, response_string, None).await?;
+    Ok(contract_client.wait_for_receipt(tx_hash).await?)
 }
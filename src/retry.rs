@@ -0,0 +1,110 @@
+//! Failure classes for task errors, used to pick the retry policy.
+
+use crate::config::{RetryConfig, RetryPolicy};
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureClass {
+    Gateway,
+    Npm,
+    Rpc,
+    Aggregator,
+    Execution,
+}
+
+impl FailureClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FailureClass::Gateway => "gateway",
+            FailureClass::Npm => "npm",
+            FailureClass::Rpc => "rpc",
+            FailureClass::Aggregator => "aggregator",
+            FailureClass::Execution => "execution",
+        }
+    }
+
+    /// The class a task error was tagged with; untagged errors count as execution failures
+    pub fn of(error: &eyre::Report) -> Self {
+        error
+            .downcast_ref::<TaskError>()
+            .map_or(FailureClass::Execution, |e| e.class)
+    }
+}
+
+impl fmt::Display for FailureClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for FailureClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            FailureClass::Gateway,
+            FailureClass::Npm,
+            FailureClass::Rpc,
+            FailureClass::Aggregator,
+            FailureClass::Execution,
+        ]
+        .into_iter()
+        .find(|class| class.as_str() == s)
+        .ok_or_else(|| format!("unknown failure class {:?}", s))
+    }
+}
+
+impl RetryConfig {
+    pub fn policy(&self, class: FailureClass) -> &RetryPolicy {
+        match class {
+            FailureClass::Gateway => &self.gateway,
+            FailureClass::Npm => &self.npm,
+            FailureClass::Rpc => &self.rpc,
+            FailureClass::Aggregator => &self.aggregator,
+            FailureClass::Execution => &self.execution,
+        }
+    }
+}
+
+/// An error from one step of running a task, tagged with its failure class
+#[derive(Debug)]
+pub struct TaskError {
+    pub class: FailureClass,
+    error: eyre::Report,
+}
+
+impl TaskError {
+    /// The error that was tagged
+    pub fn error(&self) -> &eyre::Report {
+        &self.error
+    }
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error: {:#}", self.class, self.error)
+    }
+}
+
+impl std::error::Error for TaskError {}
+
+pub trait ResultExt<T> {
+    /// Tags the error with the failure class that decides how it is retried
+    fn class(self, class: FailureClass) -> Result<T, TaskError>;
+}
+
+impl<T, E: Into<eyre::Report>> ResultExt<T> for Result<T, E> {
+    fn class(self, class: FailureClass) -> Result<T, TaskError> {
+        self.map_err(|error| TaskError { class, error: error.into() })
+    }
+}
+
+/// Seconds since the unix epoch, the clock retries are scheduled on
+pub fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
@@ -0,0 +1,46 @@
+//! SIGINT/SIGTERM handling. The first signal cancels the returned token so the
+//! listener stops taking tasks and drains the one in flight; a second signal
+//! exits immediately.
+
+use colored::*;
+use tracing::warn;
+use tokio_util::sync::CancellationToken;
+
+/// Spawns the signal handler and returns the token it cancels
+pub fn listen_for_signals() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        let signal = wait_for_signal().await;
+        warn!(
+            "{}",
+            format!("Received {}, shutting down once the current task finishes (send it again to exit now)", signal)
+                .yellow()
+        );
+        cancel.cancel();
+
+        let signal = wait_for_signal().await;
+        warn!("{}", format!("Received {} again, exiting", signal).red());
+        // exit doesn't run destructors, so the children have to go first
+        crate::process::kill_all();
+        std::process::exit(130);
+    });
+    token
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to install the SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl-C"
+}
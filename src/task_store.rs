@@ -0,0 +1,573 @@
+//! Persistent record of every task the operator has seen, what state it is in
+//! and how it got there, kept in a local SQLite database.
+
+use crate::executor::ExecutionLogs;
+use crate::receipt::ExecutionReceipt;
+use crate::respond::Task;
+use ethers::types::H256;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TaskStoreError {
+    #[error("Task database error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Failed to create task database directory: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Unknown task state {0:?}")]
+    UnknownState(String),
+    #[error("Stored execution receipt is invalid: {0}")]
+    Receipt(#[from] serde_json::Error),
+}
+
+/// An execution receipt and where it was uploaded
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredReceipt {
+    pub receipt_hash: H256,
+    pub receipt: ExecutionReceipt,
+    /// Arweave transaction id, once uploaded
+    pub arweave_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Discovered,
+    Fetching,
+    Executing,
+    Executed,
+    Submitting,
+    Confirmed,
+    /// Signed and accepted by the aggregator, which submits it on-chain
+    SentToAggregator,
+    Failed,
+    Skipped,
+    /// Failed and out of retries; only a manual requeue runs it again
+    DeadLetter,
+    /// Runs of the task gave different results, so nothing was submitted
+    NonDeterministic,
+}
+
+impl TaskState {
+    pub const ALL: [TaskState; 11] = [
+        TaskState::Discovered,
+        TaskState::Fetching,
+        TaskState::Executing,
+        TaskState::Executed,
+        TaskState::Submitting,
+        TaskState::Confirmed,
+        TaskState::SentToAggregator,
+        TaskState::Failed,
+        TaskState::Skipped,
+        TaskState::DeadLetter,
+        TaskState::NonDeterministic,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskState::Discovered => "discovered",
+            TaskState::Fetching => "fetching",
+            TaskState::Executing => "executing",
+            TaskState::Executed => "executed",
+            TaskState::Submitting => "submitting",
+            TaskState::Confirmed => "confirmed",
+            TaskState::SentToAggregator => "sent_to_aggregator",
+            TaskState::Failed => "failed",
+            TaskState::Skipped => "skipped",
+            TaskState::DeadLetter => "dead_letter",
+            TaskState::NonDeterministic => "non_deterministic",
+        }
+    }
+
+    /// Whether the task needs no further work
+    pub fn is_final(&self) -> bool {
+        matches!(
+            self,
+            TaskState::Confirmed
+                | TaskState::SentToAggregator
+                | TaskState::Skipped
+                | TaskState::DeadLetter
+                | TaskState::NonDeterministic
+        )
+    }
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TaskState {
+    type Err = TaskStoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TaskState::ALL
+            .into_iter()
+            .find(|state| state.as_str() == s)
+            .ok_or_else(|| TaskStoreError::UnknownState(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskRecord {
+    pub task_index: u32,
+    pub code_arweave_uri: String,
+    pub language: String,
+    pub response_count: String,
+    pub task_created_block: u32,
+    pub state: TaskState,
+    pub result: Option<String>,
+    pub tx_hash: Option<String>,
+    pub error: Option<String>,
+    /// Failed attempts so far
+    pub attempts: u32,
+    pub failure_class: Option<String>,
+    /// Unix time of the next scheduled retry
+    pub next_retry_at: Option<i64>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+impl TaskRecord {
+    pub fn task(&self) -> Task {
+        Task {
+            code_arweave_uri: self.code_arweave_uri.clone(),
+            language: self.language.clone(),
+            response_count: ethers::types::U256::from_dec_str(&self.response_count).unwrap_or_default(),
+            task_created_block: self.task_created_block,
+            request_id: self.task_index,
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let state: String = row.get("state")?;
+        Ok(Self {
+            task_index: row.get("task_index")?,
+            code_arweave_uri: row.get("code_arweave_uri")?,
+            language: row.get("language")?,
+            response_count: row.get("response_count")?,
+            task_created_block: row.get("task_created_block")?,
+            state: state.parse().map_err(|e: TaskStoreError| {
+                rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e))
+            })?,
+            result: row.get("result")?,
+            tx_hash: row.get("tx_hash")?,
+            error: row.get("error")?,
+            attempts: row.get("attempts")?,
+            failure_class: row.get("failure_class")?,
+            next_retry_at: row.get("next_retry_at")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
+
+/// One state change, for the audit trail of a task
+#[derive(Debug, Clone, Serialize)]
+pub struct StateTransition {
+    pub state: TaskState,
+    pub detail: Option<String>,
+    pub at: String,
+}
+
+const NOW: &str = "strftime('%Y-%m-%dT%H:%M:%SZ', 'now')";
+
+pub struct TaskStore {
+    conn: Mutex<Connection>,
+}
+
+impl TaskStore {
+    /// Opens (and if needed creates) the database at `path`
+    pub fn open(path: &Path) -> Result<Self, TaskStoreError> {
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, TaskStoreError> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, TaskStoreError> {
+        conn.execute_batch(&format!(
+            "PRAGMA journal_mode = WAL;
+            CREATE TABLE IF NOT EXISTS tasks (
+                task_index INTEGER PRIMARY KEY,
+                code_arweave_uri TEXT NOT NULL,
+                language TEXT NOT NULL,
+                response_count TEXT NOT NULL,
+                task_created_block INTEGER NOT NULL,
+                state TEXT NOT NULL,
+                result TEXT,
+                tx_hash TEXT,
+                error TEXT,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                failure_class TEXT,
+                next_retry_at INTEGER,
+                created_at TEXT NOT NULL DEFAULT ({NOW}),
+                updated_at TEXT NOT NULL DEFAULT ({NOW})
+            );
+            CREATE TABLE IF NOT EXISTS task_transitions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                task_index INTEGER NOT NULL REFERENCES tasks(task_index),
+                state TEXT NOT NULL,
+                detail TEXT,
+                at TEXT NOT NULL DEFAULT ({NOW})
+            );
+            CREATE TABLE IF NOT EXISTS task_logs (
+                task_index INTEGER PRIMARY KEY REFERENCES tasks(task_index),
+                stdout TEXT NOT NULL,
+                stderr TEXT NOT NULL,
+                truncated INTEGER NOT NULL,
+                at TEXT NOT NULL DEFAULT ({NOW})
+            );
+            CREATE TABLE IF NOT EXISTS receipts (
+                task_index INTEGER PRIMARY KEY REFERENCES tasks(task_index),
+                receipt_hash TEXT NOT NULL,
+                receipt TEXT NOT NULL,
+                arweave_id TEXT,
+                at TEXT NOT NULL DEFAULT ({NOW})
+            );
+            CREATE INDEX IF NOT EXISTS task_transitions_task ON task_transitions(task_index);
+            CREATE INDEX IF NOT EXISTS tasks_state ON tasks(state);"
+        ))?;
+
+        // Databases created before retries were tracked
+        for (column, definition) in [
+            ("attempts", "INTEGER NOT NULL DEFAULT 0"),
+            ("failure_class", "TEXT"),
+            ("next_retry_at", "INTEGER"),
+        ] {
+            let exists = conn
+                .prepare("SELECT 1 FROM pragma_table_info('tasks') WHERE name = ?1")?
+                .exists([column])?;
+            if !exists {
+                conn.execute_batch(&format!("ALTER TABLE tasks ADD COLUMN {} {}", column, definition))?;
+            }
+        }
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+
+    /// Records a task seen on-chain. A task seen again, e.g. on replay or retry,
+    /// starts over but keeps its attempt count, result and transaction hash. One
+    /// in a final state is left as it is and false is returned.
+    pub fn record_discovered(&self, task: &Task) -> Result<bool, TaskStoreError> {
+        let final_states = TaskState::ALL
+            .iter()
+            .filter(|state| state.is_final())
+            .map(|state| format!("'{}'", state.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let conn = self.conn.lock().unwrap();
+        let changed = conn.execute(
+            &format!(
+                "INSERT INTO tasks (task_index, code_arweave_uri, language, response_count, task_created_block, state)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ON CONFLICT(task_index) DO UPDATE SET
+                    state = ?6, error = NULL, next_retry_at = NULL, updated_at = {NOW}
+                WHERE tasks.state NOT IN ({final_states})"
+            ),
+            params![
+                task.request_id,
+                task.code_arweave_uri,
+                task.language,
+                task.response_count.to_string(),
+                task.task_created_block,
+                TaskState::Discovered.as_str(),
+            ],
+        )?;
+        if changed == 0 {
+            return Ok(false);
+        }
+        insert_transition(&conn, task.request_id, TaskState::Discovered, None)?;
+        Ok(true)
+    }
+
+    pub fn set_state(&self, task_index: u32, state: TaskState, detail: Option<&str>) -> Result<(), TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!("UPDATE tasks SET state = ?2, updated_at = {NOW} WHERE task_index = ?1"),
+            params![task_index, state.as_str()],
+        )?;
+        insert_transition(&conn, task_index, state, detail)
+    }
+
+    /// Moves the task to `Executed` with the response string it produced
+    pub fn set_result(&self, task_index: u32, result: &str) -> Result<(), TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!("UPDATE tasks SET state = ?2, result = ?3, updated_at = {NOW} WHERE task_index = ?1"),
+            params![task_index, TaskState::Executed.as_str(), result],
+        )?;
+        insert_transition(&conn, task_index, TaskState::Executed, None)
+    }
+
+    pub fn set_tx_hash(&self, task_index: u32, tx_hash: &str) -> Result<(), TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!("UPDATE tasks SET tx_hash = ?2, updated_at = {NOW} WHERE task_index = ?1"),
+            params![task_index, tx_hash],
+        )?;
+        Ok(())
+    }
+
+    /// Keeps the console output of the latest run of the task, replacing that of earlier attempts
+    pub fn set_logs(&self, task_index: u32, logs: &ExecutionLogs) -> Result<(), TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!(
+                "INSERT INTO task_logs (task_index, stdout, stderr, truncated) VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(task_index) DO UPDATE SET stdout = ?2, stderr = ?3, truncated = ?4, at = {NOW}"
+            ),
+            params![task_index, logs.stdout, logs.stderr, logs.truncated],
+        )?;
+        Ok(())
+    }
+
+    pub fn logs(&self, task_index: u32) -> Result<Option<ExecutionLogs>, TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row(
+                "SELECT stdout, stderr, truncated FROM task_logs WHERE task_index = ?1",
+                [task_index],
+                |row| Ok(ExecutionLogs { stdout: row.get(0)?, stderr: row.get(1)?, truncated: row.get(2)? }),
+            )
+            .optional()?)
+    }
+
+    /// Keeps the receipt of the latest run, replacing any earlier one
+    pub fn set_receipt(&self, task_index: u32, receipt: &ExecutionReceipt) -> Result<(), TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!(
+                "INSERT INTO receipts (task_index, receipt_hash, receipt) VALUES (?1, ?2, ?3)
+                ON CONFLICT(task_index) DO UPDATE SET receipt_hash = ?2, receipt = ?3, arweave_id = NULL, at = {NOW}"
+            ),
+            params![task_index, format!("{:?}", receipt.hash()), serde_json::to_string(receipt)?],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_receipt_upload(&self, task_index: u32, arweave_id: &str) -> Result<(), TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("UPDATE receipts SET arweave_id = ?2 WHERE task_index = ?1", params![task_index, arweave_id])?;
+        Ok(())
+    }
+
+    pub fn receipt(&self, task_index: u32) -> Result<Option<StoredReceipt>, TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        let row = conn
+            .query_row(
+                "SELECT receipt, arweave_id FROM receipts WHERE task_index = ?1",
+                [task_index],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+            )
+            .optional()?;
+        match row {
+            Some((receipt, arweave_id)) => {
+                let receipt: ExecutionReceipt = serde_json::from_str(&receipt)?;
+                Ok(Some(StoredReceipt { receipt_hash: receipt.hash(), receipt, arweave_id }))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Records a failed attempt. With `next_retry_at` the task is `Failed` and
+    /// retried at that unix time, without it the task moves to `DeadLetter`.
+    pub fn set_failed(
+        &self,
+        task_index: u32,
+        error: &str,
+        failure_class: &str,
+        attempts: u32,
+        next_retry_at: Option<i64>,
+    ) -> Result<(), TaskStoreError> {
+        let state = match next_retry_at {
+            Some(_) => TaskState::Failed,
+            None => TaskState::DeadLetter,
+        };
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!(
+                "UPDATE tasks SET state = ?2, error = ?3, failure_class = ?4, attempts = ?5, next_retry_at = ?6,
+                updated_at = {NOW} WHERE task_index = ?1"
+            ),
+            params![task_index, state.as_str(), error, failure_class, attempts, next_retry_at],
+        )?;
+        insert_transition(&conn, task_index, state, Some(error))
+    }
+
+    /// Failed tasks whose retry is due at unix time `now`
+    pub fn due_retries(&self, now: i64) -> Result<Vec<TaskRecord>, TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT * FROM tasks WHERE next_retry_at IS NOT NULL AND next_retry_at <= ?1 ORDER BY next_retry_at",
+        )?;
+        let records = statement
+            .query_map([now], TaskRecord::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(records)
+    }
+
+    /// Puts a failed or dead-lettered task back in the queue to run right away,
+    /// with a fresh set of attempts. Returns false when it is in neither state.
+    pub fn requeue(&self, task_index: u32, now: i64) -> Result<bool, TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        let updated = conn.execute(
+            &format!(
+                "UPDATE tasks SET state = ?2, attempts = 0, next_retry_at = ?3, updated_at = {NOW}
+                WHERE task_index = ?1 AND state IN (?4, ?5)"
+            ),
+            params![
+                task_index,
+                TaskState::Discovered.as_str(),
+                now,
+                TaskState::Failed.as_str(),
+                TaskState::DeadLetter.as_str()
+            ],
+        )?;
+        if updated == 0 {
+            return Ok(false);
+        }
+        insert_transition(&conn, task_index, TaskState::Discovered, Some("requeued"))?;
+        Ok(true)
+    }
+
+    /// Queues a task that was not run, or was cut short, to run again at `at`
+    /// without counting it as a failed attempt
+    pub fn defer(&self, task_index: u32, at: i64, reason: &str) -> Result<(), TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!("UPDATE tasks SET state = ?2, next_retry_at = ?3, updated_at = {NOW} WHERE task_index = ?1"),
+            params![task_index, TaskState::Discovered.as_str(), at],
+        )?;
+        insert_transition(&conn, task_index, TaskState::Discovered, Some(reason))
+    }
+
+    /// Folds the write-ahead log back into the database file
+    pub fn checkpoint(&self) -> Result<(), TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+        Ok(())
+    }
+
+    pub fn get(&self, task_index: u32) -> Result<Option<TaskRecord>, TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn
+            .query_row("SELECT * FROM tasks WHERE task_index = ?1", [task_index], TaskRecord::from_row)
+            .optional()?)
+    }
+
+    /// Most recently updated tasks first, optionally only those in `state`
+    pub fn list(&self, state: Option<TaskState>, limit: usize) -> Result<Vec<TaskRecord>, TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT * FROM tasks WHERE ?1 IS NULL OR state = ?1
+            ORDER BY updated_at DESC, task_index DESC LIMIT ?2",
+        )?;
+        let records = statement
+            .query_map(params![state.map(|state| state.as_str()), limit as i64], TaskRecord::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(records)
+    }
+
+    pub fn history(&self, task_index: u32) -> Result<Vec<StateTransition>, TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT state, detail, at FROM task_transitions WHERE task_index = ?1 ORDER BY id",
+        )?;
+        let rows = statement
+            .query_map([task_index], |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, Option<String>, String)>>>()?;
+        rows.into_iter()
+            .map(|(state, detail, at)| Ok(StateTransition { state: state.parse()?, detail, at }))
+            .collect()
+    }
+
+    /// Number of tasks in each state, for reporting
+    pub fn counts(&self) -> Result<Vec<(TaskState, u64)>, TaskStoreError> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT state, COUNT(*) FROM tasks GROUP BY state ORDER BY state")?;
+        let rows = statement
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(state, count)| Ok((state.parse()?, count as u64)))
+            .collect()
+    }
+}
+
+fn insert_transition(
+    conn: &Connection,
+    task_index: u32,
+    state: TaskState,
+    detail: Option<&str>,
+) -> Result<(), TaskStoreError> {
+    conn.execute(
+        "INSERT INTO task_transitions (task_index, state, detail) VALUES (?1, ?2, ?3)",
+        params![task_index, state.as_str(), detail],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::U256;
+
+    fn task(request_id: u32) -> Task {
+        Task {
+            code_arweave_uri: "code".to_string(),
+            language: "js".to_string(),
+            response_count: U256::one(),
+            task_created_block: 1,
+            request_id,
+        }
+    }
+
+    #[test]
+    fn rediscovery_keeps_settled_tasks() {
+        let store = TaskStore::open_in_memory().unwrap();
+        assert!(store.record_discovered(&task(1)).unwrap());
+        store.set_result(1, "42").unwrap();
+        store.set_tx_hash(1, "0xabc").unwrap();
+        store.set_state(1, TaskState::Confirmed, None).unwrap();
+
+        assert!(!store.record_discovered(&task(1)).unwrap());
+        let record = store.get(1).unwrap().unwrap();
+        assert_eq!(record.state, TaskState::Confirmed);
+        assert_eq!(record.result.as_deref(), Some("42"));
+        assert_eq!(record.tx_hash.as_deref(), Some("0xabc"));
+        assert_eq!(store.history(1).unwrap().last().unwrap().state, TaskState::Confirmed);
+
+        // A response handed to the aggregator is just as settled
+        store.record_discovered(&task(2)).unwrap();
+        store.set_state(2, TaskState::SentToAggregator, None).unwrap();
+        assert!(!store.record_discovered(&task(2)).unwrap());
+        assert_eq!(store.get(2).unwrap().unwrap().state, TaskState::SentToAggregator);
+        assert_eq!("sent_to_aggregator".parse::<TaskState>().unwrap(), TaskState::SentToAggregator);
+    }
+
+    #[test]
+    fn rediscovery_restarts_failed_tasks() {
+        let store = TaskStore::open_in_memory().unwrap();
+        store.record_discovered(&task(1)).unwrap();
+        store.set_failed(1, "gateway down", "gateway", 1, Some(0)).unwrap();
+
+        assert!(store.record_discovered(&task(1)).unwrap());
+        let record = store.get(1).unwrap().unwrap();
+        assert_eq!(record.state, TaskState::Discovered);
+        assert_eq!(record.attempts, 1);
+        assert_eq!(record.error, None);
+        assert_eq!(record.next_retry_at, None);
+    }
+}
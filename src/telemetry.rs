@@ -0,0 +1,100 @@
+//! Log output through `tracing`, as colored lines or JSON, optionally exporting
+//! spans to an OpenTelemetry collector (`otlp` feature).
+//!
+//! `RUST_LOG` filters as before, defaulting to `info`. Records from crates that
+//! still use `log` are forwarded into the same output.
+
+use crate::config::{LogFormat, LoggingConfig};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Flushes exported spans when dropped; keep it alive until the process exits
+#[derive(Default)]
+pub struct TelemetryGuard {
+    #[cfg(feature = "otlp")]
+    provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otlp")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Failed to flush traces: {}", e);
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber. `service_name` identifies the binary in
+/// exported traces.
+pub fn init(config: &LoggingConfig, service_name: &'static str) -> eyre::Result<TelemetryGuard> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let output = match config.format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().with_target(false).boxed(),
+        LogFormat::Json => {
+            // Messages are built with `colored`; keep escape codes out of JSON
+            colored::control::set_override(false);
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .boxed()
+        }
+    };
+    let subscriber = tracing_subscriber::registry().with(filter).with(output);
+
+    #[cfg(feature = "otlp")]
+    {
+        let mut guard = TelemetryGuard::default();
+        let layer = match &config.otlp_endpoint {
+            Some(endpoint) => {
+                let (layer, provider) = otlp::layer(endpoint, service_name)?;
+                guard.provider = Some(provider);
+                Some(layer)
+            }
+            None => None,
+        };
+        subscriber.with(layer).try_init()?;
+        Ok(guard)
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        subscriber.try_init()?;
+        if config.otlp_endpoint.is_some() {
+            tracing::warn!("logging.otlp_endpoint is set but {} was built without the otlp feature", service_name);
+        }
+        Ok(TelemetryGuard::default())
+    }
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+    use opentelemetry_sdk::{runtime, Resource};
+    use tracing::Subscriber;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// Batches spans to `{endpoint}/v1/traces` over OTLP/HTTP
+    pub fn layer<S>(endpoint: &str, service_name: &'static str) -> eyre::Result<(OpenTelemetryLayer<S, Tracer>, TracerProvider)>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new(vec![KeyValue::new("service.name", service_name)]))
+            .build();
+        let tracer = provider.tracer(service_name);
+        Ok((tracing_opentelemetry::layer().with_tracer(tracer), provider))
+    }
+}